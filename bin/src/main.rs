@@ -35,7 +35,7 @@ pub fn main() {
 			Ok(source) => match ballscript::parse(&source, &mut string_map, tracer) {
 				Ok(script) => {
 					if dump_bytecode {
-						println!("{:#?}", script);
+						print!("{}", script.disassemble());
 						0
 					} else {
 						let script = script.instance();
@@ -70,7 +70,7 @@ pub fn main() {
 
 macro_rules! add_env_fn {
 	($env:ident.$name:ident($args:ident) $code:block) => {
-		$env.add_function(stringify!($name).into(), Box::new(|$args: &[_]| $code))
+		$env.add_function(stringify!($name).into(), |$args: &[_]| $code)
 			.unwrap();
 	};
 }
@@ -155,8 +155,8 @@ fn print_parse_error(error: ParseError) -> io::Result<()> {
 }
 
 #[cold]
-fn print_call_error(error: &dyn std::error::Error) {
-	eprintln!("An error was thrown: {}", error);
+fn print_call_error(error: &(dyn std::error::Error + 'static)) {
+	eprintln!("An error was thrown: {:#}", error);
 }
 
 #[cfg(feature = "print-instructions")]
@@ -167,7 +167,7 @@ mod tracer {
 	use std::rc::Rc;
 
 	struct TracerData {
-		show_vars: [u8; 3],
+		show_vars: [u16; 3],
 		show_vars_count: u8,
 	}
 
@@ -6,12 +6,11 @@
 use crate::std_types::*;
 use crate::tokenizer::*;
 use crate::util;
+use crate::Arity;
+use crate::{Integer, Real};
 use core::convert::TryInto;
 use core::fmt;
 
-type Integer = isize;
-type Real = f64;
-
 #[derive(Debug)]
 pub(crate) struct Script<'src> {
 	pub functions: Vec<Function<'src>>,
@@ -22,15 +21,25 @@ pub(crate) struct Script<'src> {
 pub(crate) struct Function<'src> {
 	pub name: &'src str,
 	pub parameters: Vec<&'src str>,
+	/// The default values of the last parameters
+	pub defaults: Vec<Expression<'src>>,
+	/// Whether the last parameter collects any remaining arguments into an array
+	pub variadic: bool,
 	pub lines: Lines<'src>,
+	pub line: u32,
+	pub column: u32,
 }
 
 pub(crate) type Lines<'src> = Vec<Statement<'src>>;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) enum UnaryOp {
 	Neg,
 	Not,
+	/// Converts a value to a string. Used for expressions in strings.
+	Str,
+	/// Evaluates to `none` if the expression returns an error.
+	Try,
 }
 
 #[derive(Debug)]
@@ -65,12 +74,20 @@ pub(crate) enum Statement<'src> {
 		to: Expression<'src>,
 		step: Option<Expression<'src>>,
 		lines: Lines<'src>,
+		else_lines: Option<Lines<'src>>,
 	},
 	While {
 		line: u32,
 		column: u32,
 		expr: Expression<'src>,
 		lines: Lines<'src>,
+		else_lines: Option<Lines<'src>>,
+	},
+	/// Repeats the lines until a `break`.
+	Loop {
+		line: u32,
+		column: u32,
+		lines: Lines<'src>,
 	},
 	If {
 		line: u32,
@@ -103,6 +120,7 @@ pub(crate) enum Atom<'src> {
 	Integer(Integer),
 	String(util::Str<'src>),
 	Bool(bool),
+	None,
 	_Self,
 	Env,
 }
@@ -158,6 +176,9 @@ pub enum ErrorType {
 	ExpectedToken(String),
 	UnexpectedEOF,
 	NotANumber,
+	InvalidFormatExpression,
+	MissingDefault,
+	NestingTooDeep,
 	InternalError(u32),
 }
 
@@ -210,7 +231,14 @@ impl<'src> Script<'src> {
 }
 
 impl<'src> Function<'src> {
+	/// The amount of arguments this function can be called with.
+	pub fn arity(&self) -> Arity {
+		let fixed = self.parameters.len() - usize::from(self.variadic);
+		Arity::of_script_function(fixed, self.defaults.len(), self.variadic)
+	}
+
 	fn parse(tokens: &mut TokenStream<'src>) -> Result<Self, Error> {
+		let (line, column) = tokens.position();
 		let name = match tokens.next() {
 			Some(Token::Name(name)) => name,
 			Some(tk) => err!(UnexpectedToken, tk, tokens),
@@ -223,12 +251,35 @@ impl<'src> Function<'src> {
 		}
 
 		let mut parameters = Vec::new();
+		let mut defaults = Vec::new();
+		let mut variadic = false;
 		loop {
 			match tokens.next() {
 				Some(Token::BracketRoundClose) => break,
+				Some(Token::Op(Op::Mul)) => {
+					match tokens.next() {
+						Some(Token::Name(a)) => parameters.push(a),
+						Some(tk) => err!(UnexpectedToken, tk, tokens),
+						None => err!(UnexpectedEOF, tokens),
+					}
+					variadic = true;
+					// The variadic parameter must be the last one
+					match tokens.next() {
+						Some(Token::BracketRoundClose) => break,
+						Some(tk) => err!(UnexpectedToken, tk, tokens),
+						None => err!(UnexpectedEOF, tokens),
+					}
+				}
 				Some(Token::Name(a)) => {
 					parameters.push(a);
-					match tokens.next() {
+					let mut next = tokens.next();
+					if next == Some(Token::Assign(AssignOp::None)) {
+						defaults.push(Expression::parse(tokens)?);
+						next = tokens.next();
+					} else if !defaults.is_empty() {
+						err!(MissingDefault, tokens);
+					}
+					match next {
 						Some(Token::BracketRoundClose) => break,
 						Some(Token::Comma) => (),
 						_ => todo(tokens, line!())?,
@@ -249,7 +300,11 @@ impl<'src> Function<'src> {
 		Ok(Self {
 			name,
 			parameters,
+			defaults,
+			variadic,
 			lines: Self::parse_block(tokens, 1)?.0,
+			line,
+			column,
 		})
 	}
 
@@ -315,13 +370,35 @@ impl<'src> Function<'src> {
 						}
 						None => (None, expr, None),
 					};
+					let guard = match tokens.next() {
+						Some(Token::If) => Some((tokens.position(), Expression::parse(tokens)?)),
+						Some(_) => {
+							tokens.prev();
+							None
+						}
+						None => None,
+					};
 					let (blk, indent) = Self::parse_block(tokens, expected_indent + 1)?;
+					// A guard wraps the body in an `if` so iterations not matching it are skipped.
+					let blk = match guard {
+						Some(((line, column), expr)) => Vec::from([Statement::If {
+							expr,
+							lines: blk,
+							else_lines: None,
+							line,
+							column,
+						}]),
+						None => blk,
+					};
+					let (else_lines, indent) =
+						Self::parse_loop_else(tokens, expected_indent, indent)?;
 					lines.push(Statement::For {
 						var,
 						from,
 						to,
 						step,
 						lines: blk,
+						else_lines,
 						line,
 						column,
 					});
@@ -333,9 +410,12 @@ impl<'src> Function<'src> {
 					let (line, column) = tokens.position();
 					let expr = Expression::parse(tokens)?;
 					let (blk, indent) = Self::parse_block(tokens, expected_indent + 1)?;
+					let (else_lines, indent) =
+						Self::parse_loop_else(tokens, expected_indent, indent)?;
 					lines.push(Statement::While {
 						expr,
 						lines: blk,
+						else_lines,
 						line,
 						column,
 					});
@@ -343,57 +423,68 @@ impl<'src> Function<'src> {
 						return Ok((lines, indent));
 					}
 				}
-				Some(Token::If) => {
+				Some(Token::Loop) => {
 					let (line, column) = tokens.position();
-					let expr = Expression::parse(tokens)?;
 					let (blk, indent) = Self::parse_block(tokens, expected_indent + 1)?;
-					lines.push(Statement::If {
-						expr,
+					lines.push(Statement::Loop {
 						lines: blk,
-						else_lines: None,
 						line,
 						column,
 					});
 					if indent < expected_indent {
 						return Ok((lines, indent));
 					}
-					let mut prev_blk = &mut lines;
-					while let Some(tk) = tokens.next() {
-						let (line, column) = tokens.position();
-						if tk == Token::Elif {
-							let expr = Expression::parse(tokens)?;
-							let (blk, indent) = Self::parse_block(tokens, expected_indent + 1)?;
-							let if_blk = Vec::from([Statement::If {
-								expr,
-								lines: blk,
-								else_lines: None,
-								line,
-								column,
-							}]);
-							prev_blk = match prev_blk.last_mut().unwrap() {
-								Statement::If { else_lines, .. } => {
-									*else_lines = Some(if_blk);
-									else_lines.as_mut().unwrap()
-								}
-								_ => unreachable!(),
-							};
-							if indent < expected_indent {
-								return Ok((lines, indent));
+				}
+				Some(Token::If) => {
+					let (line, column) = tokens.position();
+					let expr = Expression::parse(tokens)?;
+					let (blk, mut indent) = Self::parse_block(tokens, expected_indent + 1)?;
+					// `elif`s and an `else` may only follow if the block didn't end at a lower
+					// indentation level. An `else` ends the chain.
+					let mut elifs = Vec::new();
+					let mut else_lines = None;
+					while indent == expected_indent {
+						match tokens.next() {
+							Some(Token::Elif) => {
+								let (line, column) = tokens.position();
+								let expr = Expression::parse(tokens)?;
+								let (blk, i) = Self::parse_block(tokens, expected_indent + 1)?;
+								elifs.push((expr, blk, line, column));
+								indent = i;
 							}
-						} else if tk == Token::Else {
-							let (blk, indent) = Self::parse_block(tokens, expected_indent + 1)?;
-							match prev_blk.last_mut().unwrap() {
-								Statement::If { else_lines, .. } => *else_lines = Some(blk),
-								_ => unreachable!(),
-							};
-							if indent < expected_indent {
-								return Ok((lines, indent));
+							Some(Token::Else) => {
+								let (blk, i) = Self::parse_block(tokens, expected_indent + 1)?;
+								else_lines = Some(blk);
+								indent = i;
+								break;
 							}
-						} else {
-							tokens.prev();
-							break;
+							Some(_) => {
+								tokens.prev();
+								break;
+							}
+							None => break,
 						}
 					}
+					// Each `elif` is an `if` in the `else` block of the previous one.
+					for (expr, blk, line, column) in elifs.into_iter().rev() {
+						else_lines = Some(Vec::from([Statement::If {
+							expr,
+							lines: blk,
+							else_lines,
+							line,
+							column,
+						}]));
+					}
+					lines.push(Statement::If {
+						expr,
+						lines: blk,
+						else_lines,
+						line,
+						column,
+					});
+					if indent < expected_indent {
+						return Ok((lines, indent));
+					}
 				}
 				Some(Token::Pass) => (),
 				Some(Token::Return) => {
@@ -469,7 +560,7 @@ impl<'src> Function<'src> {
 						_ => unreachable!(),
 					}
 				}
-				Some(Token::BracketRoundOpen) => {
+				Some(Token::BracketRoundOpen | Token::Try) => {
 					let (line, column) = tokens.position();
 					tokens.prev();
 					let expr = Expression::parse(tokens)?;
@@ -479,15 +570,228 @@ impl<'src> Function<'src> {
 				Some(Token::Indent(i)) if i < expected_indent => return Ok((lines, i)),
 				Some(Token::Indent(i)) if i == expected_indent => (),
 				Some(Token::Indent(i)) => err!(UnexpectedIndent, i, tokens),
+				// Not preceded by an `if` or loop, or following an `else`
+				Some(tk @ (Token::Elif | Token::Else)) => err!(UnexpectedToken, tk, tokens),
 				_ => todo(tokens, line!())?,
 			};
 		}
 	}
+
+	/// Parses the `else` block following a loop, if any. `indent` is the indentation
+	/// returned when parsing the loop block.
+	fn parse_loop_else(
+		tokens: &mut TokenStream<'src>,
+		expected_indent: u8,
+		indent: u8,
+	) -> Result<(Option<Lines<'src>>, u8), Error> {
+		if indent < expected_indent {
+			return Ok((None, indent));
+		}
+		match tokens.next() {
+			Some(Token::Else) => {
+				let (blk, indent) = Self::parse_block(tokens, expected_indent + 1)?;
+				Ok((Some(blk), indent))
+			}
+			Some(_) => {
+				tokens.prev();
+				Ok((None, indent))
+			}
+			None => Ok((None, indent)),
+		}
+	}
+}
+
+impl Statement<'_> {
+	/// Returns the line and column of the statement.
+	pub fn position(&self) -> (u32, u32) {
+		match self {
+			Statement::Declare { line, column, .. }
+			| Statement::LooseExpression { line, column, .. }
+			| Statement::Assign { line, column, .. }
+			| Statement::Expression { line, column, .. }
+			| Statement::For { line, column, .. }
+			| Statement::While { line, column, .. }
+			| Statement::Loop { line, column, .. }
+			| Statement::If { line, column, .. }
+			| Statement::Return { line, column, .. }
+			| Statement::Continue { line, column, .. }
+			| Statement::Break { line, column, .. } => (*line, *column),
+		}
+	}
+}
+
+impl Expression<'_> {
+	/// Returns the line and column of the expression.
+	pub fn position(&self) -> (u32, u32) {
+		match self {
+			Expression::Atom { line, column, .. }
+			| Expression::Operation { line, column, .. }
+			| Expression::UnaryOperation { line, column, .. }
+			| Expression::Function { line, column, .. }
+			| Expression::Array { line, column, .. }
+			| Expression::Dictionary { line, column, .. } => (*line, *column),
+		}
+	}
 }
 
 impl<'src> Expression<'src> {
 	fn parse(tokens: &mut TokenStream<'src>) -> Result<Self, Error> {
-		let lhs = match tokens.next() {
+		Self::nested(tokens, |tokens| Self::parse_binary(tokens, 0))
+	}
+
+	/// Calls `f` one nesting level deeper. Returns an error if more levels are entered than
+	/// the stream allows, which prevents stack overflows on pathological input.
+	fn nested<F>(tokens: &mut TokenStream<'src>, f: F) -> Result<Self, Error>
+	where
+		F: FnOnce(&mut TokenStream<'src>) -> Result<Self, Error>,
+	{
+		if !tokens.enter() {
+			err!(NestingTooDeep, tokens);
+		}
+		let e = f(tokens);
+		tokens.leave();
+		e
+	}
+
+	/// Parses a chain of binary operations using precedence climbing. Only operators with a
+	/// precedence of at least `min_precedence` are consumed.
+	fn parse_binary(tokens: &mut TokenStream<'src>, min_precedence: i8) -> Result<Self, Error> {
+		let depth = tokens.depth();
+		let mut lhs = Self::parse_unary(tokens)?;
+		loop {
+			let op = match tokens.next() {
+				Some(Token::Op(op)) if op.is_binary() && op.precedence() >= min_precedence => op,
+				Some(_) => {
+					tokens.prev();
+					break;
+				}
+				None => break,
+			};
+			// Each operation nests the previous ones one level deeper
+			if !tokens.enter() {
+				err!(NestingTooDeep, tokens);
+			}
+			let (line, column) = tokens.position();
+			// All operators are left-associative
+			let rhs = Self::parse_binary(tokens, op.precedence() + 1)?;
+			lhs = Self::Operation {
+				left: Box::new(lhs),
+				op,
+				right: Box::new(rhs),
+				line,
+				column,
+			};
+		}
+		tokens.leave_to(depth);
+		Ok(lhs)
+	}
+
+	/// Parses an expression with optional unary operators. Unary operators have a higher
+	/// precedence than binary operators but a lower precedence than calls and indices, i.e.
+	/// `-a.b()` is equivalent to `-(a.b())`.
+	fn parse_unary(tokens: &mut TokenStream<'src>) -> Result<Self, Error> {
+		let op = match tokens.next() {
+			Some(Token::Op(Op::Sub)) => UnaryOp::Neg,
+			Some(Token::Op(Op::Not)) => UnaryOp::Not,
+			Some(Token::Try) => UnaryOp::Try,
+			Some(_) => {
+				tokens.prev();
+				return Self::parse_postfix(tokens);
+			}
+			None => err!(UnexpectedEOF, tokens),
+		};
+		let (line, column) = tokens.position();
+		// Parse negative number literals directly so that e.g. the minimum integer can be used.
+		// `next` doesn't advance at the end of the stream, so only rewind if it returned a token.
+		if let UnaryOp::Neg = op {
+			match tokens.next() {
+				Some(Token::Number(n)) => {
+					let is_postfix = match tokens.next() {
+						Some(tk) => {
+							tokens.prev();
+							matches!(tk, Token::Op(Op::Access) | Token::BracketSquareOpen)
+						}
+						None => false,
+					};
+					if !is_postfix {
+						let atom = match parse_number(&format!("-{}", n)) {
+							Ok(Atom::Integer(i)) => Atom::Integer(i),
+							Ok(Atom::Real(r)) => Atom::Real(r),
+							_ => return Error::new(ErrorType::NotANumber, line, column),
+						};
+						return Ok(Self::Atom { atom, line, column });
+					}
+					tokens.prev();
+				}
+				Some(_) => tokens.prev(),
+				None => (),
+			}
+		}
+		let expr = Self::nested(tokens, Self::parse_unary)?;
+		Ok(Self::UnaryOperation {
+			op,
+			expr: Box::new(expr),
+			line,
+			column,
+		})
+	}
+
+	/// Parses an operand followed by any amount of method calls, property accesses and
+	/// indices.
+	fn parse_postfix(tokens: &mut TokenStream<'src>) -> Result<Self, Error> {
+		let depth = tokens.depth();
+		let mut expr = Self::parse_operand(tokens)?;
+		loop {
+			let tk = tokens.next();
+			// Each call, access or index nests the previous ones one level deeper
+			if matches!(tk, Some(Token::Op(Op::Access) | Token::BracketSquareOpen))
+				&& !tokens.enter()
+			{
+				err!(NestingTooDeep, tokens);
+			}
+			match tk {
+				Some(Token::Op(Op::Access)) => {
+					let (line, column) = tokens.position();
+					let name = match tokens.next() {
+						Some(Token::Name(name)) => name,
+						Some(tk) => err!(UnexpectedToken, tk, tokens),
+						None => err!(UnexpectedEOF, tokens),
+					};
+					expr = match tokens.next() {
+						Some(Token::BracketRoundOpen) => {
+							let args = Self::parse_expr_list(tokens, Token::BracketRoundClose)?;
+							Self::new_fn(Some(expr), name, args, tokens)
+						}
+						tk => {
+							if tk.is_some() {
+								tokens.prev();
+							}
+							let right = Self::new_name(name, tokens);
+							Self::Operation {
+								left: Box::new(expr),
+								op: Op::Access,
+								right: Box::new(right),
+								line,
+								column,
+							}
+						}
+					};
+				}
+				Some(Token::BracketSquareOpen) => expr = Self::parse_index_op(expr, tokens)?,
+				Some(_) => {
+					tokens.prev();
+					break;
+				}
+				None => break,
+			}
+		}
+		tokens.leave_to(depth);
+		Ok(expr)
+	}
+
+	/// Parses a single atom, literal, function call or parenthesized expression.
+	fn parse_operand(tokens: &mut TokenStream<'src>) -> Result<Self, Error> {
+		Ok(match tokens.next() {
 			Some(Token::BracketRoundOpen) => {
 				let e = Self::parse(tokens)?;
 				if tokens.next() != Some(Token::BracketRoundClose) {
@@ -512,8 +816,17 @@ impl<'src> Expression<'src> {
 				}
 			}
 			Some(Token::String(s)) => Self::new_str(s, tokens),
+			Some(Token::FormatString(s)) => Self::new_format(s, tokens)?,
 			Some(Token::Number(n)) => Self::new_num(n, tokens)?,
 			Some(tk) if tk == Token::True || tk == Token::False => Self::new_bool(tk, tokens),
+			Some(Token::None) => {
+				let (line, column) = tokens.position();
+				Self::Atom {
+					line,
+					column,
+					atom: Atom::None,
+				}
+			}
 			Some(Token::Name(name)) => match tokens.next() {
 				Some(Token::BracketRoundOpen) => Self::new_fn(
 					None,
@@ -521,14 +834,12 @@ impl<'src> Expression<'src> {
 					Self::parse_expr_list(tokens, Token::BracketRoundClose)?,
 					tokens,
 				),
-				Some(Token::BracketSquareOpen) => {
-					Self::parse_index_op(Self::new_name(name, tokens), tokens)?
-				}
-				Some(_) => {
-					tokens.prev();
+				tk => {
+					if tk.is_some() {
+						tokens.prev();
+					}
 					Self::new_name(name, tokens)
 				}
-				_ => todo(tokens, line!())?,
 			},
 			Some(Token::BracketSquareOpen) => {
 				let pos = tokens.position();
@@ -546,261 +857,9 @@ impl<'src> Expression<'src> {
 					column: pos.1,
 				}
 			}
-			Some(Token::Op(op)) => {
-				let (line, column) = tokens.position();
-				let op = match op {
-					Op::Sub => UnaryOp::Neg,
-					Op::Not => UnaryOp::Not,
-					_ => err!(UnexpectedToken, Token::Op(op), tokens),
-				};
-				let expr = match tokens.next() {
-					Some(Token::Name(name)) => Self::new_name(name, tokens),
-					Some(Token::Number(n)) => Self::new_num(n, tokens)?,
-					Some(Token::String(s)) => Self::new_str(s, tokens),
-					None => err!(UnexpectedEOF, tokens),
-					_ => todo(tokens, line!())?,
-				};
-				Self::UnaryOperation {
-					line,
-					column,
-					op,
-					expr: Box::new(expr),
-				}
-			}
-			_ => todo(tokens, line!())?,
-		};
-
-		Self::parse_with(lhs, tokens)
-	}
-
-	fn parse_with(lhs: Self, tokens: &mut TokenStream<'src>) -> Result<Self, Error> {
-		if let Some(tk) = tokens.next() {
-			match tk {
-				Token::Op(opl) => match tokens.next() {
-					Some(Token::Name(_)) | Some(Token::_Self) | Some(Token::Env) => {
-						tokens.prev();
-						let mid = Self::new_atom(tokens.next().unwrap(), tokens)?;
-						match tokens.next() {
-							Some(Token::Op(opr)) => {
-								Self::parse_tri_op_start(lhs, opl, mid, opr, tokens)
-							}
-							Some(Token::BracketRoundOpen) => {
-								let og_mid = if let Self::Atom {
-									atom: Atom::Name(n),
-									..
-								} = mid
-								{
-									n
-								} else {
-									return todo(tokens, line!());
-								};
-								let args = Self::parse_expr_list(tokens, Token::BracketRoundClose)?;
-								let tk = tokens.next();
-								match opl {
-									Op::Access => {
-										let f = Self::new_fn(Some(lhs), og_mid, args, tokens);
-										match tk {
-											Some(Token::Op(_)) => {
-												tokens.prev();
-												Self::parse_with(f, tokens)
-											}
-											Some(_) => {
-												tokens.prev();
-												Ok(f)
-											}
-											None => Ok(f),
-										}
-									}
-									op => {
-										if let Some(Token::Op(opr)) = tk {
-											Self::parse_tri_op_start(
-												lhs,
-												op,
-												Self::new_fn(None, og_mid, args, tokens),
-												opr,
-												tokens,
-											)
-										} else {
-											if tk.is_some() {
-												tokens.prev();
-											}
-											Ok(Self::new_op(
-												lhs,
-												op,
-												Self::new_fn(None, og_mid, args, tokens),
-												tokens,
-											))
-										}
-									}
-								}
-							}
-							Some(Token::BracketRoundClose)
-							| Some(Token::Indent(_))
-							| Some(Token::Assign(_)) => {
-								tokens.prev();
-								Ok(Self::new_op(lhs, opl, mid, tokens))
-							}
-							Some(Token::BracketSquareOpen) => {
-								let mid = Self::parse_index_op(mid, tokens)?;
-								match tokens.next() {
-									Some(Token::Op(opr)) => {
-										Self::parse_tri_op_start(lhs, opl, mid, opr, tokens)
-									}
-									Some(Token::BracketRoundClose) | Some(Token::Indent(_)) => {
-										tokens.prev();
-										Ok(Self::new_op(lhs, opl, mid, tokens))
-									}
-									None => Ok(Self::new_op(lhs, opl, mid, tokens)),
-									_ => todo(tokens, line!())?,
-								}
-							}
-							None => Ok(Self::new_op(lhs, opl, mid, tokens)),
-							_ => todo(tokens, line!())?,
-						}
-					}
-					Some(Token::Number(_)) | Some(Token::String(_)) => {
-						tokens.prev();
-						let mid = match tokens.next().unwrap() {
-							Token::Number(n) => Self::new_num(n, tokens)?,
-							Token::String(n) => Self::new_str(n, tokens),
-							_ => unreachable!(),
-						};
-						match tokens.next() {
-							Some(Token::Op(opr)) => {
-								Self::parse_tri_op_start(lhs, opl, mid, opr, tokens)
-							}
-							Some(Token::BracketRoundClose) | Some(Token::Indent(_)) => {
-								tokens.prev();
-								Ok(Self::new_op(lhs, opl, mid, tokens))
-							}
-							None => Ok(Self::new_op(lhs, opl, mid, tokens)),
-							_ => todo(tokens, line!())?,
-						}
-					}
-					Some(mid) if mid == Token::True || mid == Token::False => {
-						let mid = Self::new_bool(mid, tokens);
-						match tokens.next() {
-							Some(Token::BracketRoundClose) | Some(Token::Indent(_)) => {
-								tokens.prev();
-								Ok(Self::new_op(lhs, opl, mid, tokens))
-							}
-							None => Ok(Self::new_op(lhs, opl, mid, tokens)),
-							_ => todo(tokens, line!())?,
-						}
-					}
-					_ => todo(tokens, line!())?,
-				},
-				Token::BracketRoundClose
-				| Token::BracketSquareClose
-				| Token::BracketCurlyClose
-				| Token::Indent(_)
-				| Token::Comma
-				| Token::Colon
-				| Token::To
-				| Token::Step
-				| Token::Assign(_) => {
-					tokens.prev();
-					Ok(lhs)
-				}
-				_ => todo(tokens, line!())?,
-			}
-		} else {
-			Ok(lhs)
-		}
-	}
-
-	fn parse_tri_op_start(
-		lhs: Self,
-		opl: Op,
-		mid: Self,
-		opr: Op,
-		tokens: &mut TokenStream<'src>,
-	) -> Result<Self, Error> {
-		let rhs = match tokens.next() {
-			Some(Token::Name(rhs)) => {
-				let og_rhs = rhs;
-				let rhs = Self::new_name(rhs, tokens);
-				match tokens.next() {
-					Some(Token::BracketRoundOpen) => {
-						if opr == Op::Access {
-							let args = Self::parse_expr_list(tokens, Token::BracketRoundClose)?;
-							let f = Self::new_fn(Some(mid), og_rhs, args, tokens);
-							match tokens.next() {
-								Some(Token::Op(opr)) => {
-									return Self::parse_tri_op_start(lhs, opl, f, opr, tokens);
-								}
-								None => return Ok(Self::new_op(lhs, opl, f, tokens)),
-								_ => {
-									tokens.prev();
-									return Ok(Self::new_op(lhs, opl, f, tokens));
-								}
-							}
-						} else {
-							todo(tokens, line!())?
-						}
-					}
-					Some(Token::BracketSquareOpen) => Self::parse_index_op(rhs, tokens)?,
-					Some(_) => {
-						tokens.prev();
-						rhs
-					}
-					None => rhs,
-				}
-			}
-			Some(Token::Number(rhs)) => Self::new_num(rhs, tokens)?,
-			_ => todo(tokens, line!())?,
-		};
-		Self::parse_tri_op(lhs, opl, mid, opr, rhs, tokens)
-	}
-
-	fn parse_tri_op(
-		left: Self,
-		op_left: Op,
-		mid: Self,
-		op_right: Op,
-		right: Self,
-		tokens: &mut TokenStream<'src>,
-	) -> Result<Self, Error> {
-		let (left, op, right) = if op_left >= op_right {
-			tokens.prev();
-			let right = match tokens.next().unwrap() {
-				Token::BracketRoundClose | Token::BracketSquareClose | Token::BracketCurlyClose => {
-					right
-				}
-				_ => {
-					tokens.prev();
-					Self::parse(tokens)?
-				}
-			};
-			let left = Self::new_op(left, op_left, mid, tokens);
-			(left, op_right, right)
-		} else {
-			let right = Self::new_op(mid, op_right, right, tokens);
-			(left, op_left, right)
-		};
-		Ok(Self::new_op(left, op, right, tokens))
-	}
-
-	fn new_op(left: Self, op: Op, right: Self, tokens: &TokenStream<'src>) -> Self {
-		let pos = tokens.position();
-		Self::Operation {
-			left: Box::new(left),
-			op,
-			right: Box::new(right),
-			line: pos.0,
-			column: pos.1,
-		}
-	}
-
-	fn new_atom(tk: Token<'src>, tokens: &mut TokenStream<'src>) -> Result<Self, Error> {
-		let (line, column) = tokens.position();
-		let atom = match tk {
-			Token::_Self => Atom::_Self,
-			Token::Env => Atom::Env,
-			Token::Name(n) => Atom::Name(n),
-			_ => todo(tokens, line!())?,
-		};
-		Ok(Self::Atom { atom, line, column })
+			Some(tk) => err!(UnexpectedToken, tk, tokens),
+			None => err!(UnexpectedEOF, tokens),
+		})
 	}
 
 	fn new_name(n: &'src str, tokens: &TokenStream<'src>) -> Self {
@@ -845,6 +904,66 @@ impl<'src> Expression<'src> {
 		}
 	}
 
+	/// Turns a string with embedded expressions into a chain of concatenations.
+	fn new_format(
+		segments: Vec<FormatSegment<'src>>,
+		tokens: &TokenStream<'src>,
+	) -> Result<Self, Error> {
+		let (line, column) = tokens.position();
+		let new_str = |s| Self::Atom {
+			atom: Atom::String(s),
+			line,
+			column,
+		};
+		let mut expr = None;
+		for segment in segments {
+			let is_str = matches!(segment, FormatSegment::Str(_));
+			let right = match segment {
+				FormatSegment::Str(s) => new_str(s),
+				FormatSegment::Expr(src) => {
+					let mut sub = match TokenStream::parse(src.trim(), Indentation::Tabs) {
+						Ok(sub) => sub,
+						Err(_) => {
+							return Error::new(ErrorType::InvalidFormatExpression, line, column)
+						}
+					};
+					sub.set_max_depth(tokens.remaining_depth());
+					let e = Self::parse(&mut sub).map_err(|mut e| {
+						e.line = line;
+						e.column = column;
+						e
+					})?;
+					if let Some(tk) = sub.next() {
+						let tk = format!("{:?}", tk);
+						return Error::new(ErrorType::UnexpectedToken(tk), line, column);
+					}
+					Self::UnaryOperation {
+						op: UnaryOp::Str,
+						expr: Box::new(e),
+						line,
+						column,
+					}
+				}
+			};
+			let left = match expr.take() {
+				Some(left) => left,
+				None if is_str => {
+					expr = Some(right);
+					continue;
+				}
+				None => new_str(util::Str::Slice("")),
+			};
+			expr = Some(Self::Operation {
+				left: Box::new(left),
+				op: Op::Add,
+				right: Box::new(right),
+				line,
+				column,
+			});
+		}
+		Ok(expr.unwrap_or_else(|| new_str(util::Str::Slice(""))))
+	}
+
 	fn new_fn(
 		expr: Option<Self>,
 		name: &'src str,
@@ -861,28 +980,13 @@ impl<'src> Expression<'src> {
 		}
 	}
 
+	/// Parses all expressions separated by a `,` until `end_token` is encountered. The start
+	/// token must have been consumed already.
 	fn parse_expr_list(
 		tokens: &mut TokenStream<'src>,
 		end_token: Token,
 	) -> Result<Vec<Self>, Error> {
-		let mut args = Vec::new();
-		loop {
-			match tokens.next() {
-				Some(tk) if tk == end_token => break,
-				Some(_) => {
-					tokens.prev();
-					let expr = Expression::parse(tokens)?;
-					args.push(expr);
-				}
-				_ => todo(tokens, line!())?,
-			};
-			match tokens.next() {
-				Some(Token::Comma) => (),
-				Some(tk) if tk == end_token => break,
-				_ => todo(tokens, line!())?,
-			}
-		}
-		Ok(args)
+		Self::parse_list(tokens, end_token, Expression::parse)
 	}
 
 	/// Parses all items in the form of `key : value` separated by a `,` until `end_token`
@@ -891,42 +995,45 @@ impl<'src> Expression<'src> {
 		tokens: &mut TokenStream<'src>,
 		end_token: Token,
 	) -> Result<Vec<(Self, Self)>, Error> {
-		let mut args = Vec::new();
-		loop {
-			let key = match tokens.next() {
-				Some(tk) if tk == end_token => break,
-				Some(_) => {
-					tokens.prev();
-					let expr = Expression::parse(tokens)?;
-					expr
-				}
-				_ => todo(tokens, line!())?,
-			};
+		Self::parse_list(tokens, end_token, |tokens| {
+			let key = Expression::parse(tokens)?;
 			match tokens.next() {
 				Some(Token::Colon) => (),
 				Some(tk) => err!(UnexpectedToken, tk, tokens),
 				None => err!(UnexpectedEOF, tokens),
 			}
+			Ok((key, Expression::parse(tokens)?))
+		})
+	}
+
+	/// Parses all items separated by a `,` until `end_token` is encountered. The last item
+	/// may be followed by a `,` too.
+	fn parse_list<T>(
+		tokens: &mut TokenStream<'src>,
+		end_token: Token,
+		mut parse_item: impl FnMut(&mut TokenStream<'src>) -> Result<T, Error>,
+	) -> Result<Vec<T>, Error> {
+		let mut items = Vec::new();
+		loop {
 			match tokens.next() {
-				Some(tk) if tk == end_token => err!(UnexpectedToken, tk, tokens),
+				Some(tk) if tk == end_token => break,
 				Some(_) => {
 					tokens.prev();
-					let expr = Expression::parse(tokens)?;
-					args.push((key, expr));
+					items.push(parse_item(tokens)?);
 				}
 				None => err!(UnexpectedEOF, tokens),
-			};
+			}
 			match tokens.next() {
 				Some(Token::Comma) => (),
 				Some(tk) if tk == end_token => break,
-				_ => todo(tokens, line!())?,
+				Some(tk) => err!(UnexpectedToken, tk, tokens),
+				None => err!(UnexpectedEOF, tokens),
 			}
 		}
-		Ok(args)
+		Ok(items)
 	}
 
-	/// This function only parses what is between '[' and ']'. It is useful for expressions such
-	/// as `a[0] * a[1]` that are hard to parse in one go using `parse_tri_op` or the like.
+	/// This function only parses what is between '[' and ']'.
 	/// The preceding '[' is meant to be consumed before calling this function.
 	fn parse_index_op(var: Self, tokens: &mut TokenStream<'src>) -> Result<Self, Error> {
 		let pos = tokens.position();
@@ -959,6 +1066,10 @@ impl fmt::Display for Error {
 		use fmt::Write;
 		match &self.error {
 			ErrorType::NotANumber => f.write_str("Not a number"),
+			ErrorType::InvalidFormatExpression => f.write_str("Invalid expression in string"),
+			ErrorType::MissingDefault => {
+				f.write_str("Parameter without a default value after one with a default value")
+			}
 			ErrorType::UnexpectedToken(tk) => {
 				f.write_str("Unexpected token: '")?;
 				f.write_str(&tk)?;
@@ -969,8 +1080,9 @@ impl fmt::Display for Error {
 				f.write_str(&tk)?;
 				f.write_char('\'')
 			}
-			ErrorType::UnexpectedIndent(n) => write!(f, "Unexpected indent by {} tabs", n),
+			ErrorType::UnexpectedIndent(n) => write!(f, "Unexpected indent by {} levels", n),
 			ErrorType::UnexpectedEOF => f.write_str("Unexpected end of file"),
+			ErrorType::NestingTooDeep => f.write_str("Expression is nested too deeply"),
 			ErrorType::InternalError(line) => {
 				f.write_str("An internal error occured in the AST at line ")?;
 				f.write_str(&line.to_string())?;
@@ -981,15 +1093,32 @@ impl fmt::Display for Error {
 }
 
 #[derive(Debug, PartialEq)]
-enum NumberParseError {
+pub(crate) enum NumberParseError {
 	InvalidBase,
 	InvalidDigit,
 	Empty,
 	SeparatorInWrongPosition,
+	Overflow,
+	InvalidSuffix,
 }
 
-/// Custom number parsing function that allows underscores
-fn parse_number(s: &str) -> Result<Atom, NumberParseError> {
+/// Custom number parsing function that allows underscores. A number may end with an `i` or `r`
+/// suffix to force it to be an integer or a real respectively.
+pub(crate) fn parse_number(s: &str) -> Result<Atom<'_>, NumberParseError> {
+	match s.as_bytes().last() {
+		Some(b'i') => match parse_unsuffixed_number(&s[..s.len() - 1])? {
+			Atom::Integer(i) => Ok(Atom::Integer(i)),
+			_ => Err(NumberParseError::InvalidSuffix),
+		},
+		Some(b'r') => match parse_unsuffixed_number(&s[..s.len() - 1])? {
+			Atom::Integer(i) => Ok(Atom::Real(i as Real)),
+			r => Ok(r),
+		},
+		_ => parse_unsuffixed_number(s),
+	}
+}
+
+fn parse_unsuffixed_number(s: &str) -> Result<Atom<'_>, NumberParseError> {
 	let mut chars = s.chars();
 	let (chars, base) = if chars.next() == Some('0') {
 		if let Some(c) = chars.next() {
@@ -1046,7 +1175,7 @@ fn parse_number(s: &str) -> Result<Atom, NumberParseError> {
 				lh += c.to_digit(base).ok_or(NumberParseError::InvalidDigit)? as u64;
 				lh *= base as u64;
 				div *= base as u64;
-				if div > (1 << 53) {
+				if div > (1 << Real::MANTISSA_DIGITS) {
 					// We reached maximum precision
 					break;
 				}
@@ -1055,16 +1184,25 @@ fn parse_number(s: &str) -> Result<Atom, NumberParseError> {
 			for c in chars {
 				c.to_digit(base).ok_or(NumberParseError::InvalidDigit)?;
 			}
-			let n = uh as f64 + (lh as f64 / div as f64);
+			let n = uh as Real + (lh as Real / div as Real);
 			Ok(Atom::Real(if neg { -n } else { n }))
 		} else {
-			let mut n = 0;
+			let mut n: Integer = 0;
 			for c in chars {
-				n *= base as Integer;
+				let d = c.to_digit(base).ok_or(NumberParseError::InvalidDigit)? as Integer;
 				// Negative numbers have a larger range than positive numbers (e.g. i8 has range -128..127)
-				n -= c.to_digit(base).ok_or(NumberParseError::InvalidDigit)? as Integer;
+				n = n
+					.checked_mul(base as Integer)
+					.and_then(|n| n.checked_sub(d))
+					.ok_or(NumberParseError::Overflow)?;
+			}
+			if neg {
+				Ok(Atom::Integer(n))
+			} else {
+				n.checked_neg()
+					.map(Atom::Integer)
+					.ok_or(NumberParseError::Overflow)
 			}
-			Ok(Atom::Integer(if neg { n } else { -n }))
 		}
 	}
 }
@@ -1102,5 +1240,297 @@ mod test {
 			parse_number(".0"),
 			Err(NumberParseError::SeparatorInWrongPosition)
 		);
+		assert_eq!(
+			parse_number(&Integer::MIN.to_string()),
+			Ok(Atom::Integer(Integer::MIN))
+		);
+		assert_eq!(
+			parse_number(&Integer::MAX.to_string()),
+			Ok(Atom::Integer(Integer::MAX))
+		);
+		assert_eq!(
+			parse_number("99999999999999999999999"),
+			Err(NumberParseError::Overflow)
+		);
+	}
+
+	#[test]
+	fn number_suffix() {
+		assert_eq!(parse_number("42i"), Ok(Atom::Integer(42)));
+		assert_eq!(parse_number("42_i"), Ok(Atom::Integer(42)));
+		assert_eq!(parse_number("0xffi"), Ok(Atom::Integer(255)));
+		assert_eq!(parse_number("-7i"), Ok(Atom::Integer(-7)));
+		assert_eq!(parse_number("5r"), Ok(Atom::Real(5.0)));
+		assert_eq!(parse_number("-5r"), Ok(Atom::Real(-5.0)));
+		assert_eq!(parse_number("2.5r"), Ok(Atom::Real(2.5)));
+		assert_eq!(parse_number("2.5i"), Err(NumberParseError::InvalidSuffix));
+		assert_eq!(parse_number("42q"), Err(NumberParseError::InvalidDigit));
+		assert_eq!(parse_number("42_i32"), Err(NumberParseError::InvalidDigit));
+		assert_eq!(parse_number("r"), Err(NumberParseError::Empty));
+	}
+
+	#[cfg(feature = "real32")]
+	#[test]
+	fn number_real32() {
+		for s in ["3.1415927", "0.1", "16777215.0", "-2.5", "1234.5677"] {
+			let r = s.parse::<f32>().unwrap();
+			assert_eq!(parse_number(s), Ok(Atom::Real(r)));
+			assert_eq!(r.to_string().parse::<f32>().unwrap(), r);
+		}
+	}
+
+	#[test]
+	fn nesting_too_deep() {
+		let parse = |depth, open: &str, close: &str| {
+			let src = format!(
+				"fn f()\n\treturn {}x{}",
+				open.repeat(depth),
+				close.repeat(depth)
+			);
+			Script::parse(TokenStream::parse(&src, Indentation::Tabs).unwrap()).map(|_| ())
+		};
+		let too_deep = |r: Result<(), Error>| {
+			matches!(
+				r,
+				Err(Error {
+					error: ErrorType::NestingTooDeep,
+					..
+				})
+			)
+		};
+		assert!(parse(100, "(", ")").is_ok());
+		assert!(parse(100, "[", "]").is_ok());
+		assert!(too_deep(parse(100_000, "(", ")")));
+		assert!(too_deep(parse(100_000, "[", "]")));
+		assert!(too_deep(parse(100_000, "-", "")));
+		// Chains of operators and postfixes nest just as deeply
+		assert!(parse(100, "", " + 1").is_ok());
+		assert!(parse(100, "", ".f()").is_ok());
+		assert!(too_deep(parse(100_000, "", " + 1")));
+		assert!(too_deep(parse(100_000, "", " * 2 - 1")));
+		assert!(too_deep(parse(100_000, "", ".f()")));
+		assert!(too_deep(parse(100_000, "", ".x")));
+		assert!(too_deep(parse(100_000, "", "[0]")));
+	}
+
+	/// Parses the body of a function ending with an `if` and returns the amount of `if`s in
+	/// the chain and whether it ends with an `else`.
+	fn if_chain(body: &str) -> Result<(usize, bool), Error> {
+		fn chain(mut stmt: &Statement) -> (usize, bool) {
+			let mut n = 1;
+			loop {
+				match stmt {
+					Statement::If {
+						else_lines: Some(else_lines),
+						..
+					} => match &else_lines[..] {
+						[s @ Statement::If { .. }] => {
+							stmt = s;
+							n += 1;
+						}
+						_ => return (n, true),
+					},
+					Statement::If { .. } => return (n, false),
+					s => panic!("{:?}", s),
+				}
+			}
+		}
+		let src = format!("fn f(a, b)\n{}", body);
+		Script::parse(TokenStream::parse(&src, Indentation::Tabs).unwrap())
+			.map(|s| chain(s.functions[0].lines.last().unwrap()))
+	}
+
+	/// Asserts that parsing the body fails with an unexpected token at the given position.
+	fn assert_unexpected_in_if(body: &str, line: u32, column: u32) {
+		match if_chain(body) {
+			Err(Error {
+				error: ErrorType::UnexpectedToken(_),
+				line: l,
+				column: c,
+			}) => assert_eq!((l, c), (line, column), "{}", body),
+			Err(e) => panic!("{}\n{}", body, e),
+			Ok(s) => panic!("{}\n{:?}", body, s),
+		}
+	}
+
+	#[test]
+	fn elif_else() {
+		let ok = |body: &str| if_chain(body).unwrap_or_else(|e| panic!("{}", e));
+		assert_eq!(
+			ok("\tif a\n\t\tpass\n\telif b\n\t\tpass\n\telse\n\t\tpass"),
+			(2, true)
+		);
+		assert_eq!(
+			ok("\tif a\n\t\tpass\n\telif b\n\t\tpass\n\telif a\n\t\tpass"),
+			(3, false)
+		);
+		// The `else` belongs to the outer `if` as the inner block ended at a lower level
+		assert_eq!(
+			ok("\tif a\n\t\tif b\n\t\t\tpass\n\telse\n\t\tpass"),
+			(1, true)
+		);
+		assert_eq!(
+			ok("\tif a\n\t\tif b\n\t\t\tpass\n\t\telif a\n\t\t\tpass\n\telif b\n\t\tpass"),
+			(2, false)
+		);
+	}
+
+	#[test]
+	fn lone_elif() {
+		assert_unexpected_in_if("\telif a\n\t\tpass", 1, 1);
+		assert_unexpected_in_if("\twhile a\n\t\tpass\n\telif b\n\t\tpass", 3, 1);
+		assert_unexpected_in_if("\tif a\n\t\tpass\n\t\telif b\n\t\tpass", 3, 2);
+	}
+
+	#[test]
+	fn lone_else() {
+		assert_unexpected_in_if("\telse\n\t\tpass", 1, 1);
+		assert_unexpected_in_if("\tif a\n\t\tpass\n\t\telse\n\t\tpass", 3, 2);
+		assert_unexpected_in_if(
+			"\tfor i in a\n\t\tpass\n\telse\n\t\tpass\n\telse\n\t\tpass",
+			5,
+			1,
+		);
+	}
+
+	#[test]
+	fn else_before_elif() {
+		assert_unexpected_in_if(
+			"\tif a\n\t\tpass\n\telse\n\t\tpass\n\telif b\n\t\tpass",
+			5,
+			1,
+		);
+		assert_unexpected_in_if("\tif a\n\t\tpass\n\telse\n\t\tpass\n\telse\n\t\tpass", 5, 1);
+	}
+
+	/// Parses a call, array or dictionary and returns the amount of items in it.
+	fn list_len(expr: &str) -> Result<usize, Error> {
+		let src = format!("fn f(a)\n\treturn {}", expr);
+		let script = Script::parse(TokenStream::parse(&src, Indentation::Tabs).unwrap())?;
+		Ok(match &script.functions[0].lines[0] {
+			Statement::Return {
+				expr: Some(Expression::Function { arguments: a, .. }),
+				..
+			} => a.len(),
+			Statement::Return {
+				expr: Some(Expression::Array { array: a, .. }),
+				..
+			} => a.len(),
+			Statement::Return {
+				expr: Some(Expression::Dictionary { dictionary: d, .. }),
+				..
+			} => d.len(),
+			s => panic!("{:?}", s),
+		})
+	}
+
+	/// Asserts that parsing the list fails with the given error.
+	fn assert_list_error(expr: &str, eof: bool) {
+		match list_len(expr) {
+			Err(Error {
+				error: ErrorType::UnexpectedToken(_),
+				..
+			}) if !eof => (),
+			Err(Error {
+				error: ErrorType::UnexpectedEOF,
+				..
+			}) if eof => (),
+			Err(e) => panic!("{}: {}", expr, e),
+			Ok(_) => panic!("{}", expr),
+		}
+	}
+
+	/// The start, a single item and the end of a call, array and dictionary.
+	const LISTS: [(&str, &str, &str); 3] = [("a(", "1", ")"), ("[", "1", "]"), ("{", "1: 2", "}")];
+
+	#[test]
+	fn lists() {
+		for (open, item, close) in LISTS {
+			for n in 1..3 {
+				let expr = format!("{}{}{}", open, vec![item; n].join(", "), close);
+				assert_eq!(list_len(&expr).ok(), Some(n), "{}", expr);
+			}
+		}
+		for expr in [
+			"a(1 2)",
+			"[1 2]",
+			"{1 2}",
+			"{1: 2 3: 4}",
+			"{1: 2: 3}",
+			"{1:}",
+		] {
+			assert_list_error(expr, false);
+		}
+		for expr in ["a(1,", "[1", "{1: 2,"] {
+			assert_list_error(expr, true);
+		}
+	}
+
+	#[test]
+	fn empty_lists() {
+		for (open, _, close) in LISTS {
+			let expr = format!("{}{}", open, close);
+			assert_eq!(list_len(&expr).ok(), Some(0), "{}", expr);
+		}
+	}
+
+	#[test]
+	fn trailing_commas() {
+		for (open, item, close) in LISTS {
+			for n in 1..3 {
+				let expr = format!("{}{},{}", open, vec![item; n].join(", "), close);
+				assert_eq!(list_len(&expr).ok(), Some(n), "{}", expr);
+			}
+			// A comma alone is not a trailing comma
+			assert_list_error(&format!("{},{}", open, close), false);
+		}
+	}
+
+	#[test]
+	fn double_commas() {
+		for (open, item, close) in LISTS {
+			assert_list_error(&format!("{},,{}", open, close), false);
+			assert_list_error(&format!("{}{},,{}", open, item, close), false);
+			assert_list_error(&format!("{}{},,{}{}", open, item, item, close), false);
+		}
+	}
+
+	#[test]
+	fn word_operators() {
+		let src = "fn f(a, b)\n\tif a and not b\n\t\tpass";
+		let script = Script::parse(TokenStream::parse(src, Indentation::Tabs).unwrap())
+			.unwrap_or_else(|e| panic!("{}", e));
+		match &script.functions[0].lines[0] {
+			Statement::If {
+				expr:
+					Expression::Operation {
+						op: Op::AndThen,
+						left,
+						right,
+						..
+					},
+				..
+			} => {
+				assert!(matches!(
+					**left,
+					Expression::Atom {
+						atom: Atom::Name("a"),
+						..
+					}
+				));
+				assert!(matches!(
+					**right,
+					Expression::UnaryOperation {
+						op: UnaryOp::Not,
+						expr: box Expression::Atom {
+							atom: Atom::Name("b"),
+							..
+						},
+						..
+					}
+				));
+			}
+			s => panic!("{:?}", s),
+		}
 	}
 }
@@ -7,9 +7,10 @@ use crate::ast::{Atom, Expression, Function, Lines, Statement, UnaryOp};
 use crate::std_types::hash_map::Entry;
 use crate::std_types::*;
 use crate::tokenizer::{AssignOp, Op};
-use crate::{Rc, VariantType};
-use core::convert::TryInto;
+use crate::{Arity, Integer, Rc, Real, VariantType};
+use core::convert::{TryFrom, TryInto};
 use core::hash;
+use core::mem;
 use core::ops::Deref;
 use core::ptr;
 use unwrap_none::UnwrapNone;
@@ -19,17 +20,27 @@ where
 	V: VariantType,
 {
 	methods: &'e FxHashMap<Rc<str>, u8>,
+	/// The amount of arguments each method accepts, indexed by method.
+	arities: &'e [Arity],
 	locals: &'e FxHashMap<Rc<str>, u8>,
 	instr: Vec<Instruction>,
-	vars: FxHashMap<&'s str, u8>,
+	vars: FxHashMap<&'s str, u16>,
 	consts: Vec<V>,
-	curr_var_count: u8,
-	min_var_count: u8,
+	curr_var_count: u16,
+	min_var_count: u16,
 	param_count: u8,
+	variadic: bool,
 	loops: Vec<LoopContext>,
-	const_map: FxHashMap<Constant, u8>,
+	const_map: FxHashMap<Constant, u16>,
 	string_map: &'e mut FxHashSet<Rc<str>>,
 	jump_indices: Vec<(u32, u32)>,
+	/// Constants used inside loops, which are loaded once at the start of the function.
+	hoisted: Vec<u16>,
+	/// The placeholder register of each hoisted constant.
+	hoisted_map: FxHashMap<u16, u16>,
+	/// The name of each variable along with its register.
+	#[cfg(feature = "debug-info")]
+	var_names: Vec<(u16, Rc<str>)>,
 }
 
 enum LoopType {
@@ -44,6 +55,12 @@ struct LoopContext {
 	breaks: Vec<u32>,
 }
 
+/// The name of variables whose values are discarded.
+const DISCARD: &str = "_";
+
+/// The minimum amount of cases in an `if`/`elif` chain before a jump table is used.
+const MIN_SWITCH_CASES: usize = 4;
+
 pub struct ByteCodeError<'a> {
 	pub line: u32,
 	pub column: u32,
@@ -57,9 +74,13 @@ pub enum ByteCodeErrorType<'a> {
 	UnexpectedBreak(),
 	UnexpectedContinue(),
 	TooManyRegisters(),
+	TooManyConstants(),
 	Unsupported(&'a str),
 	UndefinedFunction(&'a str),
+	ArgumentCountMismatch(&'a str),
 	CantAssign(&'a str),
+	UnreachableCode(),
+	ConstantOperation(Box<dyn Error>),
 }
 
 macro_rules! err {
@@ -81,9 +102,10 @@ macro_rules! err {
 /// hashing and ordering `Real` types.
 #[derive(Clone, Debug, PartialOrd)]
 enum Constant {
+	None,
 	Bool(bool),
-	Int(isize),
-	Real(f64),
+	Int(Integer),
+	Real(Real),
 	Str(Rc<str>),
 }
 
@@ -94,6 +116,7 @@ where
 	pub(crate) fn parse(
 		function: Function<'s>,
 		methods: &'e FxHashMap<Rc<str>, u8>,
+		arities: &'e [Arity],
 		locals: &'e FxHashMap<Rc<str>, u8>,
 		string_map: &'e mut FxHashSet<Rc<str>>,
 	) -> Result<ByteCode<V>, ByteCodeError<'s>> {
@@ -101,104 +124,45 @@ where
 			instr: Vec::new(),
 			vars: HashMap::with_hasher(Default::default()),
 			consts: Vec::new(),
-			curr_var_count: function.parameters.len() as u8,
-			min_var_count: function.parameters.len() as u8,
+			curr_var_count: function.parameters.len() as u16,
+			min_var_count: function.parameters.len() as u16,
 			locals,
 			methods,
+			arities,
 			param_count: function.parameters.len() as u8,
+			variadic: function.variadic,
 			loops: Vec::new(),
 			const_map: HashMap::with_hasher(Default::default()),
 			string_map,
 			jump_indices: Vec::new(),
+			hoisted: Vec::new(),
+			hoisted_map: HashMap::with_hasher(Default::default()),
+			#[cfg(feature = "debug-info")]
+			var_names: Vec::new(),
 		};
 		for p in function.parameters {
-			if builder.vars.insert(p, builder.vars.len() as u8).is_some() {
+			let reg = builder.vars.len() as u16;
+			if builder.vars.insert(p, reg).is_some() {
 				err!(0, 0, DuplicateParameter, p);
 			}
+			builder.name_register(reg, p);
 		}
+		let defaults = function
+			.defaults
+			.into_iter()
+			.map(|d| builder.parse_default(d))
+			.collect::<Result<_, _>>()?;
 		builder.parse_block(function.lines)?;
+		// A jump may point past the last instruction if the function ends with a block, e.g.
+		// `if c` with only a `return` inside, in which case a `RetNone` is needed too.
+		let end = builder.instr.len() as u32;
+		let jumps_to_end = builder.jump_indices.iter().any(|&(_, jmp)| jmp == end);
 		match builder.instr.last() {
-			Some(Instruction::RetSome(_)) | Some(Instruction::RetNone) => (),
+			Some(Instruction::RetSome(_)) | Some(Instruction::RetNone) if !jumps_to_end => (),
 			_ => builder.instr.push(Instruction::RetNone),
 		}
-
-		if !builder.consts.is_empty() {
-			// All consts are using the upper-most registers, move them downwards
-			let offset = (u8::MAX - builder.consts.len() as u8).wrapping_add(1);
-			let min_var_count = builder.min_var_count;
-			for i in builder.instr.iter_mut() {
-				use Instruction::*;
-				let conv = |c: &mut u8| {
-					if *c >= offset {
-						*c = u8::MAX - *c + min_var_count
-					}
-				};
-				match i {
-					Call(a, box CallArgs { args, .. }) => {
-						conv(a);
-						for a in args.iter_mut() {
-							conv(a);
-						}
-					}
-					CallEnv {
-						args: box CallArgs { args, .. },
-					} => {
-						for a in args.iter_mut() {
-							conv(a);
-						}
-					}
-					CallSelf { args, .. } => {
-						args.iter_mut().for_each(conv);
-					}
-					JmpIf(a, _)
-					| JmpNotIf(a, _)
-					| Iter(_, a, _)
-					| RetSome(a)
-					| Neg(_, a)
-					| Not(_, a)
-					| Store(a, _)
-					| Load(a, _)
-					| Move(_, a) => conv(a),
-					Add(_, a, b)
-					| Sub(_, a, b)
-					| Mul(_, a, b)
-					| Div(_, a, b)
-					| Rem(_, a, b)
-					| And(_, a, b)
-					| Or(_, a, b)
-					| Xor(_, a, b)
-					| Shr(_, a, b)
-					| Shl(_, a, b)
-					| Eq(_, a, b)
-					| Neq(_, a, b)
-					| Less(_, a, b)
-					| LessEq(_, a, b)
-					| SetIndex(a, _, b)
-					| GetIndex(a, _, b) => {
-						conv(a);
-						conv(b);
-					}
-					IterInt {
-						from: a,
-						to: b,
-						step: c,
-						..
-					} => {
-						conv(a);
-						conv(b);
-						conv(c);
-					}
-					IterJmp(_, _)
-					| IterIntJmp(_, _)
-					| Break { .. }
-					| Jmp(_)
-					| RetNone
-					| CopySelf { .. }
-					| NewArray(_, _)
-					| NewDictionary(_, _) => (),
-				}
-			}
-		}
+		builder.remove_self_moves();
+		builder.hoist_consts(function.line, function.column)?;
 
 		let name = builder.map_string(function.name);
 
@@ -212,27 +176,52 @@ where
 				Jmp(jp)
 				| JmpIf(_, jp)
 				| JmpNotIf(_, jp)
+				| JmpNotNone(_, jp)
 				| Iter(_, _, jp)
 				| IterJmp(_, jp)
 				| IterInt { jmp_ip: jp, .. }
 				| IterIntJmp(_, jp)
-				| Break { jmp_ip: jp, .. } => *jp = unsafe { code_ptr.offset(jmp as isize) },
+				| Break { jmp_ip: jp, .. }
+				| Try(_, jp) => *jp = unsafe { code_ptr.offset(jmp as isize) },
 				b => panic!("Not a branching instruction: {}:{}  {:?}", instr, jmp, b),
 			}
 		}
 
+		// Give each method call its own entry in the method cache
+		let mut methods = 0;
+		for i in code.iter_mut() {
+			if let Instruction::Call(_, ca) = i {
+				ca.cache = methods;
+				methods += 1;
+			}
+		}
+
 		Ok(ByteCode {
 			code,
 			var_count: builder.min_var_count,
 			param_count: builder.param_count,
 			consts: builder.consts,
+			defaults,
+			variadic: builder.variadic,
 			name,
+			methods: (0..methods).map(|_| Cell::default()).collect(),
+			#[cfg(feature = "debug-info")]
+			var_names: builder.var_names.into(),
 		})
 	}
 
 	fn parse_block(&mut self, lines: Lines<'s>) -> Result<(), ByteCodeError<'s>> {
 		let mut frame_vars = Vec::new();
+		let mut terminated = false;
 		for line in lines {
+			if terminated {
+				let (l, c) = line.position();
+				err!(l, c, UnreachableCode);
+			}
+			terminated = matches!(
+				line,
+				Statement::Return { .. } | Statement::Continue { .. } | Statement::Break { .. }
+			);
 			match line {
 				Statement::Expression { expr, .. } => {
 					self.parse_expression(None, expr)?;
@@ -243,12 +232,18 @@ where
 					to,
 					step,
 					lines,
+					else_lines,
 					line,
 					column,
 				} => {
 					let og_cvc = self.curr_var_count;
 					let (l, c) = (line, column);
 
+					let to_integer = self
+						.eval_const(&to)?
+						.and_then(|v| v.as_integer().ok())
+						.is_some();
+
 					// Parse `to` expression
 					let iter_reg = self.alloc_reg(l, c)?;
 					let iter_reg = if let Some(r) = self.parse_expression(Some(iter_reg), to)? {
@@ -258,45 +253,25 @@ where
 						iter_reg
 					};
 
-					// Parse `from` and `step` expressions, if any
-					let from_step = if let Some(from) = from {
+					// Parse `from` and `step` expressions, if any. IterInt expects the step in the
+					// register after `from`.
+					let from = if from.is_some() || step.is_some() || to_integer {
 						let from_reg = self.alloc_reg(l, c)?;
-						let from = if let Some(r) = self.parse_expression(Some(from_reg), from)? {
-							self.dealloc_reg();
-							r
-						} else {
-							from_reg
-						};
-						let step = if let Some(step) = step {
-							let step_reg = self.alloc_reg(l, c)?;
-							if let Some(r) = self.parse_expression(Some(step_reg), step)? {
-								self.dealloc_reg();
-								r
-							} else {
-								step_reg
-							}
-						} else {
-							self.add_const(V::new_integer(1))
-						};
-						Some((from, step))
-					} else if let Some(step) = step {
-						let from = self.add_const(V::new_integer(0));
 						let step_reg = self.alloc_reg(l, c)?;
-						let step = if let Some(r) = self.parse_expression(Some(step_reg), step)? {
-							self.dealloc_reg();
-							r
-						} else {
-							step_reg
-						};
-						Some((from, step))
-					} else if self
-						.get_const(iter_reg)
-						.and_then(|v| v.as_integer().ok())
-						.is_some()
-					{
-						let from = self.add_const(V::new_integer(0));
-						let step = self.add_const(V::new_integer(1));
-						Some((from, step))
+						self.update_min_vars();
+						match from {
+							Some(from) => self.parse_expression_into(from_reg, from)?,
+							None => {
+								self.load_const(Some(from_reg), V::new_integer(0), l, c)?;
+							}
+						}
+						match step {
+							Some(step) => self.parse_expression_into(step_reg, step)?,
+							None => {
+								self.load_const(Some(step_reg), V::new_integer(1), l, c)?;
+							}
+						}
+						Some(from_reg)
 					} else {
 						None
 					};
@@ -304,13 +279,16 @@ where
 
 					// Insert var and iter instruction
 					let var_reg = self.alloc_reg(l, c)?;
-					self.vars.insert(var, var_reg).expect_none(var);
-					if let Some((from, step)) = from_step {
+					self.update_min_vars();
+					if var != DISCARD {
+						self.vars.insert(var, var_reg).expect_none(var);
+						self.name_register(var_reg, var);
+					}
+					if let Some(from) = from {
 						self.instr.push(Instruction::IterInt {
-							reg: var_reg.try_into().expect("TODO"),
+							reg: var_reg,
 							from,
 							to: iter_reg,
-							step,
 							jmp_ip: ptr::null(),
 						});
 					} else {
@@ -322,7 +300,7 @@ where
 
 					// Parse loop block
 					self.loops.push(LoopContext {
-						loop_type: if from_step.is_some() {
+						loop_type: if from.is_some() {
 							LoopType::ForInteger
 						} else {
 							LoopType::ForGeneric
@@ -344,7 +322,7 @@ where
 						let i = self.instr.len() as u32;
 						self.jump_indices.push((i, ip));
 					}
-					if from_step.is_none() {
+					if from.is_none() {
 						self.instr.push(Instruction::IterJmp(var_reg, ptr::null()));
 					} else {
 						self.instr
@@ -353,21 +331,38 @@ where
 					let ip = self.instr.len() as u32;
 					self.jump_indices.push((ic as u32, ip));
 
-					// Make `break`s jump to right after the `IterJmp` instruction
+					// Remove loop variable
+					if var != DISCARD {
+						self.vars.remove(var).expect(var);
+					}
+
+					self.curr_var_count = og_cvc;
+
+					// The `else` block is only skipped by `break`s
+					if let Some(else_lines) = else_lines {
+						self.parse_block(else_lines)?;
+					}
+
+					// Make `break`s jump to right after the `IterJmp` instruction or `else` block
 					for i in context.breaks {
 						let ip = self.instr.len() as u32;
 						self.jump_indices.push((i, ip));
 					}
-
-					// Remove loop variable
-					self.vars.remove(var).expect(var);
-
-					self.curr_var_count = og_cvc;
 				}
-				Statement::While { expr, lines, .. } => {
+				Statement::While {
+					expr,
+					lines,
+					else_lines,
+					..
+				} => {
 					let og_cvc = self.curr_var_count;
 
 					// Insert `Jmp` to the expr evaluation
+					//
+					// Testing at the bottom means each iteration runs only a single jump. Testing
+					// at the top instead saves this `Jmp` but needs an extra jump per iteration, or
+					// a duplicate of the condition to avoid it, which only pays off for loops that
+					// are entered often yet rarely iterate.
 					let start_ip = self.instr.len();
 					self.instr.push(Instruction::Jmp(ptr::null()));
 
@@ -378,10 +373,12 @@ where
 						breaks: Vec::new(),
 					});
 					self.parse_block(lines)?;
-					let context = self.loops.pop().unwrap();
+					// The loop is only popped after the expression, as it is evaluated every
+					// iteration too.
+					let continues = mem::take(&mut self.loops.last_mut().unwrap().continues);
 
 					// Make `continue`s jump to the expression evaluation
-					for i in context.continues {
+					for i in continues {
 						let ip = self.instr.len() as u32;
 						self.jump_indices.push((i, ip));
 					}
@@ -393,6 +390,7 @@ where
 					// Parse expression
 					let expr_reg = self.curr_var_count;
 					self.curr_var_count += 1;
+					self.update_min_vars();
 					let expr_reg = if let Some(r) = self.parse_expression(Some(expr_reg), expr)? {
 						self.curr_var_count -= 1;
 						r
@@ -403,14 +401,44 @@ where
 						.push((self.instr.len() as u32, start_ip as u32 + 1));
 					self.instr
 						.push(Instruction::JmpNotIf(expr_reg, ptr::null()));
+					let context = self.loops.pop().unwrap();
+
+					self.curr_var_count = og_cvc;
+
+					// The `else` block is only skipped by `break`s
+					if let Some(else_lines) = else_lines {
+						self.parse_block(else_lines)?;
+					}
 
-					// Make `break`s jump to right after the expression evaluation
+					// Make `break`s jump to right after the expression evaluation or `else` block
 					for i in context.breaks {
 						let ip = self.instr.len() as u32;
 						self.jump_indices.push((i, ip));
 					}
+				}
+				Statement::Loop { lines, .. } => {
+					let start_ip = self.instr.len() as u32;
+					// Like `while` loops, there is no iterator to pop on `break`.
+					self.loops.push(LoopContext {
+						loop_type: LoopType::While,
+						continues: Vec::new(),
+						breaks: Vec::new(),
+					});
+					self.parse_block(lines)?;
+					let context = self.loops.pop().unwrap();
 
-					self.curr_var_count = og_cvc;
+					// Jump back to the start unconditionally, `continue`s do the same
+					for i in context.continues {
+						self.jump_indices.push((i, start_ip));
+					}
+					self.jump_indices.push((self.instr.len() as u32, start_ip));
+					self.instr.push(Instruction::Jmp(ptr::null()));
+
+					// `break` is the only way out
+					for i in context.breaks {
+						let ip = self.instr.len() as u32;
+						self.jump_indices.push((i, ip));
+					}
 				}
 				Statement::If {
 					expr,
@@ -418,14 +446,12 @@ where
 					else_lines,
 					..
 				} => {
+					if let Some(table) = self.switch_table(&expr, &else_lines) {
+						self.parse_switch(table, expr, lines, else_lines)?;
+						continue;
+					}
 					// If
-					let expr = self.parse_expression(Some(self.curr_var_count), expr)?;
-					let expr = if let Some(expr) = expr {
-						expr
-					} else {
-						self.curr_var_count += 1;
-						self.curr_var_count - 1
-					};
+					let expr = self.parse_condition(expr)?;
 					self.instr.push(Instruction::JmpIf(expr, ptr::null()));
 					let ic = self.instr.len() as u32 - 1;
 					self.parse_block(lines)?;
@@ -444,9 +470,14 @@ where
 						self.jump_indices.push((skip_else_jmp.unwrap(), ip));
 					}
 				}
-				Statement::Return { expr, .. } => {
+				Statement::Return { expr, line, column } => {
 					if let Some(expr) = expr {
-						let r = self.parse_expression(Some(0), expr)?.unwrap_or(0);
+						// Use a scratch register as writing to any other register may clobber
+						// a variable that is still used by the expression.
+						let r = self.alloc_reg(line, column)?;
+						self.update_min_vars();
+						let r = self.parse_expression(Some(r), expr)?.unwrap_or(r);
+						self.dealloc_reg();
 						self.instr.push(Instruction::RetSome(r));
 					} else {
 						self.instr.push(Instruction::RetNone);
@@ -462,26 +493,30 @@ where
 					// Get the register or property to which much be assigned
 					match var {
 						Expression::Atom { atom, .. } => match atom {
+							Atom::Name(DISCARD) if assign_op == AssignOp::None => {
+								// Evaluate the expression only for its side effects
+								let r = self.alloc_reg(line, column)?;
+								self.update_min_vars();
+								self.parse_expression(Some(r), expr)?;
+								self.dealloc_reg();
+							}
 							Atom::Name(var) => {
 								if let Some(&reg) = self.vars.get(var) {
-									let expr = self.parse_expression(Some(reg), expr)?;
 									if let AssignOp::None = assign_op {
-										if let Some(expr) = expr {
+										if let Some(expr) =
+											self.parse_expression(Some(reg), expr)?
+										{
 											self.instr.push(Instruction::Move(reg, expr));
 										}
 									} else {
-										let expr = if let Some(expr) = expr { expr } else { reg };
-										self.instr.push(match assign_op {
-											AssignOp::None => unreachable!(),
-											AssignOp::Add => Instruction::Add(reg, reg, expr),
-											AssignOp::Sub => Instruction::Sub(reg, reg, expr),
-											AssignOp::Mul => Instruction::Mul(reg, reg, expr),
-											AssignOp::Div => Instruction::Div(reg, reg, expr),
-											AssignOp::Rem => Instruction::Rem(reg, reg, expr),
-											AssignOp::And => Instruction::And(reg, reg, expr),
-											AssignOp::Or => Instruction::Or(reg, reg, expr),
-											AssignOp::Xor => Instruction::Xor(reg, reg, expr),
-										});
+										// The expression can't be stored in the variable directly as
+										// the operation still needs its current value.
+										let og_cvc = self.curr_var_count;
+										let expr =
+											self.parse_expression_new_reg(expr, line, column)?;
+										self.update_min_vars();
+										self.instr.push(compound_op(assign_op, reg, reg, expr));
+										self.curr_var_count = og_cvc;
 									}
 								} else {
 									err!(line, column, UndefinedVariable, var);
@@ -515,41 +550,21 @@ where
 											let og_cvc = self.curr_var_count;
 											let e =
 												self.parse_expression_new_reg(expr, line, column)?;
+											self.update_min_vars();
 											if let AssignOp::None = assign_op {
+												self.instr.push(Instruction::Store(e, *local));
 											} else {
-												let tmp_reg = self.curr_var_count;
-												self.curr_var_count += 1;
+												// `e` may be a variable or constant, so the result
+												// needs a register of its own.
+												let tmp_reg = self.alloc_reg(line, column)?;
 												self.update_min_vars();
 												self.instr.push(Instruction::Load(tmp_reg, *local));
-												self.instr.push(match assign_op {
-													AssignOp::None => unreachable!(),
-													AssignOp::Add => {
-														Instruction::Add(e, tmp_reg, e)
-													}
-													AssignOp::Sub => {
-														Instruction::Sub(e, tmp_reg, e)
-													}
-													AssignOp::Mul => {
-														Instruction::Mul(e, tmp_reg, e)
-													}
-													AssignOp::Div => {
-														Instruction::Div(e, tmp_reg, e)
-													}
-													AssignOp::Rem => {
-														Instruction::Rem(e, tmp_reg, e)
-													}
-													AssignOp::And => {
-														Instruction::And(e, tmp_reg, e)
-													}
-													AssignOp::Or => Instruction::Or(e, tmp_reg, e),
-													AssignOp::Xor => {
-														Instruction::Xor(e, tmp_reg, e)
-													}
-												});
-												self.curr_var_count -= 1;
+												self.instr.push(compound_op(
+													assign_op, tmp_reg, tmp_reg, e,
+												));
+												self.instr
+													.push(Instruction::Store(tmp_reg, *local));
 											}
-											self.instr.push(Instruction::Store(e, *local));
-											self.update_min_vars();
 											self.curr_var_count = og_cvc;
 										} else {
 											err!(line, column, UndefinedVariable, var);
@@ -562,12 +577,9 @@ where
 										"Complex lvalues are not supported yet"
 									),
 								},
-								Atom::Env => err!(
-									line,
-									column,
-									Unsupported,
-									"Environment variables are not supported yet"
-								),
+								Atom::Env => {
+									err!(line, column, CantAssign, "environment constants")
+								}
 								_ => err!(
 									line,
 									column,
@@ -594,7 +606,17 @@ where
 							let right = self.parse_expression_new_reg(*right, line, column)?;
 							let expr = self.parse_expression_new_reg(expr, line, column)?;
 							self.update_min_vars();
-							self.instr.push(Instruction::SetIndex(expr, left, right));
+							if let AssignOp::None = assign_op {
+								self.instr.push(Instruction::SetIndex(expr, left, right));
+							} else {
+								// The index is only evaluated once and the element is modified in
+								// a register of its own, as `expr` may be a variable or constant.
+								let elem = self.alloc_reg(line, column)?;
+								self.update_min_vars();
+								self.instr.push(Instruction::GetIndex(elem, left, right));
+								self.instr.push(compound_op(assign_op, elem, elem, expr));
+								self.instr.push(Instruction::SetIndex(elem, left, right));
+							}
 							self.curr_var_count = og_cvc;
 						}
 						_ => err!(
@@ -608,8 +630,10 @@ where
 				Statement::LooseExpression { expr, .. } => {
 					self.parse_expression(None, expr)?;
 				}
+				Statement::Declare { var: DISCARD, .. } => (),
 				Statement::Declare { var, line, column } => {
 					if self.vars.insert(var, self.curr_var_count).is_none() {
+						self.name_register(self.curr_var_count, var);
 						self.curr_var_count += 1;
 						self.min_var_count = self.min_var_count.max(self.curr_var_count);
 						frame_vars.push(var);
@@ -628,7 +652,8 @@ where
 						.get_mut(i)
 						.ok_or_else(err!(lazy line, column, UnexpectedContinue))?;
 					c.continues.push(self.instr.len() as u32);
-					self.instr.push(Instruction::Jmp(ptr::null()));
+					// The iterators of the loops that are left must be popped
+					self.instr.push(self.exit_loops(levels as usize));
 				}
 				Statement::Break {
 					levels,
@@ -644,27 +669,11 @@ where
 						.get_mut(i)
 						.ok_or_else(err!(lazy line, column, UnexpectedBreak))?;
 					c.breaks.push(self.instr.len() as u32);
-					let (mut amount, mut amount_int) = (0, 0);
-					for l in self.loops.iter().rev().take(levels) {
-						match l.loop_type {
-							LoopType::While => (),
-							LoopType::ForGeneric => amount += 1,
-							LoopType::ForInteger => amount_int += 1,
-						}
-					}
-					self.instr.push(if amount == 0 && amount_int == 0 {
-						Instruction::Jmp(ptr::null())
-					} else {
-						Instruction::Break {
-							amount,
-							amount_int,
-							jmp_ip: ptr::null(),
-						}
-					})
+					self.instr.push(self.exit_loops(levels));
 				}
 			}
 		}
-		self.min_var_count = self.min_var_count.max(self.vars.len() as u8);
+		self.min_var_count = self.min_var_count.max(self.vars.len() as u16);
 		for fv in frame_vars {
 			self.vars.remove(fv).unwrap();
 		}
@@ -676,7 +685,7 @@ where
 		expr: Expression<'s>,
 		line: u32,
 		column: u32,
-	) -> Result<u8, ByteCodeError<'s>> {
+	) -> Result<u16, ByteCodeError<'s>> {
 		let r = self.alloc_reg(line, column)?;
 		Ok(if let Some(r) = self.parse_expression(Some(r), expr)? {
 			self.dealloc_reg();
@@ -686,19 +695,175 @@ where
 		})
 	}
 
+	/// Returns a jump that pops the iterators of the innermost `levels` loops.
+	fn exit_loops(&self, levels: usize) -> Instruction {
+		let (mut amount, mut amount_int) = (0, 0);
+		for l in self.loops.iter().rev().take(levels) {
+			match l.loop_type {
+				LoopType::While => (),
+				LoopType::ForGeneric => amount += 1,
+				LoopType::ForInteger => amount_int += 1,
+			}
+		}
+		if amount == 0 && amount_int == 0 {
+			Instruction::Jmp(ptr::null())
+		} else {
+			Instruction::Break {
+				amount,
+				amount_int,
+				jmp_ip: ptr::null(),
+			}
+		}
+	}
+
+	/// Parses an expression and ensures the result is stored in the given register.
+	fn parse_expression_into(
+		&mut self,
+		store: u16,
+		expr: Expression<'s>,
+	) -> Result<(), ByteCodeError<'s>> {
+		if let Some(r) = self.parse_expression(Some(store), expr)? {
+			self.instr.push(Instruction::Move(store, r));
+		}
+		Ok(())
+	}
+
 	fn parse_expression(
 		&mut self,
-		store: Option<u8>,
+		store: Option<u16>,
 		expr: Expression<'s>,
-	) -> Result<Option<u8>, ByteCodeError<'s>> {
+	) -> Result<Option<u16>, ByteCodeError<'s>> {
+		if let Expression::Operation { .. } | Expression::UnaryOperation { .. } = expr {
+			if let Some(v) = self.eval_const(&expr)? {
+				let (line, column) = expr.position();
+				return self.const_operand(store, v, line, column);
+			}
+		}
 		match expr {
 			Expression::Operation {
-				left, op, right, ..
+				left,
+				op: Op::Coalesce,
+				right,
+				line,
+				column,
+			} => {
+				let og_cvc = self.curr_var_count;
+				let store = self.store_or_alloc(store, line, column)?;
+				// Evaluate the left operand in a separate register as the store register may
+				// be used by the right operand.
+				let r_left = self.curr_var_count;
+				self.curr_var_count += 1;
+				let left = if let Some(l) = self.parse_expression(Some(r_left), *left)? {
+					self.curr_var_count -= 1;
+					l
+				} else {
+					r_left
+				};
+				self.update_min_vars();
+				let jmp_not_none = self.instr.len() as u32;
+				self.instr.push(Instruction::JmpNotNone(left, ptr::null()));
+				// The right operand is only evaluated if the left operand is none
+				if let Some(r) = self.parse_expression(Some(store), *right)? {
+					self.instr.push(Instruction::Move(store, r));
+				}
+				let jmp_end = self.instr.len() as u32;
+				self.instr.push(Instruction::Jmp(ptr::null()));
+				self.jump_indices
+					.push((jmp_not_none, self.instr.len() as u32));
+				self.instr.push(Instruction::Move(store, left));
+				self.jump_indices.push((jmp_end, self.instr.len() as u32));
+				self.curr_var_count = og_cvc;
+				Ok(None)
+			}
+			Expression::Operation {
+				left,
+				op: op @ (Op::AndThen | Op::OrElse),
+				right,
+				line,
+				column,
+			} => {
+				let og_cvc = self.curr_var_count;
+				let store = self.store_or_alloc(store, line, column)?;
+				// Like with `??` the left operand is evaluated in a separate register as the
+				// right operand may still use the store register.
+				let r_left = self.curr_var_count;
+				self.curr_var_count += 1;
+				let left = if let Some(l) = self.parse_expression(Some(r_left), *left)? {
+					self.curr_var_count -= 1;
+					l
+				} else {
+					r_left
+				};
+				self.update_min_vars();
+				// The right operand is only evaluated if the left operand is true for `&&` or
+				// false for `||`.
+				let jmp_left = self.instr.len() as u32;
+				self.instr.push(if op == Op::AndThen {
+					Instruction::JmpIf(left, ptr::null())
+				} else {
+					Instruction::JmpNotIf(left, ptr::null())
+				});
+				self.parse_expression_into(store, *right)?;
+				let jmp_end = self.instr.len() as u32;
+				self.instr.push(Instruction::Jmp(ptr::null()));
+				self.jump_indices.push((jmp_left, self.instr.len() as u32));
+				self.instr.push(Instruction::Move(store, left));
+				self.jump_indices.push((jmp_end, self.instr.len() as u32));
+				self.curr_var_count = og_cvc;
+				Ok(None)
+			}
+			Expression::Operation {
+				left: box Expression::Atom {
+					atom: Atom::_Self, ..
+				},
+				op: Op::Access,
+				right:
+					box Expression::Atom {
+						atom: Atom::Name(name),
+						line,
+						column,
+					},
+				..
+			} => {
+				if let Some(&local) = self.locals.get(name) {
+					// Reading a local has no side effects, so it is skipped if the value is unused.
+					if let Some(store) = store {
+						self.instr.push(Instruction::Load(store, local));
+					}
+					Ok(None)
+				} else {
+					err!(line, column, UndefinedVariable, name)
+				}
+			}
+			Expression::Operation {
+				left: box Expression::Atom {
+					atom: Atom::Env, ..
+				},
+				op: Op::Access,
+				right: box Expression::Atom {
+					atom: Atom::Name(name),
+					..
+				},
+				..
+			} => {
+				// Like reading a local, this is skipped if the value is unused.
+				if let Some(store) = store {
+					let name = Box::new(self.map_string(name));
+					self.instr.push(Instruction::LoadEnv(store, name));
+				}
+				Ok(None)
+			}
+			Expression::Operation {
+				left,
+				op,
+				right,
+				line,
+				column,
 			} => {
 				let store = store.expect("TODO: handle operations without store location");
 				let og_cvc = self.curr_var_count;
-				let (r_left, r_right) = (self.curr_var_count, self.curr_var_count + 1);
-				self.curr_var_count += 2;
+				let r_left = self.curr_var_count;
+				self.curr_var_count += 1;
 				let or_left = self.parse_expression(Some(r_left), *left)?;
 				let left = if let Some(l) = or_left {
 					self.curr_var_count -= 1;
@@ -706,6 +871,10 @@ where
 				} else {
 					r_left
 				};
+				// Only reserve the right register now so it can't overlap with the left register
+				// if the latter turned out to be unused.
+				let r_right = self.curr_var_count;
+				self.curr_var_count += 1;
 				let or_right = self.parse_expression(Some(r_right), *right)?;
 				let right = if let Some(r) = or_right {
 					self.curr_var_count -= 1;
@@ -725,19 +894,39 @@ where
 					Op::Xor => Instruction::Xor(store, left, right),
 					Op::ShiftLeft => Instruction::Shl(store, left, right),
 					Op::ShiftRight => Instruction::Shr(store, left, right),
+					Op::ShiftRightUnsigned => Instruction::Ushr(store, left, right),
 					Op::Eq => Instruction::Eq(store, left, right),
 					Op::Neq => Instruction::Neq(store, left, right),
 					Op::Less => Instruction::Less(store, left, right),
 					Op::Greater => Instruction::Less(store, right, left),
 					Op::LessEq => Instruction::LessEq(store, left, right),
 					Op::GreaterEq => Instruction::LessEq(store, right, left),
-					Op::Not | Op::AndThen | Op::OrElse => todo!(),
+					Op::Not => err!(line, column, Unsupported, "'!' is not a binary operator"),
+					Op::AndThen | Op::OrElse => unreachable!("&& and || are handled separately"),
 					Op::Index => Instruction::GetIndex(store, left, right),
+					Op::Coalesce => unreachable!("?? is handled separately"),
 					Op::Access => panic!("{:?} is not an actual op (bug in AST)", Op::Access),
 				});
 				self.curr_var_count = og_cvc;
 				Ok(None)
 			}
+			Expression::UnaryOperation {
+				expr,
+				op: UnaryOp::Try,
+				line,
+				column,
+			} => {
+				let og_cvc = self.curr_var_count;
+				let store = self.store_or_alloc(store, line, column)?;
+				self.update_min_vars();
+				let try_ip = self.instr.len() as u32;
+				self.instr.push(Instruction::Try(store, ptr::null()));
+				self.parse_expression_into(store, *expr)?;
+				self.instr.push(Instruction::EndTry);
+				self.jump_indices.push((try_ip, self.instr.len() as u32));
+				self.curr_var_count = og_cvc;
+				Ok(None)
+			}
 			Expression::UnaryOperation { expr, op, .. } => {
 				let store = store.expect("TODO: handle operations without store location");
 				let og_cvc = self.curr_var_count;
@@ -753,6 +942,8 @@ where
 				self.instr.push(match op {
 					UnaryOp::Neg => Instruction::Neg(store, expr),
 					UnaryOp::Not => Instruction::Not(store, expr),
+					UnaryOp::Str => Instruction::Str(store, expr),
+					UnaryOp::Try => unreachable!("try is handled separately"),
 				});
 				self.curr_var_count = og_cvc;
 				Ok(None)
@@ -768,20 +959,22 @@ where
 					if let Some(&reg) = self.vars.get(name) {
 						Ok(Some(reg))
 					} else if let Some(&local) = self.locals.get(name) {
-						let store = store.expect("No register to store local in");
-						self.instr.push(Instruction::Load(store, local));
+						if let Some(store) = store {
+							self.instr.push(Instruction::Load(store, local));
+						}
 						Ok(None)
 					} else {
 						err!(line, column, UndefinedVariable, name)
 					}
 				}
-				Atom::Real(r) => Ok(Some(self.add_const(V::new_real(r)))),
-				Atom::Integer(i) => Ok(Some(self.add_const(V::new_integer(i)))),
+				Atom::Real(r) => self.const_operand(store, V::new_real(r), line, column),
+				Atom::Integer(i) => self.const_operand(store, V::new_integer(i), line, column),
 				Atom::String(s) => {
 					let s = V::new_string(self.map_string(s));
-					Ok(Some(self.add_const(s)))
+					self.const_operand(store, s, line, column)
 				}
-				Atom::Bool(b) => Ok(Some(self.add_const(V::new_bool(b)))),
+				Atom::Bool(b) => self.const_operand(store, V::new_bool(b), line, column),
+				Atom::None => self.const_operand(store, V::new_none(), line, column),
 			},
 			Expression::Function {
 				expr,
@@ -795,7 +988,7 @@ where
 				enum Obj {
 					_Self,
 					Env,
-					Some(u8),
+					Some(u16),
 				}
 
 				// Parse expression on which to call the function on
@@ -841,12 +1034,16 @@ where
 					store_in: store,
 					func: self.map_string(name),
 					args: args.into_boxed_slice(),
+					cache: 0,
 				});
 
 				self.instr.push(match expr {
 					Obj::Some(expr) => Instruction::Call(expr, ca),
 					Obj::_Self => {
 						if let Some(&func) = self.methods.get(name) {
+							if !self.arities[usize::from(func)].accepts(ca.args.len()) {
+								err!(line, column, ArgumentCountMismatch, name);
+							}
 							let mut args = Box::new([0; 16]);
 							for (i, &a) in ca.args.into_iter().enumerate() {
 								args[i] = a;
@@ -854,6 +1051,7 @@ where
 							Instruction::CallSelf {
 								store_in: ca.store_in,
 								func,
+								arg_count: ca.args.len() as u8,
 								args,
 							}
 						} else {
@@ -877,7 +1075,9 @@ where
 				};
 				self.instr
 					.push(Instruction::NewArray(array_reg, array.len()));
+				let elem_cvc = self.curr_var_count;
 				for (i, expr) in array.into_iter().enumerate() {
+					let (line, column) = expr.position();
 					let r = self.curr_var_count;
 					self.curr_var_count += 1;
 					let r = if let Some(e) = self.parse_expression(Some(r), expr)? {
@@ -886,9 +1086,10 @@ where
 					} else {
 						r
 					};
+					let i = self.const_reg(V::new_integer(i as Integer), line, column)?;
 					self.update_min_vars();
-					let i = self.add_const(V::new_integer(i as isize));
 					self.instr.push(Instruction::SetIndex(r, array_reg, i));
+					self.curr_var_count = elem_cvc;
 				}
 				self.curr_var_count = og_cvc;
 				Ok(ret)
@@ -904,15 +1105,18 @@ where
 				};
 				self.instr
 					.push(Instruction::NewDictionary(dict_reg, dictionary.len()));
+				let elem_cvc = self.curr_var_count;
 				for (key_expr, val_expr) in dictionary {
-					let (k, v) = (self.curr_var_count, self.curr_var_count + 1);
-					self.curr_var_count += 2;
+					let k = self.curr_var_count;
+					self.curr_var_count += 1;
 					let k = if let Some(e) = self.parse_expression(Some(k), key_expr)? {
 						self.curr_var_count -= 1;
 						e
 					} else {
 						k
 					};
+					let v = self.curr_var_count;
+					self.curr_var_count += 1;
 					let v = if let Some(e) = self.parse_expression(Some(v), val_expr)? {
 						self.curr_var_count -= 1;
 						e
@@ -921,6 +1125,7 @@ where
 					};
 					self.update_min_vars();
 					self.instr.push(Instruction::SetIndex(v, dict_reg, k));
+					self.curr_var_count = elem_cvc;
 				}
 				self.curr_var_count = og_cvc;
 				Ok(ret)
@@ -928,44 +1133,381 @@ where
 		}
 	}
 
-	fn add_const(&mut self, var: V) -> u8 {
+	/// Parses the condition of an `if` statement and returns the register it is stored in.
+	fn parse_condition(&mut self, expr: Expression<'s>) -> Result<u16, ByteCodeError<'s>> {
+		let expr = self.parse_expression(Some(self.curr_var_count), expr)?;
+		Ok(if let Some(expr) = expr {
+			expr
+		} else {
+			self.curr_var_count += 1;
+			self.update_min_vars();
+			self.curr_var_count - 1
+		})
+	}
+
+	/// Returns the register of the variable and the value if `expr` compares a variable against
+	/// an integer constant, e.g. `x == 3`.
+	fn switch_case(&self, expr: &Expression<'s>) -> Option<(u16, Integer)> {
+		match expr {
+			Expression::Operation {
+				op: Op::Eq,
+				left,
+				right,
+				..
+			} => match (&**left, &**right) {
+				(
+					Expression::Atom {
+						atom: Atom::Name(var),
+						..
+					},
+					Expression::Atom {
+						atom: Atom::Integer(i),
+						..
+					},
+				) => self.vars.get(var).map(|&r| (r, *i)),
+				_ => None,
+			},
+			_ => None,
+		}
+	}
+
+	/// Returns the `if` statement if it is the only statement in an `else` block, i.e. an
+	/// `elif`.
+	fn elif<'a>(else_lines: &'a Option<Lines<'s>>) -> Option<&'a Statement<'s>> {
+		match else_lines.as_deref() {
+			Some([s @ Statement::If { .. }]) => Some(s),
+			_ => None,
+		}
+	}
+
+	/// Determines whether an `if`/`elif` chain compares the same variable against enough
+	/// integer constants that are close enough together to use a jump table. Returns the
+	/// register of the variable, the lowest value and the size of the table.
+	fn switch_table(
+		&self,
+		expr: &Expression<'s>,
+		mut else_lines: &Option<Lines<'s>>,
+	) -> Option<(u16, Integer, u16)> {
+		let (reg, value) = self.switch_case(expr)?;
+		let mut values = vec![value];
+		while let Some(Statement::If {
+			expr,
+			else_lines: e,
+			..
+		}) = Self::elif(else_lines)
+		{
+			match self.switch_case(expr) {
+				Some((r, v)) if r == reg => values.push(v),
+				_ => break,
+			}
+			else_lines = e;
+		}
+		values.sort_unstable();
+		values.dedup();
+		let (min, max) = (values[0], values[values.len() - 1]);
+		let len = max.checked_sub(min)?.checked_add(1)?;
+		let len = u16::try_from(len).ok()?;
+		let dense = usize::from(len) <= values.len() * 2;
+		(values.len() >= MIN_SWITCH_CASES && dense).then_some((reg, min, len))
+	}
+
+	/// Parses an `if`/`elif` chain using a jump table. The conditions are still compiled as
+	/// usual so that values that aren't integers are compared the same way as in a regular
+	/// chain.
+	fn parse_switch(
+		&mut self,
+		(reg, min, len): (u16, Integer, u16),
+		mut expr: Expression<'s>,
+		mut lines: Lines<'s>,
+		mut else_lines: Option<Lines<'s>>,
+	) -> Result<(), ByteCodeError<'s>> {
+		self.instr.push(Instruction::Switch { reg, min, len });
+		// One entry for each value, one for integers that are out of range.
+		let table = self.instr.len() as u32;
+		for _ in 0..=len {
+			self.instr.push(Instruction::Jmp(ptr::null()));
+		}
+
+		let mut cases = Vec::new();
+		let mut skip_jmps = Vec::new();
+		let default = loop {
+			let value = self.switch_case(&expr).expect("not a switch case").1;
+			let cond = self.parse_condition(expr)?;
+			self.instr.push(Instruction::JmpIf(cond, ptr::null()));
+			let ic = self.instr.len() as u32 - 1;
+			cases.push((value, self.instr.len() as u32));
+			self.parse_block(lines)?;
+			if else_lines.is_some() {
+				self.instr.push(Instruction::Jmp(ptr::null()));
+				skip_jmps.push(self.instr.len() as u32 - 1);
+			}
+			let ip = self.instr.len() as u32;
+			self.jump_indices.push((ic, ip));
+
+			let next = Self::elif(&else_lines)
+				.and_then(|s| match s {
+					Statement::If { expr, .. } => self.switch_case(expr),
+					_ => None,
+				})
+				.filter(|&(r, _)| r == reg);
+			match (next, else_lines) {
+				(Some(_), Some(mut l)) => match l.pop() {
+					Some(Statement::If {
+						expr: e,
+						lines: b,
+						else_lines: el,
+						..
+					}) => {
+						expr = e;
+						lines = b;
+						else_lines = el;
+					}
+					_ => unreachable!(),
+				},
+				(_, Some(l)) => {
+					self.parse_block(l)?;
+					break ip;
+				}
+				(_, None) => break ip,
+			}
+		};
+		let end = self.instr.len() as u32;
+		for jmp in skip_jmps {
+			self.jump_indices.push((jmp, end));
+		}
+
+		for i in 0..len {
+			let value = min + i as Integer;
+			// Earlier branches take precedence in case a value is compared multiple times.
+			let target = cases.iter().find(|c| c.0 == value).map_or(default, |c| c.1);
+			self.jump_indices.push((table + u32::from(i), target));
+		}
+		self.jump_indices.push((table + u32::from(len), default));
+		Ok(())
+	}
+
+	/// Evaluates an expression that only consists of literals and operations on them. Returns
+	/// `None` if the expression isn't constant.
+	fn eval_const(&mut self, expr: &Expression<'s>) -> Result<Option<V>, ByteCodeError<'s>> {
+		let (r, line, column) = match expr {
+			Expression::Atom { atom, .. } => {
+				return Ok(match atom {
+					Atom::Real(r) => Some(V::new_real(*r)),
+					Atom::Integer(i) => Some(V::new_integer(*i)),
+					Atom::String(s) => Some(V::new_string(self.map_string(&**s))),
+					Atom::Bool(b) => Some(V::new_bool(*b)),
+					Atom::None => Some(V::new_none()),
+					_ => None,
+				})
+			}
+			Expression::Operation {
+				left,
+				op,
+				right,
+				line,
+				column,
+			} => {
+				let a = match self.eval_const(left)? {
+					Some(a) => a,
+					None => return Ok(None),
+				};
+				let b = match self.eval_const(right)? {
+					Some(b) => b,
+					None => return Ok(None),
+				};
+				// Leave operations that panic or wrap around to the interpreter
+				if let (Ok(x), Ok(y)) = (a.as_integer(), b.as_integer()) {
+					let faults = match op {
+						Op::Add => x.checked_add(y).is_none(),
+						Op::Sub => x.checked_sub(y).is_none(),
+						Op::Mul => x.checked_mul(y).is_none(),
+						Op::Div => x.checked_div(y).is_none(),
+						Op::Rem => x.checked_rem(y).is_none(),
+						Op::ShiftLeft | Op::ShiftRight | Op::ShiftRightUnsigned => {
+							u32::try_from(y).map_or(true, |y| y >= Integer::BITS)
+						}
+						_ => false,
+					};
+					if faults {
+						return Ok(None);
+					}
+				}
+				let r = match op {
+					Op::Add => a.add(&b),
+					Op::Sub => a.sub(&b),
+					Op::Mul => a.mul(&b),
+					Op::Div => a.div(&b),
+					Op::Rem => a.rem(&b),
+					Op::And => a.bitand(&b),
+					Op::Or => a.bitor(&b),
+					Op::Xor => a.bitxor(&b),
+					Op::ShiftLeft => a.lhs(&b),
+					Op::ShiftRight => a.rhs(&b),
+					Op::ShiftRightUnsigned => a.rhs_unsigned(&b),
+					Op::Eq => Ok((a == b).into()),
+					Op::Neq => Ok((a != b).into()),
+					Op::Less => Ok((a < b).into()),
+					Op::Greater => Ok((b < a).into()),
+					Op::LessEq => Ok((a <= b).into()),
+					Op::GreaterEq => Ok((b <= a).into()),
+					Op::Coalesce => Ok(if a.is_none() { b } else { a }),
+					Op::AndThen | Op::OrElse | Op::Not | Op::Index | Op::Access => return Ok(None),
+				};
+				(r, *line, *column)
+			}
+			// An operation on constants that fails would fail at runtime too.
+			Expression::UnaryOperation {
+				op: UnaryOp::Try,
+				expr,
+				..
+			} => {
+				return Ok(self
+					.eval_const(expr)
+					.unwrap_or_else(|_| Some(V::new_none())))
+			}
+			Expression::UnaryOperation {
+				op,
+				expr,
+				line,
+				column,
+			} => {
+				let a = match self.eval_const(expr)? {
+					Some(a) => a,
+					None => return Ok(None),
+				};
+				let r = match op {
+					UnaryOp::Neg => a.neg(),
+					UnaryOp::Not => a.not(),
+					UnaryOp::Str => Ok(V::new_string(a.to_string().into())),
+					UnaryOp::Try => unreachable!("try is handled separately"),
+				};
+				(r, *line, *column)
+			}
+			_ => return Ok(None),
+		};
+		match r {
+			Ok(v) => Ok(Some(match v.into_string() {
+				Ok(s) => V::new_string(self.map_string(s)),
+				Err(v) => v,
+			})),
+			Err(e) => err!(line, column, ConstantOperation, e),
+		}
+	}
+
+	/// Converts the default value of a parameter. Only literals are allowed, which includes
+	/// negative numbers.
+	fn parse_default(&mut self, expr: Expression<'s>) -> Result<V, ByteCodeError<'s>> {
+		let msg = "Default values must be literals";
+		match expr {
+			Expression::Atom { atom, line, column } => Ok(match atom {
+				Atom::Real(r) => V::new_real(r),
+				Atom::Integer(i) => V::new_integer(i),
+				Atom::String(s) => V::new_string(self.map_string(s)),
+				Atom::Bool(b) => V::new_bool(b),
+				Atom::None => V::new_none(),
+				_ => err!(line, column, Unsupported, msg),
+			}),
+			expr => {
+				let (line, column) = expr.position();
+				err!(line, column, Unsupported, msg)
+			}
+		}
+	}
+
+	fn add_const(&mut self, var: V, line: u32, column: u32) -> Result<u16, ByteCodeError<'s>> {
 		let key = Constant::from_variant(var).expect("Failed to convert Variant to Constant");
 		match self.const_map.entry(key) {
 			Entry::Vacant(e) => {
+				let i = self
+					.consts
+					.len()
+					.try_into()
+					.ok()
+					.ok_or_else(err!(lazy line, column, TooManyConstants))?;
 				self.consts.push(e.key().clone().into_variant());
-				let r = u8::MAX - self.consts.len() as u8 + 1;
-				e.insert(r);
-				r
+				e.insert(i);
+				Ok(i)
 			}
-			Entry::Occupied(e) => *e.get(),
+			Entry::Occupied(e) => Ok(*e.get()),
 		}
 	}
 
-	fn get_const(&self, reg: u8) -> Option<V> {
-		// TODO add a reverse map
-		for (k, &v) in self.const_map.iter() {
-			if v == reg {
-				return Some(k.clone().into_variant());
-			}
+	/// Loads a constant into the `store` register. Nothing is emitted if there is no register to
+	/// store it in, as the value would be discarded anyways.
+	fn load_const(
+		&mut self,
+		store: Option<u16>,
+		var: V,
+		line: u32,
+		column: u32,
+	) -> Result<Option<u16>, ByteCodeError<'s>> {
+		if let Some(store) = store {
+			let i = self.add_const(var, line, column)?;
+			self.instr.push(Instruction::LoadConst(store, i));
+			// The store register may not have been accounted for yet, e.g. for conditions.
+			let count = store
+				.checked_add(1)
+				.ok_or_else(err!(lazy line, column, TooManyRegisters))?;
+			self.min_var_count = self.min_var_count.max(count);
 		}
-		None
+		Ok(None)
 	}
 
-	fn map_string(&mut self, string: impl Into<Rc<str>> + Deref<Target = str>) -> Rc<str> {
-		if let Some(string) = self.string_map.get(&*string) {
-			string.clone()
-		} else {
-			let string: Rc<str> = string.into();
-			self.string_map.insert(string.clone());
-			string
+	/// Loads a constant into a newly allocated register and returns the register.
+	fn const_reg(&mut self, var: V, line: u32, column: u32) -> Result<u16, ByteCodeError<'s>> {
+		if !self.loops.is_empty() {
+			return self.hoisted_reg(var, line, column);
 		}
+		let r = self.alloc_reg(line, column)?;
+		self.load_const(Some(r), var, line, column)?;
+		Ok(r)
+	}
+
+	/// Like `load_const`, but inside a loop the constant is hoisted and its register is returned
+	/// instead so it isn't loaded again every iteration.
+	fn const_operand(
+		&mut self,
+		store: Option<u16>,
+		var: V,
+		line: u32,
+		column: u32,
+	) -> Result<Option<u16>, ByteCodeError<'s>> {
+		if store.is_none() || self.loops.is_empty() {
+			return self.load_const(store, var, line, column);
+		}
+		self.hoisted_reg(var, line, column).map(Some)
+	}
+
+	/// Returns the placeholder register of a hoisted constant. Placeholders count down from
+	/// `u16::MAX` and are replaced with real registers by `hoist_consts`.
+	fn hoisted_reg(&mut self, var: V, line: u32, column: u32) -> Result<u16, ByteCodeError<'s>> {
+		let i = self.add_const(var, line, column)?;
+		let hoisted = &mut self.hoisted;
+		Ok(*self.hoisted_map.entry(i).or_insert_with(|| {
+			hoisted.push(i);
+			u16::MAX - (hoisted.len() - 1) as u16
+		}))
 	}
 
+	fn map_string(&mut self, string: impl Into<Rc<str>> + Deref<Target = str>) -> Rc<str> {
+		crate::util::intern(self.string_map, string)
+	}
+
+	/// Remembers which variable a register belongs to, for debuggers.
+	#[cfg(feature = "debug-info")]
+	fn name_register(&mut self, reg: u16, var: &str) {
+		let var = self.map_string(var);
+		self.var_names.push((reg, var));
+	}
+
+	#[cfg(not(feature = "debug-info"))]
+	fn name_register(&mut self, _: u16, _: &str) {}
+
 	fn update_min_vars(&mut self) {
 		self.min_var_count = self.min_var_count.max(self.curr_var_count);
 	}
 
-	fn alloc_reg(&mut self, line: u32, column: u32) -> Result<u8, ByteCodeError<'s>> {
+	fn alloc_reg(&mut self, line: u32, column: u32) -> Result<u16, ByteCodeError<'s>> {
 		let r = self.curr_var_count;
 		self.curr_var_count = self.curr_var_count.checked_add(1).ok_or_else(|| {
 			ByteCodeError::new(line, column, ByteCodeErrorType::TooManyRegisters())
@@ -976,6 +1518,157 @@ where
 	fn dealloc_reg(&mut self) {
 		self.curr_var_count -= 1;
 	}
+
+	/// Returns `store` or, if the result of an expression is unused, a scratch register. The
+	/// scratch register is freed by restoring `curr_var_count`.
+	fn store_or_alloc(
+		&mut self,
+		store: Option<u16>,
+		line: u32,
+		column: u32,
+	) -> Result<u16, ByteCodeError<'s>> {
+		store.map_or_else(|| self.alloc_reg(line, column), Ok)
+	}
+
+	/// Loads the hoisted constants into the registers after all other registers at the start of
+	/// the function. `line` and `column` are the position of the function.
+	fn hoist_consts(&mut self, line: u32, column: u32) -> Result<(), ByteCodeError<'s>> {
+		let n = self.hoisted.len();
+		if n == 0 {
+			return Ok(());
+		}
+		let base = self.min_var_count;
+		if usize::from(base) + n > usize::from(u16::MAX) {
+			err!(line, column, TooManyRegisters);
+		}
+		let first = u16::MAX - (n - 1) as u16;
+		for instr in self.instr.iter_mut() {
+			map_registers(instr, |r| {
+				if *r >= first {
+					*r = base + (u16::MAX - *r);
+				}
+			});
+		}
+		let prologue = self
+			.hoisted
+			.iter()
+			.enumerate()
+			.map(|(k, &i)| Instruction::LoadConst(base + k as u16, i));
+		self.instr.splice(0..0, prologue);
+		for (instr, target) in self.jump_indices.iter_mut() {
+			*instr += n as u32;
+			*target += n as u32;
+		}
+		self.min_var_count += n as u16;
+		Ok(())
+	}
+
+	/// Removes moves of a register to itself, e.g. from `a = a`. Jumps are adjusted to account
+	/// for the removed instructions.
+	fn remove_self_moves(&mut self) {
+		let mut removed = Vec::new();
+		let mut i = 0;
+		self.instr.retain(|instr| {
+			let keep = !matches!(instr, Instruction::Move(a, b) if a == b);
+			if !keep {
+				removed.push(i);
+			}
+			i += 1;
+			keep
+		});
+		// A jump to a removed instruction goes to the instruction after it instead.
+		let shift = |ip: u32| ip - removed.partition_point(|&r| r < ip) as u32;
+		for (instr, target) in self.jump_indices.iter_mut() {
+			*instr = shift(*instr);
+			*target = shift(*target);
+		}
+	}
+}
+
+/// Calls `f` with each register operand of an instruction.
+fn map_registers(instr: &mut Instruction, mut f: impl FnMut(&mut u16)) {
+	use Instruction::*;
+	match instr {
+		Call(r, ca) => {
+			f(r);
+			ca.store_in.iter_mut().for_each(&mut f);
+			ca.args.iter_mut().for_each(f);
+		}
+		CallSelf {
+			store_in,
+			arg_count,
+			args,
+			..
+		} => {
+			store_in.iter_mut().for_each(&mut f);
+			args[..usize::from(*arg_count)].iter_mut().for_each(f);
+		}
+		CallEnv { args } => {
+			args.store_in.iter_mut().for_each(&mut f);
+			args.args.iter_mut().for_each(f);
+		}
+		Jmp(_) | RetNone | Break { .. } | EndTry => (),
+		JmpIf(r, _)
+		| JmpNotIf(r, _)
+		| JmpNotNone(r, _)
+		| Switch { reg: r, .. }
+		| RetSome(r)
+		| Try(r, _)
+		| IterJmp(r, _)
+		| IterIntJmp(r, _)
+		| Store(r, _)
+		| Load(r, _)
+		| LoadConst(r, _)
+		| LoadEnv(r, _)
+		| CopySelf { dest: r }
+		| NewArray(r, _)
+		| NewDictionary(r, _) => f(r),
+		Iter(a, b, _) | Not(a, b) | Neg(a, b) | Str(a, b) | Move(a, b) => {
+			f(a);
+			f(b);
+		}
+		IterInt { reg, from, to, .. } => {
+			f(reg);
+			f(from);
+			f(to);
+		}
+		Add(a, b, c)
+		| Sub(a, b, c)
+		| Mul(a, b, c)
+		| Div(a, b, c)
+		| Rem(a, b, c)
+		| And(a, b, c)
+		| Or(a, b, c)
+		| Xor(a, b, c)
+		| Shl(a, b, c)
+		| Shr(a, b, c)
+		| Ushr(a, b, c)
+		| LessEq(a, b, c)
+		| Less(a, b, c)
+		| Neq(a, b, c)
+		| Eq(a, b, c)
+		| GetIndex(a, b, c)
+		| SetIndex(a, b, c) => {
+			f(a);
+			f(b);
+			f(c);
+		}
+	}
+}
+
+/// Returns the instruction that performs the operation of a compound assignment.
+fn compound_op(op: AssignOp, r: u16, a: u16, b: u16) -> Instruction {
+	match op {
+		AssignOp::None => unreachable!(),
+		AssignOp::Add => Instruction::Add(r, a, b),
+		AssignOp::Sub => Instruction::Sub(r, a, b),
+		AssignOp::Mul => Instruction::Mul(r, a, b),
+		AssignOp::Div => Instruction::Div(r, a, b),
+		AssignOp::Rem => Instruction::Rem(r, a, b),
+		AssignOp::And => Instruction::And(r, a, b),
+		AssignOp::Or => Instruction::Or(r, a, b),
+		AssignOp::Xor => Instruction::Xor(r, a, b),
+	}
 }
 
 impl Constant {
@@ -983,6 +1676,9 @@ impl Constant {
 	where
 		V: VariantType,
 	{
+		if var.is_none() {
+			return Ok(Self::None);
+		}
 		Ok(match var.as_bool() {
 			Ok(v) => Self::Bool(v),
 			Err(v) => match v.as_integer() {
@@ -1003,6 +1699,7 @@ impl Constant {
 		V: VariantType,
 	{
 		match self {
+			Self::None => V::new_none(),
 			Self::Bool(b) => V::new_bool(b),
 			Self::Int(i) => V::new_integer(i),
 			Self::Real(r) => V::new_real(r),
@@ -1017,8 +1714,9 @@ impl hash::Hash for Constant {
 		H: hash::Hasher,
 	{
 		match self {
+			Self::None => h.write_u8(2),
 			Self::Bool(n) => h.write_u8(if *n { 1 } else { 0 }),
-			Self::Int(n) => h.write_isize(*n),
+			Self::Int(n) => h.write(&n.to_ne_bytes()),
 			Self::Str(n) => h.write(n.as_bytes()),
 			Self::Real(n) => {
 				if n.is_nan() {
@@ -1034,6 +1732,7 @@ impl hash::Hash for Constant {
 impl PartialEq for Constant {
 	fn eq(&self, rhs: &Self) -> bool {
 		match (self, rhs) {
+			(Self::None, Self::None) => true,
 			(Self::Bool(a), Self::Bool(b)) => a == b,
 			(Self::Int(a), Self::Int(b)) => a == b,
 			(Self::Str(a), Self::Str(b)) => a == b,
@@ -1076,9 +1775,19 @@ impl fmt::Display for ByteCodeError<'_> {
 			&ByteCodeErrorType::TooManyRegisters() => {
 				w("Too many registers allocated (use less variables!)", "")
 			}
+			&ByteCodeErrorType::TooManyConstants() => w("Too many constants", ""),
 			ByteCodeErrorType::Unsupported(v) => w(v, ""),
 			ByteCodeErrorType::UndefinedFunction(v) => w("Undefined function", v),
+			ByteCodeErrorType::ArgumentCountMismatch(v) => {
+				w("Wrong amount of arguments for function", v)
+			}
 			ByteCodeErrorType::CantAssign(v) => w("Can't assign to", v),
+			ByteCodeErrorType::ConstantOperation(e) => {
+				w("Invalid operation on constants:", &e.to_string())
+			}
+			ByteCodeErrorType::UnreachableCode() => {
+				w("Unreachable code after return, break or continue", "")
+			}
 		}
 	}
 }
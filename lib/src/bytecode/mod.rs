@@ -9,84 +9,109 @@ pub(crate) use builder::{ByteCodeBuilder, ByteCodeError};
 pub use tracer::Tracer;
 
 use crate::std_types::*;
-use crate::{Array, Dictionary, Environment, ScriptObject, VariantType};
+use crate::{
+	Arity, Array, Dictionary, Environment, Integer, Method, ScriptObject, ScriptType, VariantType,
+};
+use core::any::TypeId;
+use core::cell::Cell;
+use core::convert::TryFrom;
 use core::fmt::{self, Debug, Formatter};
 use core::intrinsics::unlikely;
 use core::mem;
-use std::error::Error;
 use tracer::*;
 
 pub struct CallArgs {
-	store_in: Option<u8>,
+	store_in: Option<u16>,
 	func: Rc<str>,
-	args: Box<[u8]>,
+	args: Box<[u16]>,
+	/// The index of the entry in the method cache, only used by `Call`.
+	cache: u32,
 }
 
 // Check to ensure the size doesn't go over a certain limit
 const _INSTR_SIZE_CHECK: usize = 16 - mem::size_of::<Instruction>();
 
 pub enum Instruction {
-	Call(u8, Box<CallArgs>),
+	Call(u16, Box<CallArgs>),
 	CallSelf {
-		store_in: Option<u8>,
+		store_in: Option<u16>,
 		func: u8,
-		args: Box<[u8; 16]>,
+		arg_count: u8,
+		args: Box<[u16; 16]>,
 	},
 	CallEnv {
 		args: Box<CallArgs>,
 	},
 
 	Jmp(*const Instruction),
-	JmpIf(u8, *const Instruction),
-	JmpNotIf(u8, *const Instruction),
-	RetSome(u8),
+	JmpIf(u16, *const Instruction),
+	JmpNotIf(u16, *const Instruction),
+	JmpNotNone(u16, *const Instruction),
+	/// Skips `reg - min` instructions if `reg` is an integer in the range `min..min + len`,
+	/// `len` instructions if it is outside the range and `len + 1` if it isn't an integer.
+	Switch {
+		reg: u16,
+		len: u16,
+		min: Integer,
+	},
+	RetSome(u16),
 	RetNone,
+	/// Stores `none` in `reg` and jumps to the given instruction if any of the instructions
+	/// before the matching `EndTry` returns an error.
+	Try(u16, *const Instruction),
+	EndTry,
 
-	Iter(u8, u8, *const Instruction),
-	IterJmp(u8, *const Instruction),
+	Iter(u16, u16, *const Instruction),
+	IterJmp(u16, *const Instruction),
+	/// The step is stored in the register after `from`, which keeps the instruction small.
 	IterInt {
-		reg: u8,
-		from: u8,
-		to: u8,
-		step: u8,
+		reg: u16,
+		from: u16,
+		to: u16,
 		jmp_ip: *const Instruction,
 	},
-	IterIntJmp(u8, *const Instruction),
+	IterIntJmp(u16, *const Instruction),
 	Break {
 		amount: u8,
 		amount_int: u8,
 		jmp_ip: *const Instruction,
 	},
 
-	Add(u8, u8, u8),
-	Sub(u8, u8, u8),
-	Mul(u8, u8, u8),
-	Div(u8, u8, u8),
-	Rem(u8, u8, u8),
-	And(u8, u8, u8),
-	Or(u8, u8, u8),
-	Xor(u8, u8, u8),
-	Shl(u8, u8, u8),
-	Shr(u8, u8, u8),
-	Not(u8, u8),
-	Neg(u8, u8),
-
-	LessEq(u8, u8, u8),
-	Less(u8, u8, u8),
-	Neq(u8, u8, u8),
-	Eq(u8, u8, u8),
-
-	Store(u8, u8),
-	Load(u8, u8),
-	Move(u8, u8),
+	Add(u16, u16, u16),
+	Sub(u16, u16, u16),
+	Mul(u16, u16, u16),
+	Div(u16, u16, u16),
+	Rem(u16, u16, u16),
+	And(u16, u16, u16),
+	Or(u16, u16, u16),
+	Xor(u16, u16, u16),
+	Shl(u16, u16, u16),
+	Shr(u16, u16, u16),
+	Ushr(u16, u16, u16),
+	Not(u16, u16),
+	Neg(u16, u16),
+	Str(u16, u16),
+
+	LessEq(u16, u16, u16),
+	Less(u16, u16, u16),
+	Neq(u16, u16, u16),
+	Eq(u16, u16, u16),
+
+	Store(u16, u8),
+	Load(u16, u8),
+	Move(u16, u16),
+	LoadConst(u16, u16),
+	/// Loads the environment constant with the given name. The name is boxed to keep the
+	/// instruction small.
+	LoadEnv(u16, Box<Rc<str>>),
 	CopySelf {
-		dest: u8,
+		dest: u16,
 	},
 
-	NewArray(u8, usize),
-	NewDictionary(u8, usize),
-	GetIndex(u8, u8, u8),
-	SetIndex(u8, u8, u8),
+	NewArray(u16, usize),
+	NewDictionary(u16, usize),
+	GetIndex(u16, u16, u16),
+	SetIndex(u16, u16, u16),
 }
 
 pub struct ByteCode<V>
@@ -95,11 +120,23 @@ where
 {
 	code: Box<[Instruction]>,
 	param_count: u8,
-	var_count: u8,
+	var_count: u16,
 	consts: Vec<V>,
+	/// The default values of the last parameters
+	defaults: Vec<V>,
+	/// Whether the last parameter collects any remaining arguments into an array
+	variadic: bool,
 	name: Rc<str>,
+	/// The method each `Call` instruction resolved last.
+	methods: Box<[Cell<Option<CachedMethod<V>>>]>,
+	/// The name of each variable along with its register.
+	#[cfg(feature = "debug-info")]
+	var_names: Box<[(u16, Rc<str>)]>,
 }
 
+/// A resolved method along with the type of object it was resolved for.
+type CachedMethod<V> = (TypeId, Option<Method<V>>);
+
 pub struct RunState<'a, V>
 where
 	V: VariantType,
@@ -109,10 +146,51 @@ where
 
 pub type CallResult<T> = Result<T, Box<dyn Error>>;
 
+/// An error that occured while running a function.
+#[derive(Debug)]
+pub enum RunError {
+	/// An integer was divided by zero.
+	DivideByZero,
+	/// The result of an integer operation does not fit in an integer.
+	Overflow,
+	/// An error along with the function's name and the instruction that failed. Errors
+	/// returned by calls made from the function are wrapped too, so following
+	/// [`Error::source`] yields a trace from the outermost to the innermost function.
+	At {
+		function: Rc<str>,
+		ip: u32,
+		error: Box<dyn Error>,
+	},
+}
+
+/// The state of a `for` loop over anything but a range of integers.
+enum IterState<V>
+where
+	V: VariantType,
+{
+	/// Arrays are iterated by index, which avoids boxing an iterator and doesn't keep the
+	/// array borrowed.
+	Array {
+		array: Array<V>,
+		index: usize,
+	},
+	Other(Box<dyn Iterator<Item = V>>),
+}
+
 struct IterIntState {
-	current: isize,
-	step: isize,
-	stop: isize,
+	current: Integer,
+	step: Integer,
+	stop: Integer,
+}
+
+/// A protected region started by a `Try` instruction.
+struct TryState {
+	reg: u16,
+	catch_ip: *const Instruction,
+	/// The amount of iterators when the region was entered. Any iterators pushed since then
+	/// are removed if an error is caught.
+	iterators: usize,
+	iterators_int: usize,
 }
 
 macro_rules! try_break {
@@ -169,10 +247,16 @@ macro_rules! reg {
 
 macro_rules! run_op {
 	($vars:ident, $r:ident = $a:ident $op:tt $b:ident) => {
-		reg!(mut $vars $r) = try_break!(reg!(ref $vars $a).$op(reg!(ref $vars $b)));
+		reg!(mut $vars $r) = match reg!(ref $vars $a).$op(reg!(ref $vars $b)) {
+			Ok(v) => v,
+			Err(e) => break Err(err::op(e)),
+		};
 	};
 	($vars:ident, $r:ident = $a:ident $op:tt) => {
-		reg!(mut $vars $r) = try_break!(reg!(ref $vars $a).$op());
+		reg!(mut $vars $r) = match reg!(ref $vars $a).$op() {
+			Ok(v) => v,
+			Err(e) => break Err(err::op(e)),
+		};
 	};
 }
 
@@ -194,24 +278,29 @@ where
 		args: &[&V],
 		env: &Environment<V>,
 		tracer: &T,
-	) -> Result<V, Box<dyn std::error::Error>>
+	) -> Result<V, Box<dyn Error>>
 	where
 		T: Tracer<V>,
 	{
-		if args.len() != self.param_count as usize {
+		if !self.accepts_arg_count(args.len()) {
 			return Err(err::arg_count());
 		}
 
-		let vars_len = self.var_count as usize + self.consts.len();
+		let vars_len = self.var_count as usize;
 		let mut vars = Vec::with_capacity(vars_len);
 		// Extend is terribly slow, hence manual iteration
-		for &a in args.iter() {
+		let fixed = self.fixed_param_count();
+		for &a in args.iter().take(fixed) {
 			vars.push(a.clone());
 		}
-		vars.resize_with(self.var_count as usize, V::default);
-		for c in self.consts.iter() {
-			vars.push(c.clone());
+		for d in self.missing_defaults(args.len()) {
+			vars.push(d.clone());
+		}
+		if self.variadic {
+			let rest = args.get(fixed..).unwrap_or(&[]);
+			vars.push(Self::collect_rest(rest.iter().map(|&a| a.clone())));
 		}
+		vars.resize_with(vars_len, V::default);
 
 		let mut call_args = [core::ptr::null(); 16];
 
@@ -232,6 +321,89 @@ where
 		)
 	}
 
+	/// Whether the function can be called with the given amount of arguments.
+	#[inline]
+	fn accepts_arg_count(&self, count: usize) -> bool {
+		let fixed = self.fixed_param_count();
+		count >= fixed - self.defaults.len() && (self.variadic || count <= fixed)
+	}
+
+	/// The amount of parameters excluding the variadic parameter.
+	#[inline]
+	fn fixed_param_count(&self) -> usize {
+		self.param_count as usize - usize::from(self.variadic)
+	}
+
+	/// Returns the default values of the parameters for which no argument was passed.
+	#[inline]
+	fn missing_defaults(&self, arg_count: usize) -> &[V] {
+		let fixed = self.fixed_param_count();
+		&self.defaults[arg_count.min(fixed) + self.defaults.len() - fixed..]
+	}
+
+	/// Collects the arguments passed to the variadic parameter into an array.
+	fn collect_rest(rest: impl Iterator<Item = V>) -> V {
+		V::new_object(ScriptObject(Rc::new(Array::from_vec(rest.collect()))))
+	}
+
+	/// Returns the method the call with the given cache index resolved for objects of the same
+	/// type, resolving it first if the type differs.
+	#[inline]
+	fn resolve_method(
+		&self,
+		cache: u32,
+		object: &ScriptObject<V>,
+		func: &str,
+	) -> Option<Method<V>> {
+		let cache = &self.methods[cache as usize];
+		let type_id = ScriptType::type_id(&***object);
+		match cache.get() {
+			Some((t, method)) if t == type_id => method,
+			_ => {
+				let method = object.resolve(func);
+				cache.set(Some((type_id, method)));
+				method
+			}
+		}
+	}
+
+	/// Calls `map`, `filter` or `reduce` on an array if the callback is a function of the
+	/// script. Returns `None` if the call should be handled by the variant instead.
+	#[allow(clippy::too_many_arguments)]
+	fn call_array_callback<T>(
+		obj: &V,
+		func: &str,
+		args: &[&V],
+		object: &ScriptObject<V>,
+		functions: &[Self],
+		locals: &mut [V],
+		env: &Environment<V>,
+		tracer: &T,
+	) -> Option<CallResult<V>>
+	where
+		T: Tracer<V>,
+	{
+		let arg_count = match func {
+			"map" | "filter" => 1,
+			"reduce" => 2,
+			_ => return None,
+		};
+		if args.len() != arg_count {
+			return None;
+		}
+		let name = args[0].clone().into_string().ok()?;
+		let f = functions.iter().find(|f| f.name == name)?;
+		let obj = obj.clone().into_object().ok()?;
+		let array = obj.cast::<Array<V>>()?;
+		let mut call = |args: &[&V]| f.run(object, functions, locals, args, env, tracer);
+		let new_array = |a| V::new_object(ScriptObject(Rc::new(a)));
+		Some(match func {
+			"map" => array.map(|e| call(&[e])).map(new_array),
+			"filter" => array.filter(|e| call(&[e])).map(new_array),
+			_ => array.reduce(args[1].clone(), |acc, e| call(&[acc, e])),
+		})
+	}
+
 	fn run_loop<T>(
 		&self,
 		object: &ScriptObject<V>,
@@ -241,10 +413,10 @@ where
 		tracer: &T,
 		vec_vars: &mut Vec<V>,
 		vars_offset: usize,
-		iterators: &mut Vec<Box<dyn Iterator<Item = V>>>,
+		iterators: &mut Vec<IterState<V>>,
 		iterators_int: &mut Vec<IterIntState>,
 		call_args: &mut [*const V; 16],
-	) -> Result<V, Box<dyn std::error::Error>>
+	) -> Result<V, Box<dyn Error>>
 	where
 		T: Tracer<V>,
 	{
@@ -252,284 +424,371 @@ where
 
 		let mut curr_instr = self.code.as_ptr();
 
-		let vars_len = self.var_count as usize + self.consts.len();
+		let vars_len = self.var_count as usize;
 		let vars_offset_len = vars_offset + vars_len;
 		// Adding vars_len speeds things up because idk
 		let mut vars = &mut vec_vars[vars_offset..vars_offset_len];
 
+		let truthy = env.truthy();
+
+		let mut pc;
+		let mut handlers = Vec::<TryState>::new();
 		let ret = loop {
-			unsafe {
-				let pc = curr_instr.offset_from(self.code.as_ptr()) as u32;
-				let instr = curr_instr.as_ref().unwrap_unchecked();
-				let _trace_instruction = TraceInstruction::new(tracer, self, pc, instr);
-				{
-					let mut vars = RunState { vars };
-					tracer.peek(self, &mut vars);
-				}
-				curr_instr = curr_instr.offset(1);
-				use Instruction::*;
-				match instr {
-					Call(
-						reg,
-						box CallArgs {
-							store_in,
-							func,
-							args,
-						},
-					) => {
-						// Set arguments
-						if unlikely(call_args.len() < args.len()) {
-							break Err(err::arg_oob());
-						}
-						for (i, a) in args.iter().enumerate() {
-							call_args[i] = reg!(ref vars a) as *const V;
+			let ret = loop {
+				unsafe {
+					pc = curr_instr.offset_from(self.code.as_ptr()) as u32;
+					let instr = curr_instr.as_ref().unwrap_unchecked();
+					let _trace_instruction = TraceInstruction::new(tracer, self, pc, instr);
+					{
+						let mut vars = RunState { vars };
+						tracer.peek(self, &mut vars);
+					}
+					curr_instr = curr_instr.offset(1);
+					use Instruction::*;
+					match instr {
+						Call(
+							reg,
+							box CallArgs {
+								store_in,
+								func,
+								args,
+								cache,
+							},
+						) => {
+							// Set arguments
+							if unlikely(call_args.len() < args.len()) {
+								break Err(err::arg_oob());
+							}
+							for (i, a) in args.iter().enumerate() {
+								call_args[i] = reg!(ref vars a) as *const V;
+							}
+							// SAFETY: All the pointers are valid references.
+							let ca: &[&V] = &*(&call_args[..args.len()] as *const _ as *const _);
+
+							// Perform call
+							let obj = reg!(ref vars reg);
+							let trace_call = TraceCall::new(tracer, self, func);
+							let method = obj
+								.as_object()
+								.and_then(|o| Some((o, self.resolve_method(*cache, o, func)?)));
+							let r = if let Some((o, method)) = method {
+								method(o, ca, env)
+							} else {
+								Self::call_array_callback(
+									obj, func, ca, object, functions, locals, env, tracer,
+								)
+								.unwrap_or_else(|| obj.call(func, ca, env))
+							};
+							let r = match r.or_else(|e| env.resolve_method(obj, func, ca, e)) {
+								Ok(r) => r,
+								Err(e) => break Err(e),
+							};
+							mem::drop(trace_call);
+
+							// Store return value
+							if let Some(reg) = store_in {
+								reg!(mut vars reg) = r;
+							}
 						}
-						// SAFETY: All the pointers are valid references.
-						let ca: &[&V] = &*(&call_args[..args.len()] as *const _ as *const _);
-
-						// Perform call
-						let obj = reg!(ref vars reg);
-						let trace_call = TraceCall::new(tracer, self, func);
-						let r = match obj.call(func, ca, env) {
-							Ok(r) => r,
-							Err(e) => break Err(e),
-						};
-						mem::drop(trace_call);
-
-						// Store return value
-						if let Some(reg) = store_in {
-							reg!(mut vars reg) = r;
+						CallEnv {
+							args:
+								box CallArgs {
+									store_in,
+									func,
+									args,
+									..
+								},
+						} => {
+							// Set arguments
+							if unlikely(call_args.len() < args.len()) {
+								break Err(err::arg_oob());
+							}
+							for (i, a) in args.iter().enumerate() {
+								call_args[i] = reg!(ref vars a) as *const V;
+							}
+							// SAFETY: All the pointers are valid references.
+							let ca: &[&V] = &*(&call_args[..args.len()] as *const _ as *const _);
+
+							// Perform call
+							let trace_call = TraceCall::new(tracer, self, func);
+							let r = match env.call(func, ca) {
+								Ok(r) => r,
+								Err(e) => break Err(e),
+							};
+							mem::drop(trace_call);
+
+							// Store return value
+							if let Some(reg) = store_in {
+								reg!(mut vars reg) = r;
+							}
 						}
-					}
-					CallEnv {
-						args: box CallArgs {
+						CallSelf {
 							store_in,
 							func,
+							arg_count,
 							args,
-						},
-					} => {
-						// Set arguments
-						if unlikely(call_args.len() < args.len()) {
-							break Err(err::arg_oob());
-						}
-						for (i, a) in args.iter().enumerate() {
-							call_args[i] = reg!(ref vars a) as *const V;
-						}
-						// SAFETY: All the pointers are valid references.
-						let ca: &[&V] = &*(&call_args[..args.len()] as *const _ as *const _);
-
-						// Perform call
-						let trace_call = TraceCall::new(tracer, self, func);
-						let r = match env.call(func, ca) {
-							Ok(r) => r,
-							Err(e) => break Err(e),
-						};
-						mem::drop(trace_call);
-
-						// Store return value
-						if let Some(reg) = store_in {
-							reg!(mut vars reg) = r;
-						}
-					}
-					CallSelf {
-						store_in,
-						func,
-						args,
-					} => {
-						// Perform call
-						let r = try_break!(box functions
+						} => {
+							// Perform call
+							let r = try_break!(box functions
 							.get(*func as usize)
 							.ok_or(err::UndefinedFunction));
 
-						drop(vars);
+							drop(vars);
 
-						// Resize variable stack
-						let cvl = vars_offset_len + r.var_count as usize;
-						let cvol = cvl + r.consts.len();
-						// TODO figure out if it's possible to get the compiler to (partially)
-						// inline the resize_with function.
-						if vec_vars.len() < cvol {
-							vec_vars.resize_with(cvol, V::default);
-						}
+							// Resize variable stack
+							let cvl = vars_offset_len + r.var_count as usize;
+							// TODO figure out if it's possible to get the compiler to (partially)
+							// inline the resize_with function.
+							if vec_vars.len() < cvl {
+								vec_vars.resize_with(cvl, V::default);
+							}
 
-						unchecked_assert!(
-							(r.param_count as usize) < args.len(),
-							"Too many parameters"
-						);
-						let arg_count = r.param_count as usize;
-						for (i, &a) in args.iter().enumerate() {
-							// Manual break is faster than `take()`
-							if i >= arg_count {
-								break;
+							let arg_count = *arg_count as usize;
+							if unlikely(!r.accepts_arg_count(arg_count)) {
+								break Err(err::arg_count());
+							}
+							let fixed = r.fixed_param_count();
+							for (i, &a) in args.iter().enumerate() {
+								// Manual break is faster than `take()`
+								if i >= arg_count.min(fixed) {
+									break;
+								}
+								let a = &(vars_offset + a as usize);
+								vec_vars[vars_offset_len + i] = reg!(ref vec_vars a).clone();
+							}
+							for (i, d) in r.missing_defaults(arg_count).iter().enumerate() {
+								vec_vars[vars_offset_len + arg_count + i] = d.clone();
+							}
+							if r.variadic {
+								let rest = args[..arg_count].get(fixed..).unwrap_or(&[]);
+								let rest = rest.iter().map(|&a| {
+									let a = &(vars_offset + a as usize);
+									reg!(ref vec_vars a).clone()
+								});
+								vec_vars[vars_offset_len + fixed] = Self::collect_rest(rest);
 							}
-							let a = &(vars_offset + a as usize);
-							vec_vars[vars_offset_len + i] = reg!(ref vec_vars a).clone();
-						}
-						for (i, c) in r.consts.iter().enumerate() {
-							vec_vars[cvl + i] = c.clone();
-						}
 
-						let trace_call = TraceSelfCall::new(tracer, self, *func);
-						let r = try_break!(r.run_loop(
-							object,
-							functions,
-							locals,
-							env,
-							tracer,
-							vec_vars,
-							vars_offset_len,
-							iterators,
-							iterators_int,
-							call_args,
-						));
-						mem::drop(trace_call);
-
-						vars = &mut vec_vars[vars_offset..vars_offset_len];
-
-						// Store return value
-						if let Some(reg) = store_in {
-							reg!(mut vars reg) = r;
+							let trace_call = TraceSelfCall::new(tracer, self, *func);
+							let r = try_break!(r.run_loop(
+								object,
+								functions,
+								locals,
+								env,
+								tracer,
+								vec_vars,
+								vars_offset_len,
+								iterators,
+								iterators_int,
+								call_args,
+							));
+							mem::drop(trace_call);
+
+							vars = &mut vec_vars[vars_offset..vars_offset_len];
+
+							// Store return value
+							if let Some(reg) = store_in {
+								reg!(mut vars reg) = r;
+							}
 						}
-					}
-					RetSome(reg) => break Ok(mem::take(reg!(ref mut vars reg))),
-					RetNone => break Ok(V::default()),
-					Iter(reg, iter, jmp_ip) => {
-						let iter = reg!(ref vars iter);
-						let mut iter = try_break!(iter.iter());
-						if let Some(e) = iter.next() {
-							reg!(mut vars reg) = e;
-							iterators.push(iter);
-						} else {
-							curr_instr = *jmp_ip;
+						RetSome(reg) => break Ok(mem::take(reg!(ref mut vars reg))),
+						RetNone => break Ok(V::default()),
+						Iter(reg, iter, jmp_ip) => {
+							let iter = reg!(ref vars iter);
+							let mut iter = match iter.as_object().and_then(|o| o.cast::<Array<V>>())
+							{
+								Some(array) => IterState::Array {
+									array: array.clone(),
+									index: 0,
+								},
+								None => IterState::Other(try_break!(iter.iter())),
+							};
+							if let Some(e) = iter.next() {
+								reg!(mut vars reg) = e;
+								iterators.push(iter);
+							} else {
+								curr_instr = *jmp_ip;
+							}
 						}
-					}
-					IterJmp(reg, jmp_ip) => {
-						let iter = try_break!(box iterators.last_mut().ok_or(err::NoIterator));
-						if let Some(e) = iter.next() {
-							reg!(mut vars reg) = e;
-							curr_instr = *jmp_ip;
-						} else {
-							let _ = iterators.pop().unwrap();
+						IterJmp(reg, jmp_ip) => {
+							let iter = try_break!(box iterators.last_mut().ok_or(err::NoIterator));
+							if let Some(e) = iter.next() {
+								reg!(mut vars reg) = e;
+								curr_instr = *jmp_ip;
+							} else {
+								let _ = iterators.pop().unwrap();
+							}
 						}
-					}
-					Break {
-						amount,
-						amount_int,
-						jmp_ip,
-					} => {
-						for _ in 0..*amount {
-							if unlikely(iterators.pop().is_none()) {
-								return Err(Box::new(err::NoIterator));
+						Break {
+							amount,
+							amount_int,
+							jmp_ip,
+						} => {
+							for _ in 0..*amount {
+								if unlikely(iterators.pop().is_none()) {
+									return Err(Box::new(err::NoIterator));
+								}
+							}
+							for _ in 0..*amount_int {
+								if unlikely(iterators_int.pop().is_none()) {
+									return Err(Box::new(err::NoIterator));
+								}
 							}
+							curr_instr = *jmp_ip;
 						}
-						for _ in 0..*amount_int {
-							if unlikely(iterators_int.pop().is_none()) {
-								return Err(Box::new(err::NoIterator));
+						IterInt {
+							reg,
+							from,
+							to,
+							jmp_ip,
+						} => {
+							let step = &(*from + 1);
+							let from = reg!(ref vars from);
+							let to = reg!(ref vars to);
+							let step = reg!(ref vars step);
+							let from = from.as_integer().ok();
+							let to = to.as_integer().ok();
+							let step = step.as_integer().ok();
+							let fts = from.and_then(|f| to.and_then(|t| step.map(|s| (f, t, s))));
+							let (from, to, step) = try_break!(box fts.ok_or(err::IncompatibleType));
+							// A zero step would loop forever, which is almost certainly a mistake
+							if step == 0 {
+								break Err(Box::new(err::ZeroStep));
+							}
+							if from != to {
+								reg!(mut vars reg) = V::new_integer(from);
+								iterators_int.push(IterIntState {
+									current: from,
+									stop: to,
+									step,
+								});
+							} else {
+								curr_instr = *jmp_ip;
 							}
 						}
-						curr_instr = *jmp_ip;
-					}
-					IterInt {
-						reg,
-						from,
-						to,
-						step,
-						jmp_ip,
-					} => {
-						let from = reg!(ref vars from);
-						let to = reg!(ref vars to);
-						let step = reg!(ref vars step);
-						let from = from.as_integer().ok();
-						let to = to.as_integer().ok();
-						let step = step.as_integer().ok();
-						let fts = from.and_then(|f| to.and_then(|t| step.map(|s| (f, t, s))));
-						let (from, to, step) = try_break!(box fts.ok_or(err::IncompatibleType));
-						if from != to {
-							reg!(mut vars reg) = V::new_integer(from);
-							iterators_int.push(IterIntState {
-								current: from,
-								stop: to,
-								step,
+						IterIntJmp(reg, jmp_ip) => {
+							let iter =
+								try_break!(box iterators_int.last_mut().ok_or(err::NoIterator));
+							// The step is never zero as IterInt checks for it
+							let next = iter.current.checked_add(iter.step).filter(|&n| {
+								(iter.step > 0 && n < iter.stop) || (iter.step < 0 && n > iter.stop)
 							});
-						} else {
-							curr_instr = *jmp_ip;
+							if let Some(next) = next {
+								iter.current = next;
+								reg!(mut vars reg) = V::new_integer(next);
+								curr_instr = *jmp_ip;
+							} else {
+								let _ = iterators_int.pop().unwrap();
+							}
 						}
-					}
-					IterIntJmp(reg, jmp_ip) => {
-						let iter = try_break!(box iterators_int.last_mut().ok_or(err::NoIterator));
-						iter.current += iter.step;
-						// >= is preferred over > so that step == 0 loops forever
-						if (iter.step >= 0 && iter.current < iter.stop)
-							|| (iter.step < 0 && iter.current > iter.stop)
-						{
-							reg!(mut vars reg) = V::new_integer(iter.current);
-							curr_instr = *jmp_ip;
-						} else {
-							let _ = iterators_int.pop().unwrap();
+						JmpIf(reg, jmp_ip) => {
+							if let Some(b) = condition(reg!(ref vars reg), truthy) {
+								if !b {
+									curr_instr = *jmp_ip;
+								}
+							} else {
+								break Err(Box::new(err::NotBoolean));
+							}
 						}
-					}
-					JmpIf(reg, jmp_ip) => {
-						if let Ok(b) = reg!(ref vars reg).as_bool() {
-							if !b {
-								curr_instr = *jmp_ip;
+						JmpNotIf(reg, jmp_ip) => {
+							if let Some(b) = condition(reg!(ref vars reg), truthy) {
+								if b {
+									curr_instr = *jmp_ip;
+								}
+							} else {
+								break Err(Box::new(err::NotBoolean));
 							}
-						} else {
-							break Err(Box::new(err::NotBoolean));
 						}
-					}
-					JmpNotIf(reg, jmp_ip) => {
-						if let Ok(b) = reg!(ref vars reg).as_bool() {
-							if b {
+						JmpNotNone(reg, jmp_ip) => {
+							if !reg!(ref vars reg).is_none() {
 								curr_instr = *jmp_ip;
 							}
-						} else {
-							break Err(Box::new(err::NotBoolean));
 						}
-					}
-					Jmp(jmp_ip) => curr_instr = *jmp_ip,
-					Add(r, a, b) => run_op!(vars, r = a add b),
-					Sub(r, a, b) => run_op!(vars, r = a sub b),
-					Mul(r, a, b) => run_op!(vars, r = a mul b),
-					Div(r, a, b) => run_op!(vars, r = a div b),
-					Rem(r, a, b) => run_op!(vars, r = a rem b),
-					And(r, a, b) => run_op!(vars, r = a bitand b),
-					Or(r, a, b) => run_op!(vars, r = a bitor b),
-					Xor(r, a, b) => run_op!(vars, r = a bitxor b),
-					Shl(r, a, b) => run_op!(vars, r = a lhs b),
-					Shr(r, a, b) => run_op!(vars, r = a rhs b),
-					LessEq(r, a, b) => run_cmp!(vars, r = a <= b),
-					Less(r, a, b) => run_cmp!(vars, r = a < b),
-					Neq(r, a, b) => run_cmp!(vars, r = a != b),
-					Eq(r, a, b) => run_cmp!(vars, r = a == b),
-					Neg(r, a) => run_op!(vars, r = a neg),
-					Not(r, a) => run_op!(vars, r = a not),
-					Store(r, l) => {
-						let l = try_break!(locals.get_mut(*l as usize).ok_or_else(err::loob));
-						*l = reg!(ref vars r).clone();
-					}
-					Load(r, l) => {
-						let v = try_break!(locals.get(*l as usize).ok_or_else(err::loob));
-						reg!(mut vars r) = v.clone();
-					}
-					Move(d, s) => reg!(mut vars d) = reg!(ref vars s).clone(),
-					CopySelf { dest } => reg!(mut vars dest) = V::new_object(object.clone()),
-					NewArray(r, c) => {
-						reg!(mut vars r) = V::new_object(ScriptObject(Rc::new(Array::with_len(*c))))
-					}
-					NewDictionary(r, c) => {
-						let d = Rc::new(Dictionary::with_capacity(*c));
-						reg!(mut vars r) = V::new_object(ScriptObject(d));
-					}
-					// FIXME using try_break for these last two statements makes everything slower,
-					// presumably because the damn optimizer thinks inserting jmp instructions
-					// everywhere is always a good idea if it means a few bytes less of code.
-					GetIndex(r, o, i) => {
-						reg!(mut vars r) = reg!(ref vars o).index(reg!(ref vars i))?
-					}
-					SetIndex(r, o, i) => {
-						reg!(ref vars o).set_index(reg!(ref vars i), reg!(ref vars r).clone())?
+						Switch { reg, len, min } => {
+							let len = *len as usize;
+							let offset = match reg!(ref vars reg).as_integer() {
+								Ok(i) => i
+									.checked_sub(*min)
+									.and_then(|i| usize::try_from(i).ok())
+									.filter(|&i| i < len)
+									.unwrap_or(len),
+								Err(_) => len + 1,
+							};
+							curr_instr = curr_instr.add(offset);
+						}
+						Jmp(jmp_ip) => curr_instr = *jmp_ip,
+						Add(r, a, b) => run_op!(vars, r = a add b),
+						Sub(r, a, b) => run_op!(vars, r = a sub b),
+						Mul(r, a, b) => run_op!(vars, r = a mul b),
+						Div(r, a, b) => run_op!(vars, r = a div b),
+						Rem(r, a, b) => run_op!(vars, r = a rem b),
+						And(r, a, b) => run_op!(vars, r = a bitand b),
+						Or(r, a, b) => run_op!(vars, r = a bitor b),
+						Xor(r, a, b) => run_op!(vars, r = a bitxor b),
+						Shl(r, a, b) => run_op!(vars, r = a lhs b),
+						Shr(r, a, b) => run_op!(vars, r = a rhs b),
+						Ushr(r, a, b) => run_op!(vars, r = a rhs_unsigned b),
+						LessEq(r, a, b) => run_cmp!(vars, r = a <= b),
+						Less(r, a, b) => run_cmp!(vars, r = a < b),
+						Neq(r, a, b) => run_cmp!(vars, r = a != b),
+						Eq(r, a, b) => run_cmp!(vars, r = a == b),
+						Neg(r, a) => run_op!(vars, r = a neg),
+						Not(r, a) => run_op!(vars, r = a not),
+						Str(r, a) => {
+							reg!(mut vars r) = V::new_string(reg!(ref vars a).to_string().into())
+						}
+						Store(r, l) => {
+							let l = try_break!(locals.get_mut(*l as usize).ok_or_else(err::loob));
+							*l = reg!(ref vars r).clone();
+						}
+						Load(r, l) => {
+							let v = try_break!(locals.get(*l as usize).ok_or_else(err::loob));
+							reg!(mut vars r) = v.clone();
+						}
+						Move(d, s) => reg!(mut vars d) = reg!(ref vars s).clone(),
+						LoadConst(d, c) => {
+							let c = *c as usize;
+							unchecked_assert!(c < self.consts.len());
+							reg!(mut vars d) = self.consts.get_unchecked(c).clone();
+						}
+						LoadEnv(r, name) => reg!(mut vars r) = try_break!(env.constant(name)),
+						CopySelf { dest } => reg!(mut vars dest) = V::new_object(object.clone()),
+						NewArray(r, c) => {
+							reg!(mut vars r) =
+								V::new_object(ScriptObject(Rc::new(Array::with_len(*c))))
+						}
+						NewDictionary(r, c) => {
+							let d = Rc::new(Dictionary::with_capacity(*c));
+							reg!(mut vars r) = V::new_object(ScriptObject(d));
+						}
+						GetIndex(r, o, i) => {
+							reg!(mut vars r) = try_break!(reg!(ref vars o).index(reg!(ref vars i)))
+						}
+						SetIndex(r, o, i) => {
+							try_break!(reg!(ref vars o)
+								.set_index(reg!(ref vars i), reg!(ref vars r).clone()))
+						}
+						Try(reg, catch_ip) => handlers.push(TryState {
+							reg: *reg,
+							catch_ip: *catch_ip,
+							iterators: iterators.len(),
+							iterators_int: iterators_int.len(),
+						}),
+						EndTry => {
+							let _ = handlers.pop();
+						}
 					}
 				}
+			};
+			// Resume at the innermost protected region if there is one.
+			match (ret, handlers.pop()) {
+				(Err(_), Some(h)) => {
+					iterators.truncate(h.iterators);
+					iterators_int.truncate(h.iterators_int);
+					vars = &mut vec_vars[vars_offset..vars_offset_len];
+					vars[usize::from(h.reg)] = V::default();
+					curr_instr = h.catch_ip;
+				}
+				(ret, _) => break ret,
 			}
 		};
 
@@ -543,12 +802,168 @@ where
 			tracer.error(self, &mut state, err.as_ref());
 		}
 
-		ret
+		ret.map_err(|e| err::at(self.name.clone(), pc, e))
 	}
 
 	pub fn name(&self) -> &Rc<str> {
 		&self.name
 	}
+
+	/// The amount of arguments the function can be called with.
+	pub fn arity(&self) -> Arity {
+		let fixed = self.fixed_param_count();
+		Arity::of_script_function(fixed, self.defaults.len(), self.variadic)
+	}
+
+	/// Returns the name of each variable and parameter along with the register it is stored
+	/// in, which is an index in [`RunState::variables`]. Variables in separate blocks may
+	/// share a register, in which case each of them is listed.
+	#[cfg(feature = "debug-info")]
+	pub fn variable_names(&self) -> &[(u16, Rc<str>)] {
+		&self.var_names
+	}
+
+	/// Returns a listing of the instructions in which jump targets are shown as labels. Each
+	/// instruction is preceded by its index, which is also the `ip` passed to [`Tracer`]s and
+	/// stored in [`RunError::At`].
+	pub fn disassemble(&self) -> String {
+		use core::fmt::Write;
+
+		let mut labels = self
+			.code
+			.iter()
+			.filter_map(Instruction::jump_target)
+			.map(|j| instruction_index(&self.code, j))
+			.collect::<Vec<_>>();
+		labels.sort_unstable();
+		labels.dedup();
+
+		let mut s = String::new();
+		let _ = writeln!(s, "{}:", self.name);
+		let _ = writeln!(
+			s,
+			"  parameters {}, registers {}",
+			self.param_count, self.var_count
+		);
+		for (i, c) in self.consts.iter().enumerate() {
+			let _ = writeln!(s, "  const {} = {:?}", i, c);
+		}
+		for (i, instruction) in self.code.iter().enumerate() {
+			let label = match labels.binary_search(&i) {
+				Ok(l) => format!("L{}:", l),
+				Err(_) => String::new(),
+			};
+			let instruction = Labeled {
+				instruction,
+				code: &self.code,
+				labels: Some(&labels),
+			};
+			let _ = writeln!(s, "{:>4}  {:<6}{}", i, label, instruction);
+		}
+		if labels.last() == Some(&self.code.len()) {
+			let _ = writeln!(s, "{:>4}  L{}:", self.code.len(), labels.len() - 1);
+		}
+		s
+	}
+}
+
+impl RunError {
+	/// The name of the function in which the error occured, if known.
+	pub fn function(&self) -> Option<&Rc<str>> {
+		match self {
+			RunError::At { function, .. } => Some(function),
+			_ => None,
+		}
+	}
+
+	/// The index of the instruction that failed, if known.
+	pub fn ip(&self) -> Option<u32> {
+		match self {
+			RunError::At { ip, .. } => Some(*ip),
+			_ => None,
+		}
+	}
+
+	/// The error that caused the instruction to fail. This is the error itself if it isn't
+	/// wrapped with a location.
+	pub fn error(&self) -> &(dyn Error + 'static) {
+		match self {
+			RunError::At { error, .. } => error.as_ref(),
+			_ => self,
+		}
+	}
+
+	/// Returns the error that caused this one, skipping the location of every function it
+	/// passed through.
+	pub fn cause(&self) -> &(dyn Error + 'static) {
+		let mut error: &(dyn Error + 'static) = self;
+		while let Some(RunError::At { error: e, .. }) = error.downcast_ref() {
+			error = e.as_ref();
+		}
+		error
+	}
+
+	/// Returns the `RunError` that caused `error`, skipping the location of every function it
+	/// passed through. This allows matching on the errors returned by calls directly, e.g.
+	/// `matches!(RunError::cause_of(&*e), Some(RunError::DivideByZero))`. Returns `None` if
+	/// `error` was caused by anything else, such as a [`CallError`](crate::CallError).
+	pub fn cause_of<'a>(error: &'a (dyn Error + 'static)) -> Option<&'a Self> {
+		error.downcast_ref::<Self>()?.cause().downcast_ref()
+	}
+}
+
+impl Error for RunError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			RunError::At { error, .. } => Some(error.as_ref()),
+			_ => None,
+		}
+	}
+}
+
+/// Only the underlying error of `At` is shown so the message stays the same no matter how deep
+/// it occured. The alternate form (`{:#}`) follows it with the location in every function it
+/// passed through, starting with the outermost function.
+impl fmt::Display for RunError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self {
+			RunError::DivideByZero => f.write_str("Division by zero"),
+			RunError::Overflow => f.write_str("Integer overflow"),
+			RunError::At { .. } => {
+				fmt::Display::fmt(self.cause(), f)?;
+				if f.alternate() {
+					let mut error: &(dyn Error + 'static) = self;
+					while let Some(RunError::At {
+						function,
+						ip,
+						error: e,
+					}) = error.downcast_ref()
+					{
+						write!(f, "\n  in {} at instruction {}", function, ip)?;
+						error = e.as_ref();
+					}
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+impl<V> IterState<V>
+where
+	V: VariantType,
+{
+	#[inline]
+	fn next(&mut self) -> Option<V> {
+		match self {
+			Self::Array { array, index } => {
+				let e = array.get(*index);
+				*index += 1;
+				e
+			}
+			Self::Other(iter) => iter.next(),
+		}
+	}
 }
 
 impl<V> RunState<'_, V>
@@ -560,25 +975,90 @@ where
 	}
 }
 
-/// This returns each instruction on oneline instead of 5+ with the default Debug
-impl Debug for Instruction {
+/// The destination of a jump, shown as either the index of an instruction or a label.
+enum Target {
+	Index(isize),
+	Label(usize),
+}
+
+impl Debug for Target {
 	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-		let jp = f.precision();
-		let jp = |j: &*const _| {
-			if let Some(jp) = jp {
-				((*j as usize - jp) / mem::size_of::<Instruction>()) as isize
-			} else {
-				-1
+		match self {
+			Target::Index(i) => write!(f, "{}", i),
+			Target::Label(l) => write!(f, "L{}", l),
+		}
+	}
+}
+
+/// An instruction with its jump target shown as a label, or as an index if there are no
+/// labels.
+struct Labeled<'a> {
+	instruction: &'a Instruction,
+	code: &'a [Instruction],
+	labels: Option<&'a [usize]>,
+}
+
+impl fmt::Display for Labeled<'_> {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		self.instruction.fmt_with(f, |j| {
+			let i = instruction_index(self.code, *j);
+			match self.labels {
+				Some(l) => Target::Label(l.binary_search(&i).unwrap_or(usize::MAX)),
+				None => Target::Index(i as isize),
 			}
-		};
+		})
+	}
+}
+
+/// Returns the value of a condition, or `None` if it isn't a boolean and truthy values aren't
+/// accepted.
+#[inline(always)]
+fn condition<V: VariantType>(value: &V, truthy: bool) -> Option<bool> {
+	if truthy {
+		Some(value.as_bool_truthy())
+	} else {
+		value.as_bool().ok()
+	}
+}
+
+fn instruction_index(code: &[Instruction], instruction: *const Instruction) -> usize {
+	(instruction as usize - code.as_ptr() as usize) / mem::size_of::<Instruction>()
+}
+
+impl Instruction {
+	/// Returns the instruction this instruction may jump to, if any.
+	fn jump_target(&self) -> Option<*const Instruction> {
+		use Instruction::*;
+		match self {
+			Jmp(p)
+			| JmpIf(_, p)
+			| JmpNotIf(_, p)
+			| JmpNotNone(_, p)
+			| Iter(_, _, p)
+			| IterJmp(_, p)
+			| IterIntJmp(_, p)
+			| IterInt { jmp_ip: p, .. }
+			| Break { jmp_ip: p, .. }
+			| Try(_, p) => Some(*p),
+			_ => None,
+		}
+	}
+
+	fn fmt_with(
+		&self,
+		f: &mut Formatter,
+		jp: impl Fn(&*const Instruction) -> Target,
+	) -> fmt::Result {
 		use Instruction::*;
 		match self {
 			Call(r, a) => write!(f, "call    {}, {:?}", r, a),
 			CallSelf {
 				store_in,
 				func,
+				arg_count,
 				args,
 			} => {
+				let args = &args[..*arg_count as usize];
 				if let Some(store_in) = store_in {
 					write!(f, "calls   {}, {}, {:?}", store_in, func, args)
 				} else {
@@ -588,6 +1068,8 @@ impl Debug for Instruction {
 			CallEnv { args } => write!(f, "calle   {:?}", args),
 			RetSome(reg) => write!(f, "ret     {}", reg),
 			RetNone => write!(f, "ret     none"),
+			Try(r, p) => write!(f, "try     {}, {:?}", r, jp(p)),
+			EndTry => write!(f, "endtry"),
 
 			Iter(r, i, p) => write!(f, "iter    {}, {}, {:?}", r, i, jp(p)),
 			IterJmp(r, p) => write!(f, "iterjp  {}, {:?}", r, jp(p)),
@@ -595,17 +1077,8 @@ impl Debug for Instruction {
 				reg,
 				from,
 				to,
-				step,
 				jmp_ip,
-			} => write!(
-				f,
-				"iteri   {}, {}, {}, {}, {:?}",
-				reg,
-				from,
-				to,
-				step,
-				jp(jmp_ip)
-			),
+			} => write!(f, "iteri   {}, {}, {}, {:?}", reg, from, to, jp(jmp_ip)),
 			IterIntJmp(r, p) => write!(f, "iterijp {}, {:?}", r, jp(p)),
 			Break {
 				amount,
@@ -615,7 +1088,9 @@ impl Debug for Instruction {
 
 			JmpIf(r, p) => write!(f, "jpif    {}, {:?}", r, jp(p)),
 			JmpNotIf(r, p) => write!(f, "jpnif   {}, {:?}", r, jp(p)),
-			Jmp(p) => write!(f, "jp      {:?}", p),
+			JmpNotNone(r, p) => write!(f, "jpnnone {}, {:?}", r, jp(p)),
+			Jmp(p) => write!(f, "jp      {:?}", jp(p)),
+			Switch { reg, len, min } => write!(f, "switch  {}, {}, {}", reg, min, len),
 
 			Add(r, a, b) => write!(f, "add     {}, {}, {}", r, a, b),
 			Sub(r, a, b) => write!(f, "sub     {}, {}, {}", r, a, b),
@@ -627,8 +1102,10 @@ impl Debug for Instruction {
 			Xor(r, a, b) => write!(f, "xor     {}, {}, {}", r, a, b),
 			Shl(r, a, b) => write!(f, "shl     {}, {}, {}", r, a, b),
 			Shr(r, a, b) => write!(f, "shr     {}, {}, {}", r, a, b),
+			Ushr(r, a, b) => write!(f, "ushr    {}, {}, {}", r, a, b),
 			Neg(r, a) => write!(f, "neg     {}, {}", r, a),
 			Not(r, a) => write!(f, "not     {}, {}", r, a),
+			Str(r, a) => write!(f, "str     {}, {}", r, a),
 
 			Eq(r, a, b) => write!(f, "eq      {}, {}, {}", r, a, b),
 			Neq(r, a, b) => write!(f, "neq     {}, {}, {}", r, a, b),
@@ -638,6 +1115,8 @@ impl Debug for Instruction {
 			Store(r, a) => write!(f, "store   {}, {}", r, a),
 			Load(r, a) => write!(f, "load    {}, {}", r, a),
 			Move(a, b) => write!(f, "move    {}, {}", a, b),
+			LoadConst(a, b) => write!(f, "loadc   {}, {}", a, b),
+			LoadEnv(a, b) => write!(f, "loade   {}, {}", a, b),
 			CopySelf { dest } => write!(f, "cpyself {}", dest),
 
 			NewArray(r, c) => write!(f, "newarr  {}, {}", r, c),
@@ -648,6 +1127,15 @@ impl Debug for Instruction {
 	}
 }
 
+/// This returns each instruction on oneline instead of 5+ with the default Debug
+/// Jump targets are shown as `-1` as the instruction doesn't know where the code starts.
+/// [`ByteCode::disassemble`] shows them instead.
+impl Debug for Instruction {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		self.fmt_with(f, |_| Target::Index(-1))
+	}
+}
+
 impl fmt::Debug for CallArgs {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		if let Some(n) = self.store_in {
@@ -673,20 +1161,26 @@ where
 		br(f)?;
 		f.write_str("consts:")?;
 		for (i, c) in self.consts.iter().enumerate() {
-			let i = i as u8 + self.var_count;
 			if f.alternate() {
 				write!(f, "\n    {:>3}: {:?}", i, c)?;
 			} else {
-				write!(f, " {}: {:?},", i, c)?;
+				let sep = if i > 0 { "," } else { "" };
+				write!(f, "{} {}: {:?}", sep, i, c)?;
 			}
 		}
 		br(f)?;
 		f.write_str("code:")?;
 		for (i, c) in self.code.iter().enumerate() {
+			let c = Labeled {
+				instruction: c,
+				code: &self.code,
+				labels: None,
+			};
 			if f.alternate() {
-				write!(f, "\n    {:>3}: {:.*?}", i, self.code.as_ptr() as usize, c)?;
+				write!(f, "\n    {:>3}: {}", i, c)?;
 			} else {
-				write!(f, "{}: {:.*?}", i, self.code.as_ptr() as usize, c)?;
+				let sep = if i > 0 { "," } else { "" };
+				write!(f, "{} {}: {}", sep, i, c)?;
 			}
 		}
 		Ok(())
@@ -694,8 +1188,10 @@ where
 }
 
 pub(super) mod err {
+	use super::RunError;
+	use crate::std_types::*;
+	use crate::CallError;
 	use core::fmt;
-	use std::error::Error;
 
 	macro_rules! err {
 		($name:ident, $msg:literal) => {
@@ -728,6 +1224,7 @@ pub(super) mod err {
 	err!(ArgumentOutOfBounds, "Argument out of bounds");
 	err!(IncompatibleType, "Type is not compatible");
 	err!(NotBoolean, "Type is not boolean");
+	err!(ZeroStep, "Step of integer loop is zero");
 
 	#[inline(never)]
 	#[cold]
@@ -746,4 +1243,25 @@ pub(super) mod err {
 	pub fn loob() -> E {
 		Box::new(LocalOutOfBounds)
 	}
+
+	#[inline(never)]
+	#[cold]
+	pub fn at(function: Rc<str>, ip: u32, error: E) -> E {
+		Box::new(RunError::At {
+			function,
+			ip,
+			error,
+		})
+	}
+
+	/// Converts arithmetic faults of an operation to their own `RunError`.
+	#[inline(never)]
+	#[cold]
+	pub fn op(error: E) -> E {
+		match error.downcast_ref() {
+			Some(CallError::DivideByZero) => Box::new(RunError::DivideByZero),
+			Some(CallError::Overflow) => Box::new(RunError::Overflow),
+			_ => error,
+		}
+	}
 }
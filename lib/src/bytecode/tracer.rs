@@ -3,83 +3,60 @@
 // This file is licensed under the MIT license. See LICENSE for details.
 
 use super::{ByteCode, Instruction, RunState};
+use crate::std_types::Error;
 use crate::std_types::Rc;
 use crate::VariantType;
-use std::error::Error;
 
 /// This trait is used to inspect the execution of bytecode. It can trace every
-/// instruction that is being executed & follow calls.
+/// instruction that is being executed & follow calls. All methods do nothing by default.
 pub trait Tracer<V>: Clone + 'static
 where
 	V: VariantType,
 {
 	/// Called before the execution of a single instruction
-	fn instruction_pre(&self, bytecode: &ByteCode<V>, ip: u32, instruction: &Instruction);
+	#[inline(always)]
+	fn instruction_pre(&self, _bytecode: &ByteCode<V>, _ip: u32, _instruction: &Instruction) {}
 
 	/// Called after the execution of a single instruction
-	fn instruction_post(&self, bytecode: &ByteCode<V>, ip: u32, instruction: &Instruction);
+	#[inline(always)]
+	fn instruction_post(&self, _bytecode: &ByteCode<V>, _ip: u32, _instruction: &Instruction) {}
 
 	/// Called right before a function call
-	fn call_pre(&self, bytecode: &ByteCode<V>, function: &Rc<str>);
+	#[inline(always)]
+	fn call_pre(&self, _bytecode: &ByteCode<V>, _function: &Rc<str>) {}
 
 	/// Called right after a function call
-	fn call_post(&self, bytecode: &ByteCode<V>, function: &Rc<str>);
+	#[inline(always)]
+	fn call_post(&self, _bytecode: &ByteCode<V>, _function: &Rc<str>) {}
 
 	/// Called right before a function call
-	fn call_self_pre(&self, bytecode: &ByteCode<V>, function: u8);
+	#[inline(always)]
+	fn call_self_pre(&self, _bytecode: &ByteCode<V>, _function: u8) {}
 
 	/// Called right after a function call
-	fn call_self_post(&self, bytecode: &ByteCode<V>, function: u8);
+	#[inline(always)]
+	fn call_self_post(&self, _bytecode: &ByteCode<V>, _function: u8) {}
 
 	/// Called at the start of a bytecode run
-	fn run_pre(&self, bytecode: &ByteCode<V>);
+	#[inline(always)]
+	fn run_pre(&self, _bytecode: &ByteCode<V>) {}
 
 	/// Called at the end of a bytecode run
-	fn run_post(&self, bytecode: &ByteCode<V>);
+	#[inline(always)]
+	fn run_post(&self, _bytecode: &ByteCode<V>) {}
 
 	/// Called every iteration to allow reading and modifying the [`RunState`](super::RunState)
 	/// It is called right after instruction_pre but before the instruction is actually called.
-	fn peek(&self, bytecode: &ByteCode<V>, state: &mut RunState<V>);
+	#[inline(always)]
+	fn peek(&self, _bytecode: &ByteCode<V>, _state: &mut RunState<V>) {}
 
 	/// Called whenever an error occurs in the inner loop
-	fn error(&self, bytecode: &ByteCode<V>, state: &mut RunState<V>, error: &dyn Error);
+	#[inline(always)]
+	fn error(&self, _bytecode: &ByteCode<V>, _state: &mut RunState<V>, _error: &dyn Error) {}
 }
 
 /// Default implementation that does nothing and thus has no performance impact.
-impl<V> Tracer<V> for ()
-where
-	V: VariantType,
-{
-	#[inline(always)]
-	fn instruction_pre(&self, _: &ByteCode<V>, _: u32, _: &Instruction) {}
-
-	#[inline(always)]
-	fn instruction_post(&self, _: &ByteCode<V>, _: u32, _: &Instruction) {}
-
-	#[inline(always)]
-	fn call_pre(&self, _: &ByteCode<V>, _: &Rc<str>) {}
-
-	#[inline(always)]
-	fn call_post(&self, _: &ByteCode<V>, _: &Rc<str>) {}
-
-	#[inline(always)]
-	fn call_self_pre(&self, _: &ByteCode<V>, _: u8) {}
-
-	#[inline(always)]
-	fn call_self_post(&self, _: &ByteCode<V>, _: u8) {}
-
-	#[inline(always)]
-	fn run_pre(&self, _: &ByteCode<V>) {}
-
-	#[inline(always)]
-	fn run_post(&self, _: &ByteCode<V>) {}
-
-	#[inline(always)]
-	fn peek(&self, _: &ByteCode<V>, _: &mut RunState<V>) {}
-
-	#[inline(always)]
-	fn error(&self, _: &ByteCode<V>, _: &mut RunState<V>, _: &dyn Error) {}
-}
+impl<V> Tracer<V> for () where V: VariantType {}
 
 /// Starts tracing a run and automatically calls `run_pos` when it is dropped.
 /// Useful in conjuction with the `?` syntax and panics.
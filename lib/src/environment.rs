@@ -2,22 +2,58 @@
 //
 // This file is licensed under the MIT license. See script/LICENSE for details.
 
-use crate::std_types::{hash_map::Entry, FxHashMap};
-use crate::{CallError, CallResult, Rc, VariantType};
+#[cfg(not(feature = "std"))]
+use crate::std_types::*;
+use crate::std_types::{hash_map::Entry, Error, FxHashMap};
+use crate::{CallError, CallResult, Range, Rc, ScriptObject, ScriptType, VariantType};
+use core::cell::RefCell;
+use core::cmp::Ordering;
 
 #[derive(Default)]
 pub struct Environment<V>
 where
 	V: VariantType,
 {
-	functions: FxHashMap<Rc<str>, EnvironmentFunction<V>>,
+	functions: FxHashMap<Rc<str>, (EnvironmentFunction<V>, Arity)>,
+	constants: FxHashMap<Rc<str>, V>,
+	method_resolver: Option<MethodResolver<V>>,
+	truthy: bool,
 }
 
 pub type EnvironmentFunction<V> = Box<dyn Fn(&[&V]) -> CallResult<V>>;
 
+pub type MethodResolver<V> = Box<dyn Fn(&V, &str, &[&V]) -> Option<CallResult<V>>>;
+
+/// The amount of arguments a global function accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+	/// Any amount of arguments. The function checks the amount itself.
+	Any,
+	/// Exactly the given amount of arguments.
+	Exact(usize),
+	/// At least the first and at most the second amount of arguments.
+	Range(usize, usize),
+}
+
+/// Functions that are available in every environment unless a function with the same name
+/// is added.
+const BUILTINS: &[(&str, Arity)] = &[
+	("range", Arity::Range(1, 3)),
+	("assert", Arity::Range(1, 2)),
+	("assert_eq", Arity::Exact(2)),
+	("abs", Arity::Exact(1)),
+	("sign", Arity::Exact(1)),
+	("clamp", Arity::Exact(3)),
+	("format", Arity::Any),
+	("sum", Arity::Exact(1)),
+	("min", Arity::Exact(1)),
+	("max", Arity::Exact(1)),
+];
+
 #[derive(Debug)]
 pub enum EnvironmentError {
 	FunctionAlreadyExists,
+	ConstantAlreadyExists,
 }
 
 impl<V> Environment<V>
@@ -27,26 +63,888 @@ where
 	pub fn new() -> Self {
 		Self {
 			functions: FxHashMap::with_hasher(Default::default()),
+			constants: FxHashMap::with_hasher(Default::default()),
+			method_resolver: None,
+			truthy: false,
 		}
 	}
 
-	pub fn add_function(
+	/// Sets whether conditions of `if` and `while` accept any value, in which case `none`,
+	/// `0`, `0.0` and `""` are false and anything else is true. By default conditions must
+	/// be booleans.
+	pub fn set_truthy(&mut self, truthy: bool) {
+		self.truthy = truthy;
+	}
+
+	/// Whether conditions accept any value. See [`Self::set_truthy`].
+	pub fn truthy(&self) -> bool {
+		self.truthy
+	}
+
+	/// Registers a closure as a global function that can be called with `env.<name>()`.
+	/// Argument and type checking is up to the closure, which should return a
+	/// [`CallError`] if the arguments are not suitable.
+	pub fn add_function<F>(&mut self, name: String, f: F) -> Result<(), EnvironmentError>
+	where
+		F: Fn(&[&V]) -> CallResult<V> + 'static,
+	{
+		self.add_function_with_arity(name, Arity::Any, f)
+	}
+
+	/// Registers a closure as a global function that only accepts the given amount of
+	/// arguments. Calls with any other amount fail with [`CallError::BadArgumentCount`]
+	/// without invoking the closure.
+	pub fn add_function_with_arity<F>(
 		&mut self,
 		name: String,
-		f: EnvironmentFunction<V>,
-	) -> Result<(), EnvironmentError> {
+		arity: Arity,
+		f: F,
+	) -> Result<(), EnvironmentError>
+	where
+		F: Fn(&[&V]) -> CallResult<V> + 'static,
+	{
+		if self.constants.contains_key(&*name) {
+			return Err(EnvironmentError::ConstantAlreadyExists);
+		}
 		match self.functions.entry(name.into()) {
 			Entry::Vacant(e) => {
-				e.insert(f);
+				e.insert((Box::new(f), arity));
 				Ok(())
 			}
 			Entry::Occupied(_) => Err(EnvironmentError::FunctionAlreadyExists),
 		}
 	}
 
+	/// Registers a value that can be read with `env.<name>`, e.g. a mathematical constant or
+	/// a configuration option. Constants share their names with functions.
+	pub fn add_constant(&mut self, name: String, value: V) -> Result<(), EnvironmentError> {
+		if self.functions.contains_key(&*name) {
+			return Err(EnvironmentError::FunctionAlreadyExists);
+		}
+		match self.constants.entry(name.into()) {
+			Entry::Vacant(e) => {
+				e.insert(value);
+				Ok(())
+			}
+			Entry::Occupied(_) => Err(EnvironmentError::ConstantAlreadyExists),
+		}
+	}
+
+	/// Returns the value of the constant with the given name.
+	pub fn constant(&self, name: &str) -> CallResult<V> {
+		if let Some(v) = self.constants.get(name) {
+			Ok(v.clone())
+		} else if self.functions.contains_key(name) || BUILTINS.iter().any(|(n, _)| *n == name) {
+			Err(CallError::not_a_constant(name))
+		} else {
+			Err(CallError::undefined_constant(name))
+		}
+	}
+
+	/// Registers a closure that creates a host object as a global function, such that
+	/// `env.<name>(...)` returns the object to the script. Calls with any other amount of
+	/// arguments than given fail without invoking the closure.
+	pub fn add_constructor<T, F>(
+		&mut self,
+		name: String,
+		arity: Arity,
+		f: F,
+	) -> Result<(), EnvironmentError>
+	where
+		T: ScriptType<V>,
+		F: Fn(&[&V]) -> CallResult<T> + 'static,
+	{
+		self.add_function_with_arity(name, arity, move |args: &[&V]| {
+			f(args).map(|o| V::new_object(ScriptObject(Rc::new(o))))
+		})
+	}
+
+	/// Registers a closure that can mutate its state, e.g. a handle to the host's world, as a
+	/// global function. Calling the function again while it is still running results in an
+	/// error.
+	pub fn add_function_mut<F>(&mut self, name: String, f: F) -> Result<(), EnvironmentError>
+	where
+		F: FnMut(&[&V]) -> CallResult<V> + 'static,
+	{
+		let f = RefCell::new(f);
+		self.add_function(name, move |args: &[&V]| (f.try_borrow_mut()?)(args))
+	}
+
+	/// Sets a closure that is called with the value, the name of the method and the arguments
+	/// when a script calls a method that the value doesn't have. It can return `None` to
+	/// leave the method undefined. This allows adding methods to values without modifying
+	/// the variant type, e.g. for proxy objects.
+	pub fn set_method_resolver<F>(&mut self, f: F)
+	where
+		F: Fn(&V, &str, &[&V]) -> Option<CallResult<V>> + 'static,
+	{
+		self.method_resolver = Some(Box::new(f));
+	}
+
+	/// Gives the method resolver a chance to handle a method call that failed with `error`
+	/// because the method is not defined.
+	pub(crate) fn resolve_method(
+		&self,
+		obj: &V,
+		method: &str,
+		args: &[&V],
+		error: Box<dyn Error>,
+	) -> CallResult<V> {
+		match (&self.method_resolver, error.downcast_ref()) {
+			(Some(resolve), Some(CallError::UndefinedFunction(_))) => {
+				resolve(obj, method, args).unwrap_or(Err(error))
+			}
+			_ => Err(error),
+		}
+	}
+
+	/// Calls the function with the given name. If no such function has been added, one of the
+	/// builtin functions is called instead, if any.
 	pub fn call(&self, func: &str, args: &[&V]) -> CallResult<V> {
-		self.functions
-			.get(func)
-			.ok_or_else(CallError::undefined_function)?(args)
+		if let Some((f, arity)) = self.functions.get(func) {
+			if !arity.accepts(args.len()) {
+				return Err(CallError::bad_argument_count());
+			}
+			return f(args);
+		}
+		if self.constants.contains_key(func) {
+			return Err(CallError::not_a_function(func));
+		}
+		match func {
+			"range" => range(args),
+			"assert" => assert(args),
+			"assert_eq" => assert_eq(args),
+			"abs" => abs(args),
+			"sign" => sign(args),
+			"clamp" => clamp(args),
+			"format" => format(args),
+			"sum" => sum(args),
+			"min" => extreme(args, Ordering::Less),
+			"max" => extreme(args, Ordering::Greater),
+			_ => Err(CallError::undefined_function(func)),
+		}
+	}
+
+	/// Returns the name and arity of every function that can be called, including builtin
+	/// functions.
+	pub fn functions(&self) -> impl Iterator<Item = (&str, Arity)> {
+		let added = self
+			.functions
+			.iter()
+			.map(|(name, (_, arity))| (&**name, *arity));
+		let builtins = BUILTINS
+			.iter()
+			.filter(move |(name, _)| !self.functions.contains_key(*name))
+			.copied();
+		added.chain(builtins)
+	}
+}
+
+impl Arity {
+	/// Whether a function with this arity can be called with the given amount of arguments.
+	pub fn accepts(self, count: usize) -> bool {
+		match self {
+			Self::Any => true,
+			Self::Exact(n) => count == n,
+			Self::Range(min, max) => (min..=max).contains(&count),
+		}
+	}
+
+	/// The arity of a script function with the given amount of parameters, excluding the
+	/// variadic parameter, of which the given amount have a default value.
+	pub(crate) fn of_script_function(fixed: usize, defaults: usize, variadic: bool) -> Self {
+		let min = fixed - defaults;
+		if variadic {
+			Self::Range(min, usize::MAX)
+		} else if min == fixed {
+			Self::Exact(fixed)
+		} else {
+			Self::Range(min, fixed)
+		}
+	}
+}
+
+/// Creates a [`Range`] with `range(stop)`, `range(start, stop)` or `range(start, stop, step)`.
+fn range<V>(args: &[&V]) -> CallResult<V>
+where
+	V: VariantType,
+{
+	let mut n = [0, 0, 1];
+	match args.len() {
+		1 => {
+			n[1] = args[0]
+				.as_integer()
+				.map_err(|_| CallError::bad_argument())?
+		}
+		2 | 3 => {
+			for (n, a) in n.iter_mut().zip(args) {
+				*n = a.as_integer().map_err(|_| CallError::bad_argument())?;
+			}
+		}
+		_ => return Err(CallError::bad_argument_count()),
+	}
+	let range = Range::new(n[0], n[1], n[2]).ok_or_else(CallError::bad_argument)?;
+	Ok(V::new_object(ScriptObject(Rc::new(range))))
+}
+
+/// Fails with the message given by `assert(cond, message)` if `cond` is `false`.
+fn assert<V>(args: &[&V]) -> CallResult<V>
+where
+	V: VariantType,
+{
+	let (cond, message) = match args {
+		[cond] => (cond, None),
+		[cond, message] => (cond, Some(message)),
+		_ => return Err(CallError::bad_argument_count()),
+	};
+	if cond.as_bool().map_err(|_| CallError::bad_argument())? {
+		Ok(V::default())
+	} else {
+		let message = message.map(|m| m.to_string()).unwrap_or_default();
+		Err(CallError::assertion_failed(message))
+	}
+}
+
+/// Fails if the two arguments of `assert_eq(a, b)` are not equal.
+fn assert_eq<V>(args: &[&V]) -> CallResult<V>
+where
+	V: VariantType,
+{
+	match args {
+		[a, b] if a == b => Ok(V::default()),
+		[a, b] => Err(CallError::assertion_failed(format!("{:?} != {:?}", a, b))),
+		_ => Err(CallError::bad_argument_count()),
+	}
+}
+
+/// Returns the absolute value of the argument of `abs(x)`.
+fn abs<V>(args: &[&V]) -> CallResult<V>
+where
+	V: VariantType,
+{
+	match args {
+		[x] => x.abs(),
+		_ => Err(CallError::bad_argument_count()),
+	}
+}
+
+/// Returns `-1`, `0` or `1` depending on the sign of the argument of `sign(x)`.
+fn sign<V>(args: &[&V]) -> CallResult<V>
+where
+	V: VariantType,
+{
+	match args {
+		[x] => x.sign(),
+		_ => Err(CallError::bad_argument_count()),
+	}
+}
+
+/// Restricts `x` to the range `min..=max` with `clamp(x, min, max)`.
+fn clamp<V>(args: &[&V]) -> CallResult<V>
+where
+	V: VariantType,
+{
+	match args {
+		[x, min, max] => x.clamp(min, max),
+		_ => Err(CallError::bad_argument_count()),
+	}
+}
+
+/// Replaces each `{}` in the first argument of `format(template, ...)` with the next argument.
+/// `{{` and `}}` are replaced with literal braces.
+fn format<V>(args: &[&V]) -> CallResult<V>
+where
+	V: VariantType,
+{
+	use core::fmt::Write;
+
+	let (template, mut args) = match args.split_first() {
+		Some((template, args)) => (template, args.iter()),
+		None => return Err(CallError::bad_argument_count()),
+	};
+	let template = (*template)
+		.clone()
+		.into_string()
+		.map_err(|_| CallError::bad_argument())?;
+	let mut out = String::with_capacity(template.len());
+	let mut chars = template.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'{' if chars.next_if_eq(&'{').is_some() => out.push('{'),
+			'}' if chars.next_if_eq(&'}').is_some() => out.push('}'),
+			'{' if chars.next_if_eq(&'}').is_some() => {
+				let arg = args.next().ok_or_else(CallError::bad_argument_count)?;
+				let _ = write!(out, "{}", arg);
+			}
+			'{' | '}' => return Err(CallError::bad_argument()),
+			c => out.push(c),
+		}
+	}
+	if args.next().is_some() {
+		return Err(CallError::bad_argument_count());
+	}
+	Ok(V::new_string(out.into()))
+}
+
+/// Adds all values of the argument of `sum(iterable)`. Returns `0` if there are no values.
+fn sum<V>(args: &[&V]) -> CallResult<V>
+where
+	V: VariantType,
+{
+	match args {
+		[iterable] => iterable
+			.iter()?
+			.try_fold(V::new_integer(0), |sum, v| sum.add(&v)),
+		_ => Err(CallError::bad_argument_count()),
+	}
+}
+
+/// Returns the smallest (`min(iterable)`) or largest (`max(iterable)`) value of the argument,
+/// depending on which ordering is wanted. Returns `none` if there are no values.
+fn extreme<V>(args: &[&V], wanted: Ordering) -> CallResult<V>
+where
+	V: VariantType,
+{
+	let mut iter = match args {
+		[iterable] => iterable.iter()?,
+		_ => return Err(CallError::bad_argument_count()),
+	};
+	let mut extreme = match iter.next() {
+		Some(v) => v,
+		None => return Ok(V::new_none()),
+	};
+	for v in iter {
+		let ord = v
+			.partial_cmp(&extreme)
+			.ok_or_else(CallError::incompatible_type)?;
+		if ord == wanted {
+			extreme = v;
+		}
+	}
+	Ok(extreme)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{Integer, Variant};
+
+	fn sum_env() -> Environment<Variant> {
+		let mut env = Environment::new();
+		env.add_function("sum".into(), |args: &[&Variant]| {
+			let mut sum = 0;
+			for a in args {
+				sum += a.as_integer().map_err(|_| CallError::bad_argument())?;
+			}
+			Ok(Variant::Integer(sum))
+		})
+		.unwrap();
+		env
+	}
+
+	fn run(source: &str, env: &Environment<Variant>) -> CallResult<Variant> {
+		let mut string_map = Default::default();
+		let class = crate::parse::<Variant, ()>(source, &mut string_map, ())
+			.unwrap_or_else(|e| panic!("{}", e));
+		class.instance().call("main", &[], env)
+	}
+
+	/// A minimal variant with only numbers that compares them with `numeric_cmp`. Of the
+	/// optional methods it only implements `sign`.
+	#[derive(Clone, Debug, Default)]
+	enum Num {
+		#[default]
+		None,
+		Bool(bool),
+		Int(Integer),
+		Real(crate::Real),
+	}
+
+	impl PartialEq for Num {
+		fn eq(&self, rhs: &Self) -> bool {
+			match (self, rhs) {
+				(Num::Bool(a), Num::Bool(b)) => a == b,
+				_ => self.numeric_cmp(rhs) == Some(Ordering::Equal),
+			}
+		}
+	}
+
+	impl PartialOrd for Num {
+		fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
+			self.numeric_cmp(rhs)
+		}
+	}
+
+	impl core::fmt::Display for Num {
+		fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+			core::fmt::Debug::fmt(self, f)
+		}
+	}
+
+	impl From<bool> for Num {
+		fn from(b: bool) -> Self {
+			Self::Bool(b)
+		}
+	}
+
+	#[rustfmt::skip]
+	impl VariantType for Num {
+		fn new_bool(value: bool) -> Self { value.into() }
+		fn new_integer(value: Integer) -> Self { Self::Int(value) }
+		fn new_real(value: crate::Real) -> Self { Self::Real(value) }
+		fn new_char(_: char) -> Self { Self::default() }
+		fn new_string(_: Rc<str>) -> Self { Self::default() }
+		fn new_bytes(_: Rc<[u8]>) -> Self { Self::default() }
+		fn new_object(_: ScriptObject<Self>) -> Self { Self::default() }
+		fn is_none(&self) -> bool { matches!(self, Self::None) }
+		fn as_bool(&self) -> Result<bool, &Self> {
+			if let Self::Bool(b) = self { Ok(*b) } else { Err(self) }
+		}
+		fn as_integer(&self) -> Result<Integer, &Self> {
+			if let Self::Int(i) = self { Ok(*i) } else { Err(self) }
+		}
+		fn as_real(&self) -> Result<crate::Real, &Self> {
+			if let Self::Real(r) = self { Ok(*r) } else { Err(self) }
+		}
+		fn as_char(&self) -> Result<char, &Self> { Err(self) }
+		fn into_string(self) -> Result<Rc<str>, Self> { Err(self) }
+		fn into_object(self) -> Result<ScriptObject<Self>, Self> { Err(self) }
+		fn call(&self, f: &str, _: &[&Self], _: &Environment<Self>) -> CallResult<Self> {
+			Err(CallError::undefined_function(f))
+		}
+		fn iter(&self) -> CallResult<Box<dyn Iterator<Item = Self>>> {
+			Err(CallError::incompatible_type())
+		}
+		fn index(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn set_index(&self, _: &Self, _: Self) -> CallResult<()> {
+			Err(CallError::incompatible_type())
+		}
+		fn add(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn sub(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn mul(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn div(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn rem(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn bitand(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn bitor(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn bitxor(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn lhs(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn rhs(&self, _: &Self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn neg(&self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn not(&self) -> CallResult<Self> { Err(CallError::incompatible_type()) }
+		fn sign(&self) -> CallResult<Self> {
+			match self {
+				Self::Int(i) => Ok(Self::Int(i.signum())),
+				_ => Err(CallError::incompatible_type()),
+			}
+		}
+	}
+
+	#[test]
+	fn call_closure() {
+		let env = sum_env();
+		let r = run("fn main()\n\treturn env.sum(1, 2, 3)\n", &env);
+		assert_eq!(r.unwrap(), Variant::Integer(6));
+	}
+
+	#[test]
+	fn call_closure_bad_argument() {
+		let env = sum_env();
+		let r = run("fn main()\n\treturn env.sum(1, \"2\")\n", &env);
+		assert_eq!(r.unwrap_err().to_string(), "Bad argument type");
+	}
+
+	#[test]
+	fn array_callback() {
+		let env = sum_env();
+		let r = run(
+			"fn main()\n\tvar a = [1, 2, 3]\n\treturn a.reduce(\"sum\", 4)\n",
+			&env,
+		);
+		assert_eq!(r.unwrap(), Variant::Integer(10));
+		let r = run(
+			"fn main()\n\tvar a = [1, \"2\"]\n\treturn a.map(\"sum\")\n",
+			&env,
+		);
+		assert_eq!(r.unwrap_err().to_string(), "Bad argument type");
+	}
+
+	#[test]
+	fn range() {
+		let env = Environment::new();
+		let t = |args: &str| {
+			let src = format!(
+				"fn main()\n\tvar a = []\n\tfor i in env.range({})\n\t\ta.push(i)\n\treturn a\n",
+				args
+			);
+			run(&src, &env).map(|r| r.to_string())
+		};
+		assert_eq!(t("2, 10, 2").unwrap(), "[2, 4, 6, 8]");
+		assert_eq!(t("3").unwrap(), "[0, 1, 2]");
+		assert_eq!(t("10, 2, -3").unwrap(), "[10, 7, 4]");
+		assert_eq!(t("5, 5").unwrap(), "[]");
+		assert_eq!(t("10, 2").unwrap(), "[]");
+		assert!(t("0, 2, 0").is_err());
+	}
+
+	#[test]
+	fn arity() {
+		let mut env = Environment::new();
+		env.add_function_with_arity("add".into(), Arity::Exact(2), |args: &[&Variant]| {
+			args[0] + args[1]
+		})
+		.unwrap();
+		let r = run("fn main()\n\treturn env.add(1, 2)\n", &env);
+		assert_eq!(r.unwrap(), Variant::Integer(3));
+		let r = run("fn main()\n\treturn env.add(1)\n", &env);
+		assert_eq!(r.unwrap_err().to_string(), "Bad argument count");
+		assert!(run("fn main()\n\treturn env.range()\n", &env).is_err());
+
+		let mut functions = env.functions().collect::<Vec<_>>();
+		functions.sort_by_key(|f| f.0);
+		assert_eq!(
+			functions,
+			[
+				("abs", Arity::Exact(1)),
+				("add", Arity::Exact(2)),
+				("assert", Arity::Range(1, 2)),
+				("assert_eq", Arity::Exact(2)),
+				("clamp", Arity::Exact(3)),
+				("format", Arity::Any),
+				("max", Arity::Exact(1)),
+				("min", Arity::Exact(1)),
+				("range", Arity::Range(1, 3)),
+				("sign", Arity::Exact(1)),
+				("sum", Arity::Exact(1))
+			]
+		);
+		env.add_function("range".into(), |_: &[&Variant]| Ok(Variant::None))
+			.unwrap();
+		assert_eq!(env.functions().filter(|f| f.0 == "range").count(), 1);
+	}
+
+	#[test]
+	fn assert() {
+		let env = Environment::new();
+		let t = |s: &str| {
+			run(
+				&format!("fn main()\n\tvar a = [1, 2]\n\t{}\n\treturn 5", s),
+				&env,
+			)
+			.map_err(|e| e.to_string())
+		};
+		assert_eq!(t("env.assert(a.len() == 2)"), Ok(Variant::Integer(5)));
+		assert_eq!(t("env.assert(true, \"unused\")"), Ok(Variant::Integer(5)));
+		assert_eq!(t("env.assert_eq(a[1], 2.0)"), Ok(Variant::Integer(5)));
+		assert_eq!(
+			t("env.assert(a.len() == 3)").unwrap_err(),
+			"Assertion failed"
+		);
+		assert_eq!(
+			t("env.assert(a[0] > 1, \"a[0] is too small\")").unwrap_err(),
+			"Assertion failed: a[0] is too small"
+		);
+		assert_eq!(
+			t("env.assert_eq(a, [1, \"2\"])").unwrap_err(),
+			"Assertion failed: [1, 2] != [1, \"2\"]"
+		);
+		assert_eq!(t("env.assert(1)").unwrap_err(), "Bad argument type");
+	}
+
+	#[test]
+	fn format() {
+		let env = Environment::new();
+		let t = |template: &str, args: &[&Variant]| {
+			let template = Variant::String(template.into());
+			let args = core::iter::once(&template)
+				.chain(args.iter().copied())
+				.collect::<Vec<_>>();
+			env.call("format", &args).map(|v| v.to_string())
+		};
+		let (a, b) = (Variant::Integer(2), Variant::String("b".into()));
+		assert_eq!(
+			t("{} + {} = {}", &[&a, &b, &Variant::None]).unwrap(),
+			"2 + b = none"
+		);
+		assert_eq!(t("no placeholders", &[]).unwrap(), "no placeholders");
+		assert_eq!(t("{{}} {{{}}}", &[&a]).unwrap(), "{} {2}");
+		assert_eq!(
+			t("{} {}", &[&a]).unwrap_err().to_string(),
+			"Bad argument count"
+		);
+		assert_eq!(
+			t("{}", &[&a, &b]).unwrap_err().to_string(),
+			"Bad argument count"
+		);
+		assert_eq!(t("{", &[&a]).unwrap_err().to_string(), "Bad argument type");
+		assert_eq!(t("}", &[]).unwrap_err().to_string(), "Bad argument type");
+		assert!(env.call("format", &[&a]).is_err());
+		assert!(env.call("format", &[]).is_err());
+		// Braces in string literals must be escaped to not be interpolated
+		let src = "fn main()\n\tvar a = [1]\n\treturn env.format(\"{{}} + {{}}\", a, a.len())";
+		assert_eq!(run(src, &env).unwrap().to_string(), "[1] + 1");
+	}
+
+	#[test]
+	fn numeric_builtins() {
+		let env = Environment::new();
+		let t = |s: &str| run(&format!("fn main()\n\treturn {}", s), &env).map(|v| v.to_string());
+		assert_eq!(
+			t("[env.abs(-3), env.abs(2.5), env.abs(-0.5)]").unwrap(),
+			"[3, 2.5, 0.5]"
+		);
+		assert_eq!(
+			t("[env.sign(-3), env.sign(0), env.sign(7)]").unwrap(),
+			"[-1, 0, 1]"
+		);
+		assert_eq!(
+			t("[env.sign(-0.5), env.sign(0.0), env.sign(2.0)]").unwrap(),
+			"[-1, 0, 1]"
+		);
+		assert_eq!(
+			t("[env.clamp(-3, 0, 5), env.clamp(3, 0, 5), env.clamp(9, 0, 5)]").unwrap(),
+			"[0, 3, 5]"
+		);
+		assert_eq!(
+			t("[env.clamp(0.5, 0, 1), env.clamp(2, 0, 1.5)]").unwrap(),
+			"[0.5, 1.5]"
+		);
+		assert_eq!(
+			t("env.abs(\"a\")").unwrap_err().to_string(),
+			"Type is not compatible"
+		);
+		assert_eq!(
+			t("env.clamp(3, 5, 0)").unwrap_err().to_string(),
+			"Bad argument type"
+		);
+		assert_eq!(
+			t("env.clamp(3, 0, 0.0 / 0.0)").unwrap_err().to_string(),
+			"Bad argument type"
+		);
+		assert_eq!(
+			t("env.sign(1, 2)").unwrap_err().to_string(),
+			"Bad argument count"
+		);
+		let min = format!("env.abs(-{} - 1)", Integer::MAX);
+		assert_eq!(t(&min).unwrap_err().to_string(), "Integer overflow");
+	}
+
+	#[test]
+	fn method_resolver() {
+		let mut env = Environment::new();
+		env.set_method_resolver(|obj: &Variant, method, args| match (obj, method, args) {
+			(Variant::Integer(i), "double", []) => Some(Ok(Variant::Integer(i * 2))),
+			(Variant::Integer(_), "double", _) => Some(Err(CallError::bad_argument_count())),
+			(Variant::Integer(_), "abs", _) => Some(Ok(Variant::None)),
+			_ => None,
+		});
+		let t = |s: &str| {
+			run(&format!("fn main()\n\tvar x = -5\n\treturn {}", s), &env).map(|v| v.to_string())
+		};
+		assert_eq!(t("x.double()").unwrap(), "-10");
+		assert_eq!(t("x.double().double()").unwrap(), "-20");
+		assert_eq!(t("x.abs()").unwrap(), "5");
+		assert_eq!(
+			t("x.double(1)").unwrap_err().to_string(),
+			"Bad argument count"
+		);
+		assert_eq!(
+			t("x.nope()").unwrap_err().to_string(),
+			"Undefined function 'nope'"
+		);
+		assert_eq!(
+			t("\"a\".double()").unwrap_err().to_string(),
+			"Undefined function 'double'"
+		);
+		// Errors other than undefined methods are not passed to the resolver.
+		assert_eq!(t("x.pow(-1)").unwrap_err().to_string(), "Bad argument type");
+	}
+
+	#[test]
+	fn reductions() {
+		let env = Environment::new();
+		let t = |s: &str| run(&format!("fn main()\n\treturn {}", s), &env).map(|v| v.to_string());
+		assert_eq!(t("env.sum([1, 2.5, 3])").unwrap(), "6.5");
+		assert_eq!(t("env.min([1, 2.5, 3])").unwrap(), "1");
+		assert_eq!(t("env.max([1, 2.5, 3])").unwrap(), "3");
+		assert_eq!(t("env.max([3, 2.5, 3.5])").unwrap(), "3.5");
+		assert_eq!(
+			t("[env.sum(5), env.min(5), env.max(5)]").unwrap(),
+			"[10, 0, 4]"
+		);
+		assert_eq!(t("env.sum([])").unwrap(), "0");
+		assert_eq!(t("[env.min([]), env.max([])]").unwrap(), "[none, none]");
+		assert_eq!(
+			t("env.min([1, \"a\"])").unwrap_err().to_string(),
+			"Type is not compatible"
+		);
+		assert_eq!(
+			t("env.sum([1, none])").unwrap_err().to_string(),
+			"Cannot add Integer and None"
+		);
+	}
+
+	#[test]
+	fn minimal_variant() {
+		let env = Environment::new();
+		assert_eq!(env.call("sign", &[&Num::Int(-5)]).unwrap(), Num::Int(-1));
+		assert_eq!(env.call("sign", &[&Num::Int(0)]).unwrap(), Num::Int(0));
+		let incompatible = |r: CallResult<Num>| {
+			matches!(
+				r.unwrap_err().downcast_ref(),
+				Some(CallError::IncompatibleType)
+			)
+		};
+		assert!(incompatible(env.call("abs", &[&Num::Int(-5)])));
+		assert!(incompatible(
+			env.call("clamp", &[&Num::Int(5), &Num::Int(0), &Num::Int(3)])
+		));
+		// Builtins that don't depend on the optional methods work too
+		assert!(env.call("assert_eq", &[&Num::Int(3), &Num::Int(3)]).is_ok());
+		assert!(env
+			.call("assert_eq", &[&Num::Int(3), &Num::Int(4)])
+			.is_err());
+	}
+
+	#[test]
+	fn numeric_cmp() {
+		let src = "fn lt(a, b)\n\treturn a < b\n\nfn eq(a, b)\n\treturn a == b";
+		let class = crate::parse::<Num, ()>(src, &mut Default::default(), ())
+			.unwrap_or_else(|e| panic!("{}", e));
+		let script = class.instance();
+		let env = Environment::new();
+		let t = |f, a: Num, b: Num| script.call(f, &[&a, &b], &env).unwrap();
+		assert_eq!(t("lt", Num::Int(3), Num::Real(3.5)), Num::Bool(true));
+		assert_eq!(t("lt", Num::Real(3.5), Num::Int(3)), Num::Bool(false));
+		assert_eq!(t("lt", Num::Real(-0.5), Num::Real(0.5)), Num::Bool(true));
+		assert_eq!(t("eq", Num::Int(2), Num::Real(2.0)), Num::Bool(true));
+		assert_eq!(t("eq", Num::Int(2), Num::Int(3)), Num::Bool(false));
+		assert_eq!(t("lt", Num::Int(2), Num::None), Num::Bool(false));
+		let big = Integer::MAX;
+		assert_eq!(t("lt", Num::Int(big - 1), Num::Int(big)), Num::Bool(true));
+		assert_eq!(Num::Real(crate::Real::NAN).numeric_cmp(&Num::Int(1)), None);
+		assert_eq!(Num::None.numeric_cmp(&Num::None), None);
+	}
+
+	#[test]
+	fn duplicate_function() {
+		let mut env = sum_env();
+		let r = env.add_function("sum".into(), |_: &[&Variant]| Ok(Variant::None));
+		assert!(matches!(r, Err(EnvironmentError::FunctionAlreadyExists)));
+	}
+
+	#[test]
+	fn constants() {
+		let mut env = sum_env();
+		env.add_constant("PI".into(), Variant::Real(3.0)).unwrap();
+		let t = |body: &str| run(&format!("fn main()\n\treturn {}", body), &env);
+		assert_eq!(t("env.PI * 2").unwrap(), Variant::Real(6.0));
+		assert_eq!(
+			t("env.PI()").unwrap_err().to_string(),
+			"'PI' is a constant, not a function"
+		);
+		assert_eq!(
+			t("env.sum + 1").unwrap_err().to_string(),
+			"'sum' is a function, not a constant"
+		);
+		assert_eq!(
+			t("env.abs").unwrap_err().to_string(),
+			"'abs' is a function, not a constant"
+		);
+		assert_eq!(
+			t("env.TAU").unwrap_err().to_string(),
+			"Undefined constant 'TAU'"
+		);
+		let r = env.add_constant("PI".into(), Variant::None);
+		assert!(matches!(r, Err(EnvironmentError::ConstantAlreadyExists)));
+		let r = env.add_constant("sum".into(), Variant::None);
+		assert!(matches!(r, Err(EnvironmentError::FunctionAlreadyExists)));
+		let r = env.add_function("PI".into(), |_: &[&Variant]| Ok(Variant::None));
+		assert!(matches!(r, Err(EnvironmentError::ConstantAlreadyExists)));
+	}
+
+	#[test]
+	fn constructor() {
+		#[derive(Debug, PartialEq)]
+		struct Vector2(Integer, Integer);
+
+		impl ScriptType<Variant> for Vector2 {
+			fn call_self(
+				&self,
+				_: &ScriptObject<Variant>,
+				function: &str,
+				_: &[&Variant],
+				_: &Environment<Variant>,
+			) -> CallResult<Variant> {
+				match function {
+					"x" => Ok(Variant::Integer(self.0)),
+					"y" => Ok(Variant::Integer(self.1)),
+					_ => Err(CallError::undefined_function(function)),
+				}
+			}
+
+			fn index(&self, index: &Variant) -> CallResult<Variant> {
+				match index {
+					Variant::String(s) if &**s == "x" => Ok(Variant::Integer(self.0)),
+					Variant::String(s) if &**s == "y" => Ok(Variant::Integer(self.1)),
+					_ => Err(CallError::bad_argument()),
+				}
+			}
+		}
+
+		let mut env = Environment::new();
+		env.add_constructor("Vector2".into(), Arity::Exact(2), |args: &[&Variant]| {
+			let x = args[0]
+				.as_integer()
+				.map_err(|_| CallError::bad_argument())?;
+			let y = args[1]
+				.as_integer()
+				.map_err(|_| CallError::bad_argument())?;
+			Ok(Vector2(x, y))
+		})
+		.unwrap();
+		let t = |body: &str| run(&format!("fn main()\n\tvar v = {}", body), &env);
+		let r = t("env.Vector2(3, 4)\n\treturn [v[\"x\"], v[\"y\"], v.x(), v.y()]");
+		assert_eq!(r.unwrap().to_string(), "[3, 4, 3, 4]");
+		let r = t("env.Vector2(3, 4)\n\treturn v");
+		assert_eq!(r.unwrap().downcast_ref::<Vector2>(), Some(&Vector2(3, 4)));
+		assert!(t("env.Vector2(3)\n\treturn v").is_err());
+		assert!(t("env.Vector2(3, \"4\")\n\treturn v").is_err());
+		assert!(t("env.Vector2(3, 4)\n\treturn v.z()").is_err());
+		let r = env.add_constructor("Vector2".into(), Arity::Any, |_: &[&Variant]| {
+			Ok(Vector2(0, 0))
+		});
+		assert!(matches!(r, Err(EnvironmentError::FunctionAlreadyExists)));
+	}
+
+	#[test]
+	fn mutable_state() {
+		let counter = Rc::new(RefCell::new(0));
+		let mut env = Environment::new();
+		let c = counter.clone();
+		env.add_function_mut("increment".into(), move |args: &[&Variant]| {
+			if args.len() != 1 {
+				return Err(CallError::bad_argument_count());
+			}
+			*c.borrow_mut() += args[0]
+				.as_integer()
+				.map_err(|_| CallError::bad_argument())?;
+			Ok(Variant::Integer(*c.borrow()))
+		})
+		.unwrap();
+		let mut id = 0;
+		env.add_function_mut("next_id".into(), move |_: &[&Variant]| {
+			id += 1;
+			Ok(Variant::Integer(id))
+		})
+		.unwrap();
+
+		let r = run(
+			"fn main()\n\tfor i in 5\n\t\tenv.increment(i)\n\tenv.next_id()\n\treturn env.next_id()\n",
+			&env,
+		);
+		assert_eq!(r.unwrap(), Variant::Integer(2));
+		assert_eq!(*counter.borrow(), 10);
+		assert!(run("fn main()\n\tenv.increment()\n", &env).is_err());
+		assert_eq!(*counter.borrow(), 10);
 	}
 }
@@ -12,7 +12,38 @@
 #![feature(optimize_attribute)]
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use unwrap_none::UnwrapNone;
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("the libm feature is required if std is disabled");
+
+#[cfg(all(feature = "int32", feature = "int64"))]
+compile_error!("the int32 and int64 features are mutually exclusive");
+
+/// The type used to represent integers.
+#[cfg(not(any(feature = "int32", feature = "int64")))]
+pub type Integer = isize;
+/// The type used to represent integers.
+#[cfg(feature = "int32")]
+pub type Integer = i32;
+/// The type used to represent integers.
+#[cfg(feature = "int64")]
+pub type Integer = i64;
+
+/// The unsigned counterpart of [`Integer`], used for logical shifts.
+#[cfg(not(any(feature = "int32", feature = "int64")))]
+type UInteger = usize;
+/// The unsigned counterpart of [`Integer`], used for logical shifts.
+#[cfg(feature = "int32")]
+type UInteger = u32;
+/// The unsigned counterpart of [`Integer`], used for logical shifts.
+#[cfg(feature = "int64")]
+type UInteger = u64;
+
+/// The type used to represent real numbers.
+#[cfg(not(feature = "real32"))]
+pub type Real = f64;
+/// The type used to represent real numbers.
+#[cfg(feature = "real32")]
+pub type Real = f32;
 
 mod ast;
 mod bytecode;
@@ -23,15 +54,16 @@ mod types;
 mod util;
 mod variant;
 
-pub use bytecode::{ByteCode, CallResult, Instruction, RunState, Tracer};
-pub use environment::Environment;
-pub use script::{Class, Instance, ScriptObject, ScriptType};
-pub use types::{Array, Dictionary};
+pub use bytecode::{ByteCode, CallResult, Instruction, RunError, RunState, Tracer};
+pub use environment::{Arity, Environment};
+pub use script::{CallError, Class, Instance, Method, ScriptIter, ScriptObject, ScriptType};
+pub use tokenizer::Indentation;
+pub use types::{Array, Dictionary, Range};
 pub use variant::specialized;
 pub use variant::{Variant, VariantType};
 
 use bytecode::{ByteCodeBuilder, ByteCodeError};
-use script::{CallError, Script};
+use script::Script;
 use tokenizer::TokenStream;
 
 use core::fmt;
@@ -41,6 +73,7 @@ pub(crate) mod std_types {
 	use core::hash::BuildHasherDefault;
 	use rustc_hash::FxHasher;
 	pub use std::collections::{hash_map, HashMap, HashSet};
+	pub use std::error::Error;
 	pub use std::rc::Rc;
 	pub use std::sync::Arc;
 	pub type FxHashMap<K, V> = HashMap<K, V, BuildHasherDefault<FxHasher>>;
@@ -56,13 +89,43 @@ pub(crate) mod std_types {
 	pub use alloc::rc::Rc;
 	pub use alloc::string::{String, ToString};
 	pub use alloc::sync::Arc;
+	pub use alloc::vec;
 	pub use alloc::vec::Vec;
+	pub use core::error::Error;
 	pub use hashbrown::{hash_map, HashMap, HashSet};
 
 	use core::hash::BuildHasherDefault;
 	use rustc_hash::FxHasher;
 	pub type FxHashMap<K, V> = HashMap<K, V, BuildHasherDefault<FxHasher>>;
 	pub type FxHashSet<K> = HashSet<K, BuildHasherDefault<FxHasher>>;
+
+	/// Floating point functions that are only available with `std`.
+	pub trait RealExt {
+		fn sqrt(self) -> Self;
+		fn hypot(self, other: Self) -> Self;
+	}
+
+	#[cfg(not(feature = "real32"))]
+	impl RealExt for f64 {
+		fn sqrt(self) -> Self {
+			libm::sqrt(self)
+		}
+
+		fn hypot(self, other: Self) -> Self {
+			libm::hypot(self, other)
+		}
+	}
+
+	#[cfg(feature = "real32")]
+	impl RealExt for f32 {
+		fn sqrt(self) -> Self {
+			libm::sqrtf(self)
+		}
+
+		fn hypot(self, other: Self) -> Self {
+			libm::hypotf(self, other)
+		}
+	}
 }
 
 use std_types::*;
@@ -76,6 +139,8 @@ pub struct ParseError<'a> {
 
 enum ParseErrorType<'a> {
 	DuplicateLocal(&'a str),
+	DuplicateFunction(&'a str),
+	Token(tokenizer::TokenError),
 	Ast(ast::Error),
 	ByteCode(ByteCodeError<'a>),
 }
@@ -99,11 +164,77 @@ impl<'a> ParseError<'a> {
 		)
 	}
 
+	fn new_token(source: &'a str, error: tokenizer::TokenStreamError) -> Self {
+		Self::new(
+			source,
+			error.line,
+			error.column,
+			ParseErrorType::Token(error.error),
+		)
+	}
+
 	fn new_ast(source: &'a str, error: ast::Error) -> Self {
 		Self::new(source, error.line, error.column, ParseErrorType::Ast(error))
 	}
 }
 
+/// Options that affect how scripts are parsed. See [`parse_with_options`].
+#[derive(Clone, Copy, Debug)]
+pub struct ParseOptions {
+	max_nesting_depth: u16,
+	indentation: Indentation,
+}
+
+impl ParseOptions {
+	pub fn new() -> Self {
+		Self {
+			max_nesting_depth: tokenizer::DEFAULT_MAX_DEPTH,
+			indentation: Indentation::Tabs,
+		}
+	}
+
+	/// Sets how deep expressions can be nested, e.g. with parentheses, unary operators or
+	/// chains of operators, calls and indices. Deeper expressions are rejected to prevent
+	/// stack overflows on pathological input. The default is 128.
+	pub fn set_max_nesting_depth(&mut self, depth: u16) {
+		self.max_nesting_depth = depth;
+	}
+
+	/// Returns how deep expressions can be nested.
+	pub fn max_nesting_depth(&self) -> u16 {
+		self.max_nesting_depth
+	}
+
+	/// Sets what lines are indented with. Only the given kind of indentation is accepted and,
+	/// if spaces are used, each line must be indented by a multiple of the given amount. The
+	/// default is tabs.
+	///
+	/// # Panics
+	///
+	/// If the amount of spaces is zero.
+	pub fn set_indentation(&mut self, indentation: Indentation) {
+		assert_ne!(
+			indentation,
+			Indentation::Spaces(0),
+			"indentation can't be empty"
+		);
+		self.indentation = indentation;
+	}
+
+	/// Returns what lines are indented with.
+	pub fn indentation(&self) -> Indentation {
+		self.indentation
+	}
+}
+
+impl Default for ParseOptions {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Parses a script into a [`Class`]. Names and string constants of all functions are interned
+/// in `string_map`, which may also be shared between scripts.
 pub fn parse<'a, 'b: 'a, V, T>(
 	source: &'a str,
 	string_map: &'b mut FxHashSet<Rc<str>>,
@@ -113,14 +244,30 @@ where
 	V: VariantType,
 	T: Tracer<V>,
 {
-	let tks = TokenStream::parse(source).unwrap();
+	parse_with_options(source, string_map, tracer, &ParseOptions::new())
+}
+
+/// Parses a script into a [`Class`] like [`parse`] with the given options.
+pub fn parse_with_options<'a, 'b: 'a, V, T>(
+	source: &'a str,
+	string_map: &'b mut FxHashSet<Rc<str>>,
+	tracer: T,
+	options: &ParseOptions,
+) -> Result<Class<V, T>, ParseError<'a>>
+where
+	V: VariantType,
+	T: Tracer<V>,
+{
+	let mut tks = TokenStream::parse(source, options.indentation)
+		.map_err(|e| ParseError::new_token(source, e))?;
+	tks.set_max_depth(options.max_nesting_depth);
 	let ast = ast::Script::parse(tks).map_err(|e| ParseError::new_ast(source, e))?;
 
 	let locals = {
 		let locals = ast.variables;
 		let mut hm = FxHashMap::with_capacity_and_hasher(locals.len(), Default::default());
 		for (i, l) in locals.iter().enumerate() {
-			if hm.insert(l.to_string().into(), i as u8).is_some() {
+			if hm.insert(util::intern(string_map, *l), i as u8).is_some() {
 				return Err(ParseError::new(
 					source,
 					0,
@@ -137,16 +284,31 @@ where
 
 	for (i, f) in ast.functions.iter().enumerate() {
 		let i = i as u8;
-		let name = f.name.into();
-		script
-			.function_map
-			.insert(name, i)
-			.expect_none("Duplicate function");
+		let name = util::intern(string_map, f.name);
+		if script.function_map.insert(name, i).is_some() {
+			return Err(ParseError::new(
+				source,
+				f.line,
+				f.column,
+				ParseErrorType::DuplicateFunction(f.name),
+			));
+		}
 	}
+	let arities = ast
+		.functions
+		.iter()
+		.map(ast::Function::arity)
+		.collect::<Vec<_>>();
 	for f in ast.functions {
-		ByteCodeBuilder::parse(f, &script.function_map, &script.locals, string_map)
-			.map(|f| script.functions.push(f))
-			.map_err(|e| ParseError::new_bytecode(source, e))?;
+		ByteCodeBuilder::parse(
+			f,
+			&script.function_map,
+			&arities,
+			&script.locals,
+			string_map,
+		)
+		.map(|f| script.functions.push(f))
+		.map_err(|e| ParseError::new_bytecode(source, e))?;
 	}
 	script.function_map.shrink_to_fit();
 	script.functions.shrink_to_fit();
@@ -163,9 +325,17 @@ impl fmt::Display for ParseError<'_> {
 				f.write_str(v)?;
 				f.write_char('\'')?;
 			}
+			ParseErrorType::DuplicateFunction(v) => {
+				f.write_str("Duplicate function '")?;
+				f.write_str(v)?;
+				f.write_char('\'')?;
+			}
 			ParseErrorType::ByteCode(e) => {
 				e.fmt(f)?;
 			}
+			ParseErrorType::Token(e) => {
+				e.fmt(f)?;
+			}
 			ParseErrorType::Ast(e) => {
 				e.fmt(f)?;
 			}
@@ -173,3 +343,1658 @@ impl fmt::Display for ParseError<'_> {
 		write!(f, " at line {}, column {}", self.line + 1, self.column + 1)
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn compile(source: &str) -> Class<Variant, ()> {
+		parse(source, &mut Default::default(), ()).unwrap_or_else(|e| panic!("{}", e))
+	}
+
+	fn compiles(source: &str) -> bool {
+		parse::<Variant, ()>(source, &mut Default::default(), ()).is_ok()
+	}
+
+	/// Returns the error message of a script that fails to compile.
+	fn compile_error(source: &str) -> String {
+		match parse::<Variant, ()>(source, &mut Default::default(), ()) {
+			Ok(_) => panic!("Compiled successfully:\n{}", source),
+			Err(e) => e.to_string(),
+		}
+	}
+
+	fn run(source: &str) -> CallResult<Variant> {
+		compile(source)
+			.instance()
+			.call("main", &[], &Environment::new())
+	}
+
+	/// Everything a [`Recorder`] has seen while running a script.
+	#[derive(Default)]
+	struct Recording {
+		/// The name of each method that is called.
+		calls: Vec<Rc<str>>,
+		/// The index of each instruction that is executed.
+		ips: Vec<u32>,
+		/// The registers before each instruction and when an error occurs.
+		variables: Vec<Vec<Variant>>,
+		/// The value of each named variable before each instruction.
+		#[cfg(feature = "debug-info")]
+		names: Vec<String>,
+	}
+
+	#[derive(Clone, Default)]
+	struct Recorder(Rc<core::cell::RefCell<Recording>>);
+
+	impl Recorder {
+		fn compile(&self, source: &str) -> Class<Variant, Self> {
+			parse(source, &mut Default::default(), self.clone()).unwrap_or_else(|e| panic!("{}", e))
+		}
+	}
+
+	impl Tracer<Variant> for Recorder {
+		fn instruction_pre(&self, _: &ByteCode<Variant>, ip: u32, _: &Instruction) {
+			self.0.borrow_mut().ips.push(ip);
+		}
+
+		fn call_pre(&self, _: &ByteCode<Variant>, function: &Rc<str>) {
+			self.0.borrow_mut().calls.push(function.clone());
+		}
+
+		fn peek(&self, _bytecode: &ByteCode<Variant>, state: &mut RunState<Variant>) {
+			let mut recording = self.0.borrow_mut();
+			recording.variables.push(state.variables().to_vec());
+			#[cfg(feature = "debug-info")]
+			for (reg, name) in _bytecode.variable_names() {
+				let value = &state.variables()[usize::from(*reg)];
+				recording.names.push(format!("{} = {}", name, value));
+			}
+		}
+
+		fn error(&self, _: &ByteCode<Variant>, state: &mut RunState<Variant>, _: &dyn Error) {
+			self.0
+				.borrow_mut()
+				.variables
+				.push(state.variables().to_vec());
+		}
+	}
+
+	#[test]
+	fn not() {
+		let t = |s: &str| run(s).unwrap();
+		assert_eq!(t("fn main()\n\treturn !true"), Variant::Bool(false));
+		assert_eq!(t("fn main()\n\treturn !0"), Variant::Integer(-1));
+		assert_eq!(t("fn main()\n\treturn !(1 < 2)"), Variant::Bool(false));
+		assert_eq!(
+			t("fn main()\n\treturn !self.f()\n\nfn f()\n\treturn false"),
+			Variant::Bool(true)
+		);
+		assert!(run("fn main()\n\treturn self.f(\"x\")\n\nfn f(a)\n\treturn !a").is_err());
+		// Constant operands are evaluated when compiling
+		assert!(!compiles("fn main()\n\treturn !\"x\""));
+	}
+
+	#[test]
+	fn unary_operand() {
+		let t = |e: &str| {
+			let src = format!(
+				"fn main()\n\tvar a = 3\n\tvar b = 4\n\tvar arr = [false, 5]\n\treturn {}\n\nfn f(x)\n\treturn x * 10",
+				e
+			);
+			run(&src).unwrap()
+		};
+		assert_eq!(t("-(a + b)"), Variant::Integer(-7));
+		assert_eq!(t("-self.f(a)"), Variant::Integer(-30));
+		assert_eq!(t("!arr[0]"), Variant::Bool(true));
+		assert_eq!(t("-arr[1] * 2"), Variant::Integer(-10));
+		assert_eq!(t("-a.abs()"), Variant::Integer(-3));
+		assert_eq!(t("a - -b"), Variant::Integer(7));
+		assert_eq!(t("a * -b"), Variant::Integer(-12));
+		assert_eq!(t("self.f(-a + 1)"), Variant::Integer(-20));
+		assert_eq!(t(&Integer::MIN.to_string()), Variant::Integer(Integer::MIN));
+		// A negative literal may be the last token of the source
+		assert_eq!(run("fn main()\n\treturn -5").unwrap(), Variant::Integer(-5));
+		assert!(!compiles("fn main()\n\treturn -"));
+	}
+
+	#[test]
+	fn loop_else() {
+		let t = |s: &str| run(s).unwrap();
+		let for_else = "fn main()\n\tvar r = 0\n\tfor i in 3\n\t\tif i == %\n\t\t\tbreak\n\telse\n\t\tr = 1\n\treturn r";
+		assert_eq!(t(&for_else.replace('%', "5")), Variant::Integer(1));
+		assert_eq!(t(&for_else.replace('%', "1")), Variant::Integer(0));
+		let for_empty =
+			"fn main()\n\tvar r = 0\n\tfor c in \"\"\n\t\tpass\n\telse\n\t\tr = 1\n\treturn r";
+		assert_eq!(t(for_empty), Variant::Integer(1));
+		let while_else = "fn main()\n\tvar i = 0\n\tvar r = 0\n\twhile i < 3\n\t\tif i == %\n\t\t\tbreak\n\t\ti += 1\n\telse\n\t\tr = 1\n\treturn r";
+		assert_eq!(t(&while_else.replace('%', "5")), Variant::Integer(1));
+		assert_eq!(t(&while_else.replace('%', "1")), Variant::Integer(0));
+	}
+
+	#[test]
+	fn loop_break() {
+		let t = |s: &str| run(&format!("fn main()\n\tvar n = 0\n{}\n\treturn n", s)).unwrap();
+		let src = "\tloop\n\t\tn += 1\n\t\tif n == 5\n\t\t\tbreak";
+		assert_eq!(t(src), Variant::Integer(5));
+		// `continue` jumps back to the start
+		let src = "\tvar i = 0\n\tloop\n\t\ti += 1\n\t\tif i % 2 == 0\n\t\t\tcontinue\n\t\tn += i\n\t\tif i > 6\n\t\t\tbreak";
+		assert_eq!(t(src), Variant::Integer(16));
+		// Breaking out of a `for` loop inside a `loop` pops its iterator
+		let src = "\tfor x in [1, 2]\n\t\tloop\n\t\t\tfor y in 10\n\t\t\t\tn += x\n\t\t\t\tif y == 2\n\t\t\t\t\tbreak 1";
+		assert_eq!(t(src), Variant::Integer(9));
+	}
+
+	#[test]
+	fn break_continue_levels() {
+		// Loops of each kind iterate 3 times. The innermost body adds 1 and then breaks or
+		// continues, the other bodies add a weight after the inner loop. Two outer loops that
+		// are never exited check whether the iterator stacks are balanced.
+		const LOOPS: [&str; 3] = ["while", "for", "for-int"];
+
+		fn source(loops: &[usize], exit: &str, levels: usize) -> String {
+			let mut src =
+				String::from("fn main()\n\tvar n = 0\n\tfor _ in [0, 1]\n\t\tfor _ in 2\n");
+			for (d, &l) in loops.iter().enumerate() {
+				let tabs = "\t".repeat(3 + d);
+				src += &match LOOPS[l] {
+					"while" => format!(
+						"{0}var w{1} = 0\n{0}while w{1} < 3\n{0}\tw{1} += 1\n",
+						tabs, d
+					),
+					"for" => format!("{}for _ in [0, 1, 2]\n", tabs),
+					_ => format!("{}for _ in 3\n", tabs),
+				};
+			}
+			let tabs = "\t".repeat(3 + loops.len());
+			src += &format!("{0}n += 1\n{0}{1} {2}\n", tabs, exit, levels);
+			for d in (1..loops.len()).rev() {
+				src += &format!("{}n += {}\n", "\t".repeat(3 + d), 10i32.pow(d as u32));
+			}
+			src + "\treturn n"
+		}
+
+		// Returns how many more loops have to be exited, if any
+		fn simulate(
+			d: usize,
+			len: usize,
+			cont: bool,
+			levels: usize,
+			n: &mut Integer,
+		) -> Option<usize> {
+			for _ in 0..3 {
+				let exit = if d == len - 1 {
+					*n += 1;
+					levels
+				} else {
+					match simulate(d + 1, len, cont, levels, n) {
+						Some(levels) => levels,
+						None => {
+							*n += 10i32.pow(d as u32 + 1) as Integer;
+							continue;
+						}
+					}
+				};
+				match (exit, cont) {
+					(0, true) => continue,
+					(0, false) => return None,
+					(exit, _) => return Some(exit - 1),
+				}
+			}
+			None
+		}
+
+		for len in 1..=3 {
+			for i in 0..LOOPS.len().pow(len as u32) {
+				let loops = (0..len)
+					.map(|d| i / LOOPS.len().pow(d as u32) % LOOPS.len())
+					.collect::<Vec<_>>();
+				for levels in 0..len {
+					for (exit, cont) in [("break", false), ("continue", true)] {
+						let src = source(&loops, exit, levels);
+						let mut n = 0;
+						simulate(0, len, cont, levels, &mut n);
+						assert_eq!(run(&src).unwrap(), Variant::Integer(n * 4), "{}", src);
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn integer_loop_step() {
+		let src = "fn main()\n\tvar s = %\n\tvar r = []\n\tfor i in 5 to 0 step s\n\t\tr.push(i)\n\treturn r";
+		let t = |step: &str| run(&src.replace('%', step)).map(|r| r.to_string());
+		assert_eq!(t("-2").unwrap(), "[5, 3, 1]");
+		assert_eq!(
+			t("0").unwrap_err().to_string(),
+			"Step of integer loop is zero"
+		);
+	}
+
+	#[test]
+	fn triple_quoted_string() {
+		let src = "fn main()\n\tvar s = \"\"\"{{\n\t\"a\": 'b'\n}}\"\"\"\n\treturn s + \"!\"";
+		let r = run(src).unwrap().into_string().unwrap();
+		assert_eq!(&*r, "{\n\t\"a\": 'b'\n}!");
+		assert_eq!(
+			compile_error("fn main()\n\treturn \"\"\"a\nb"),
+			"Unterminated string at line 2, column 9"
+		);
+	}
+
+	#[test]
+	fn string_index() {
+		let t = |e: &str| run(&format!("fn main()\n\tvar s = \"abc\"\n\treturn {}", e));
+		assert_eq!(t("s[0]").unwrap(), Variant::Char('a'));
+		assert_eq!(t("s[-1]").unwrap(), Variant::Char('c'));
+		assert_eq!(t("s[1].to_int()").unwrap(), Variant::Integer(98));
+		assert!(t("s[3]").is_err());
+		assert!(run("fn main()\n\tvar s = \"abc\"\n\ts[0] = s[1]").is_err());
+	}
+
+	#[test]
+	fn raw_string() {
+		let t = |e: &str| {
+			run(&format!("fn main()\n\treturn {}", e))
+				.unwrap()
+				.to_string()
+		};
+		assert_eq!(t("r\"\\n\".len()"), "2");
+		assert_eq!(t("r\"\\d{2}\" + \"\\n\""), "\\d{2}\n");
+	}
+
+	#[test]
+	fn format_string() {
+		let t = |s: &str| run(s).unwrap();
+		let s = |s: &str| Variant::String(s.into());
+		assert_eq!(
+			t("fn main()\n\tvar a = 1\n\tvar b = 2\n\treturn \"sum={a+b}\""),
+			s("sum=3")
+		);
+		assert_eq!(t("fn main()\n\treturn \"{{x}}\""), s("{x}"));
+		assert_eq!(
+			t("fn main()\n\tvar x = 4\n\treturn \"{x}{{{x * 2}}}\""),
+			s("4{8}")
+		);
+		assert_eq!(t("fn main()\n\treturn \"a\" + \"{1.5}\""), s("a1.5"));
+
+		// Any value can be put in a string
+		let v = |e: &str| t(&format!("fn main()\n\tvar v = {}\n\treturn \"<{{v}}>\"", e));
+		assert_eq!(v("[].pop()"), s("<none>"));
+		assert_eq!(v("none"), s("<none>"));
+		assert_eq!(v("true"), s("<true>"));
+		assert_eq!(v("\"abc\""), s("<abc>"));
+		assert_eq!(v("[1, \"b\", []]"), s("<[1, \"b\", []]>"));
+		assert_eq!(v("{\"a\": 1}"), s("<{\"a\": 1}>"));
+		assert_eq!(v("{}"), s("<{}>"));
+		assert_eq!(t("fn main()\n\treturn \"{none}{[1]}\""), s("none[1]"));
+
+		assert_eq!(
+			compile_error("fn main()\n\treturn \"{1\""),
+			"Unterminated expression in string at line 2, column 9"
+		);
+		assert_eq!(
+			compile_error("fn main()\n\treturn \"{}\""),
+			"Unterminated expression in string at line 2, column 9"
+		);
+		assert_eq!(
+			compile_error("fn main()\n\tvar a = 1\n\treturn \"{a\n\""),
+			"Unterminated expression in string at line 3, column 9"
+		);
+	}
+
+	#[test]
+	fn max_nesting_depth() {
+		let t = |src: &str, depth| {
+			let mut options = ParseOptions::new();
+			if let Some(depth) = depth {
+				options.set_max_nesting_depth(depth);
+			}
+			parse_with_options::<Variant, ()>(src, &mut Default::default(), (), &options)
+				.map(|_| ())
+				.map_err(|e| e.to_string())
+		};
+		let sum = format!("fn main()\n\treturn 0{}", " + 1".repeat(200));
+		assert!(t(&sum, None).is_err());
+		assert!(t(&sum, Some(1000)).is_ok());
+		let parens = format!("fn main()\n\treturn {}1{}", "(".repeat(20), ")".repeat(20));
+		assert!(t(&parens, None).is_ok());
+		let e = t(&parens, Some(10)).unwrap_err();
+		assert!(e.starts_with("Expression is nested too deeply"), "{}", e);
+	}
+
+	#[test]
+	fn space_indentation() {
+		let t = |src: &str, indentation| {
+			let mut options = ParseOptions::new();
+			options.set_indentation(indentation);
+			let class =
+				parse_with_options::<Variant, ()>(src, &mut Default::default(), (), &options)
+					.map_err(|e| e.to_string())?;
+			let r = class.instance().call("main", &[], &Environment::new());
+			Ok::<_, String>(r.unwrap())
+		};
+		let src = "fn main()\n    var n = 0\n    for i in 4\n        if i > 1\n            n += i\n    return n";
+		assert_eq!(t(src, Indentation::Spaces(4)), Ok(Variant::Integer(5)));
+		let two = src.replace("    ", "  ");
+		assert_eq!(t(&two, Indentation::Spaces(2)), Ok(Variant::Integer(5)));
+		let e = t(&two, Indentation::Spaces(4)).unwrap_err();
+		assert!(e.starts_with("Indentation is not a multiple"), "{}", e);
+		let e = t(src, Indentation::Tabs).unwrap_err();
+		assert!(e.starts_with("Spaces can't be used"), "{}", e);
+		let tabs = src.replace("    ", "\t");
+		assert_eq!(t(&tabs, Indentation::Tabs), Ok(Variant::Integer(5)));
+	}
+
+	#[test]
+	fn pass() {
+		let t = |s: &str| run(s).unwrap();
+		let none = |s: &str| matches!(t(s), Variant::None);
+		assert!(none("fn main()\n\tpass"));
+		assert!(none("fn main()\n\tif true\n\t\tpass"));
+		assert!(none("fn main()\n\twhile false\n\t\tpass"));
+		assert!(none("fn main()\n\tfor i in 3\n\t\tpass"));
+		assert_eq!(
+			t("fn main()\n\tif false\n\t\tpass\n\telse\n\t\tpass\n\treturn 4"),
+			Variant::Integer(4)
+		);
+		assert!(none("fn f()\n\tpass\n\nfn main()\n\treturn self.f()"));
+		assert!(none("fn main()\n\tif false\n\t\treturn 1"));
+		assert!(none(
+			"fn main()\n\tif false\n\t\treturn 1\n\telse\n\t\tpass"
+		));
+		assert_eq!(t("fn main()\n\tif true\n\t\treturn 1"), Variant::Integer(1));
+	}
+
+	#[test]
+	fn return_expression() {
+		let t = |e: &str| {
+			let src = format!(
+				"fn main()\n\treturn self.f(3, 4)\n\nfn f(p0, p1)\n\treturn {}",
+				e
+			);
+			run(&src).unwrap()
+		};
+		assert_eq!(t("p0 + p1"), Variant::Integer(7));
+		assert_eq!(t("p1 - p0"), Variant::Integer(1));
+		assert_eq!(t("(p1 - p0) * p0"), Variant::Integer(3));
+		assert_eq!(t("p1 * 10 + p0"), Variant::Integer(43));
+		assert_eq!(t("p1"), Variant::Integer(4));
+		assert_eq!(t("-p1"), Variant::Integer(-4));
+	}
+
+	#[test]
+	fn host_iter() {
+		let r = run("fn main()\n\treturn [1, \"two\", 3.5]").unwrap();
+		let v = ScriptIter::new(&r).unwrap().map(|v| v.to_string());
+		assert_eq!(v.collect::<Vec<_>>(), ["1", "two", "3.5"]);
+		let r = run("fn main()\n\treturn \"abc\"").unwrap();
+		assert_eq!(ScriptIter::new(&r).unwrap().count(), 3);
+		assert!(ScriptIter::new(&Variant::Bool(true)).is_err());
+	}
+
+	#[test]
+	fn array_callback() {
+		let t = |s: &str| run(s).unwrap().to_string();
+		let f = "\n\nfn double(x)\n\treturn x * 2\n\nfn odd(x)\n\treturn x % 2 == 1\n\nfn add(x, y)\n\treturn x + y";
+		let main = |s: &str| format!("fn main()\n\tvar a = [1, 2, 3]\n\treturn {}{}", s, f);
+		assert_eq!(t(&main("a.map(\"double\")")), "[2, 4, 6]");
+		assert_eq!(t(&main("a.filter(\"odd\")")), "[1, 3]");
+		assert_eq!(t(&main("a.reduce(\"add\", 10)")), "16");
+		assert_eq!(t(&main("[].map(\"double\")")), "[]");
+		assert!(run(&main("a.map(\"add\")")).is_err());
+		assert!(run(&main("a.filter(\"double\")")).is_err());
+		assert!(run(&main("a.map(\"nope\")")).is_err());
+	}
+
+	#[test]
+	fn bytes() {
+		let t = |s: &str| {
+			let s = format!(
+				"fn main()\n\tvar b = \"h\u{e9}\\\"\".to_bytes()\n\treturn {}",
+				s
+			);
+			run(&s).unwrap().to_string()
+		};
+		assert_eq!(t("b.to_str()"), "h\u{e9}\"");
+		assert_eq!(t("b"), r#"b"h\xc3\xa9\"""#);
+		assert_eq!(t("[b.len(), b[0], b[-1]]"), "[4, 104, 34]");
+		assert_eq!(
+			t("[b == \"h\u{e9}\\\"\".to_bytes(), b == \"h\".to_bytes()]"),
+			"[true, false]"
+		);
+		let s = "fn main()\n\tvar a = []\n\tfor x in \"ab\".to_bytes()\n\t\ta.push(x)\n\treturn a";
+		assert_eq!(run(s).unwrap().to_string(), "[97, 98]");
+		assert!(run("fn main()\n\treturn \"a\".to_bytes()[1]").is_err());
+		let src = "fn main(b)\n\treturn b.to_str()";
+		let class = compile(src);
+		let b = Variant::Bytes([0xff, b'a'][..].into());
+		let e = class.instance().call("main", &[&b], &Environment::new());
+		assert_eq!(e.unwrap_err().to_string(), "Bytes are not valid UTF-8");
+	}
+
+	#[test]
+	fn fmt_nested() {
+		let t = |s: &str| {
+			let s = format!("fn main()\n\tvar a = [1, \"two\", [3]]\n\t{}", s);
+			run(&s).unwrap().to_string()
+		};
+		assert_eq!(t("return a"), r#"[1, "two", [3]]"#);
+		assert_eq!(t("return {}"), "{}");
+		assert_eq!(t("return {\"a\": a}"), r#"{"a": [1, "two", [3]]}"#);
+		// Values that contain themselves are elided. Each cycle is removed again so the test
+		// doesn't leak.
+		let s = "a[2].push(a[2])\n\tvar s = \"{a[2]}\"\n\ta[2].pop()\n\treturn s";
+		assert_eq!(t(s), "[3, [...]]");
+		let s = "var d = {}\n\td[\"a\"] = d\n\tvar s = \"{d}\"\n\td.remove(\"a\")\n\treturn s";
+		assert_eq!(t(s), r#"{"a": {...}}"#);
+	}
+
+	#[test]
+	fn eq_nested() {
+		let t =
+			|s: &str| run(&format!("fn main()\n\treturn {}", s)).unwrap() == Variant::Bool(true);
+		assert!(t("[1, 2.0] == [1.0, 2]"));
+		assert!(!t("[1, 2] == [1]"));
+		assert!(!t("[1] == 1"));
+		assert!(t("{\"a\": [[1, 2.0]]} == {\"a\": [[1.0, 2]]}"));
+		assert!(!t("{\"a\": [[1, 2]]} == {\"a\": [[1]]}"));
+		assert!(!t("{\"a\": [1]} == [[1]]"));
+		let s = "fn main()\n\tvar f = [1]\n\tvar g = [1]\n\tf.push(f)\n\tg.push(g)\n\t\
+			var e = f == g\n\tf.pop()\n\tg.pop()\n\treturn e";
+		assert_eq!(run(s).unwrap(), Variant::Bool(true));
+	}
+
+	#[test]
+	fn copy() {
+		let orig = "[1, [2], {0: [3]}]";
+		let t = |s: &str| {
+			let s = format!(
+				"fn main()\n\tvar a = {}\n\tvar b = a.copy()\n\t{}\n\treturn a",
+				orig, s
+			);
+			run(&s).unwrap().to_string()
+		};
+		assert_eq!(t("b[0] = 4"), orig);
+		assert_eq!(t("b[1].push(5)"), orig);
+		assert_eq!(t("b[2][0].pop()"), orig);
+		assert_eq!(t("b[2][1] = 6"), orig);
+		assert_eq!(t("a[1].push(5)"), "[1, [2, 5], {0: [3]}]");
+		let s = "fn main()\n\tvar a = []\n\ta.push(a)\n\treturn a.copy()";
+		assert_eq!(run(s).unwrap().to_string(), "[[...]]");
+	}
+
+	#[test]
+	fn slice() {
+		let t = |s: &str| {
+			let s = format!("fn main()\n\tvar a = [1, 2, 3, 4]\n\treturn a.slice({})", s);
+			run(&s).unwrap().to_string()
+		};
+		assert_eq!(t("1, 3"), "[2, 3]");
+		assert_eq!(t("0, 4"), "[1, 2, 3, 4]");
+		assert_eq!(t("-100, 100"), "[1, 2, 3, 4]");
+		assert_eq!(t("-3, -1"), "[2, 3]");
+		assert_eq!(t("1, -1"), "[2, 3]");
+		assert_eq!(t("3, 1"), "[]");
+		assert_eq!(t("4, 10"), "[]");
+	}
+
+	#[test]
+	fn join() {
+		let t = |s: &str| run(&format!("fn main()\n\treturn {}", s)).map(|v| v.to_string());
+		assert_eq!(t("[\"a\", \"b\", \"c\"].join(\", \")").unwrap(), "a, b, c");
+		assert_eq!(
+			t("[1, \"b\", 2.5, true, [3]].join(\"-\")").unwrap(),
+			"1-b-2.5-true-[3]"
+		);
+		assert_eq!(t("[\"a\"].join(\", \")").unwrap(), "a");
+		assert_eq!(t("[].join(\", \")").unwrap(), "");
+		assert!(t("[1].join(2)").is_err());
+	}
+
+	#[test]
+	fn sort() {
+		let t = |s: &str| {
+			let s = format!("fn main()\n\tvar a = {}\n\ta.sort()\n\treturn a", s);
+			run(&s).map(|v| v.to_string())
+		};
+		assert_eq!(t("[3, -1, 2, 0]").unwrap(), "[-1, 0, 2, 3]");
+		assert_eq!(t("[2.5, -1.0, 0.5]").unwrap(), "[-1, 0.5, 2.5]");
+		assert_eq!(t("[2, 1.5, 1]").unwrap(), "[1, 1.5, 2]");
+		assert_eq!(t("[\"b\", \"c\", \"a\"]").unwrap(), r#"["a", "b", "c"]"#);
+		assert_eq!(t("[]").unwrap(), "[]");
+		assert!(t("[1, \"a\"]").is_err());
+		assert!(t("[0.0 / 0.0, \"a\"]").is_err());
+		// NaNs go last regardless of their sign while comparisons in scripts are still false
+		let nan = "(0.0 / 0.0)";
+		let s = format!("[1.0, {0}, -1, -{0}, 1.0 / 0.0, 0, -1.0 / 0.0]", nan);
+		assert_eq!(t(&s).unwrap(), "[-inf, -1, 0, 1, inf, NaN, NaN]");
+		let s = format!(
+			"fn main()\n\tvar n = {}\n\treturn [n < 1, n > 1, n == n]",
+			nan
+		);
+		assert_eq!(run(&s).unwrap().to_string(), "[false, false, false]");
+		// Enough elements for a sort that doesn't use insertion sort, which may panic on an
+		// inconsistent ordering
+		let mut v = (0..100)
+			.map(|i| format!("{}", (i * 37) % 101))
+			.collect::<Vec<_>>();
+		for i in (0..100).step_by(7) {
+			v[i] = if i % 2 == 0 {
+				nan.into()
+			} else {
+				format!("-{}", nan)
+			};
+		}
+		let sorted = t(&format!("[{}]", v.join(", "))).unwrap();
+		let sorted = sorted[1..sorted.len() - 1].split(", ").collect::<Vec<_>>();
+		let (numbers, nans) = sorted.split_at(100 - 15);
+		assert!(nans.iter().all(|&n| n == "NaN"), "{:?}", sorted);
+		let numbers = numbers.iter().map(|n| n.parse::<Integer>().unwrap());
+		assert!(numbers.clone().zip(numbers.skip(1)).all(|(a, b)| a < b));
+		// Elements that can't be compared with each other must give an error, not a panic
+		let mut seed = 1u64;
+		for _ in 0..20 {
+			let v = (0..40)
+				.map(|_| {
+					seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+					let x = (seed >> 33) % 100;
+					match (seed >> 20) % 3 {
+						0 => format!("{}", x),
+						1 => format!("\"{}\"", x),
+						_ => format!("[{}, \"a\"]", x),
+					}
+				})
+				.collect::<Vec<_>>();
+			assert!(t(&format!("[{}]", v.join(", "))).is_err());
+		}
+		let s = "fn main()\n\tvar a = [1, 3, 2]\n\ta.reverse()\n\treturn a";
+		assert_eq!(run(s).unwrap().to_string(), "[2, 3, 1]");
+	}
+
+	#[test]
+	fn dictionary_get() {
+		let t = |s: &str| {
+			let s = format!("fn main()\n\tvar d = {{\"a\": 1}}\n\treturn {}", s);
+			run(&s).unwrap().to_string()
+		};
+		assert_eq!(t("d.get(\"a\", 0)"), "1");
+		assert_eq!(t("d.get(\"b\", 0)"), "0");
+		assert_eq!(t("d.get(1, \"none\")"), "none");
+		assert_eq!(t("d.has(\"a\")"), "true");
+		assert_eq!(t("d.has(\"b\")"), "false");
+	}
+
+	#[test]
+	fn dictionary_missing_key() {
+		let t = |s: &str| {
+			let s = format!("fn main()\n\tvar d = {{\"a\": none, 1: 2}}\n\treturn {}", s);
+			run(&s)
+		};
+		assert_eq!(t("d[\"a\"]").unwrap(), Variant::None);
+		let e = t("d[\"b\"]").unwrap_err();
+		let e = e.downcast_ref::<RunError>().unwrap().cause();
+		assert!(matches!(e.downcast_ref(), Some(CallError::KeyNotFound(k)) if k == "b"));
+		assert_eq!(e.to_string(), "Key not found: b");
+		assert_eq!(t("d[2]").unwrap_err().to_string(), "Key not found: 2");
+	}
+
+	#[test]
+	fn dictionary_order() {
+		let t = |s: &str| {
+			let d = "{5: none, \"b\": none, 1: none, \"a\": none}";
+			run(&format!("fn main()\n\tvar d = {}\n\t{}\n\treturn d", d, s))
+				.unwrap()
+				.to_string()
+		};
+		assert_eq!(t("pass"), r#"{5: none, "b": none, 1: none, "a": none}"#);
+		assert_eq!(
+			t("d[5] = true"),
+			r#"{5: true, "b": none, 1: none, "a": none}"#
+		);
+		assert_eq!(
+			t("d[0] = true"),
+			r#"{5: none, "b": none, 1: none, "a": none, 0: true}"#
+		);
+		assert_eq!(t("d.remove(1)"), r#"{5: none, "b": none, "a": none}"#);
+	}
+
+	#[test]
+	fn array_iter() {
+		let t = |s: &str| {
+			let s = format!(
+				"fn main()\n\tvar a = [1, \"b\", [3]]\n\tvar r = []\n\t{}\n\treturn r",
+				s
+			);
+			run(&s).unwrap().to_string()
+		};
+		assert_eq!(t("for x in a\n\t\tr.push(x)"), r#"[1, "b", [3]]"#);
+		assert_eq!(t("for x in []\n\t\tr.push(x)"), "[]");
+		assert_eq!(
+			t("for x in a\n\t\tfor y in a\n\t\t\tif y == \"b\"\n\t\t\t\tbreak\n\t\t\tr.push([x, y])"),
+			r#"[[1, 1], ["b", 1], [[3], 1]]"#
+		);
+		assert_eq!(
+			t("for x in a\n\t\tfor c in \"xy\"\n\t\t\tr.push(c)\n\t\t\tbreak\n\t\tr.push(x)"),
+			r#"[x, 1, x, "b", x, [3]]"#
+		);
+		// The array isn't borrowed while iterating, so it can be modified. Elements that are
+		// added are visited too.
+		assert_eq!(
+			t("for x in a\n\t\tr.push(x)\n\t\tif x == 1\n\t\t\ta.push(2)"),
+			r#"[1, "b", [3], 2]"#
+		);
+		assert_eq!(t("for x in a\n\t\ta.pop()\n\t\tr.push(x)"), r#"[1, "b"]"#);
+	}
+
+	#[test]
+	fn object_into_inner() {
+		let src =
+			"var a\n\nfn unique()\n\treturn [1, 2]\n\nfn shared()\n\tself.a = [3]\n\treturn self.a";
+		let class = compile(src);
+		let instance = class.instance();
+		let env = Environment::new();
+		let get = |f| instance.call(f, &[], &env).unwrap().into_object().unwrap();
+
+		let array = get("unique")
+			.try_into_inner::<Array<Variant>>()
+			.ok()
+			.unwrap();
+		let v = array.try_into_vec().unwrap();
+		assert_eq!(v, [Variant::Integer(1), Variant::Integer(2)]);
+
+		let obj = get("shared");
+		assert!(get("unique")
+			.try_into_inner::<Dictionary<Variant>>()
+			.is_err());
+		let obj = obj.try_into_inner::<Array<Variant>>().unwrap_err();
+		let array = obj.cast::<Array<Variant>>().unwrap().clone();
+		assert_eq!(array.try_into_vec().unwrap_err().len(), 1);
+	}
+
+	#[test]
+	fn object_add() {
+		let t = |s: &str| {
+			let src = format!(
+				"fn main()\n\tvar a = [1, 2]\n\tvar d = {{1: 2, 3: 4}}\n\t{}",
+				s
+			);
+			run(&src).map(|v| v.to_string())
+		};
+		assert_eq!(t("return a + [3, 4]").unwrap(), "[1, 2, 3, 4]");
+		assert_eq!(t("return a + a").unwrap(), "[1, 2, 1, 2]");
+		assert_eq!(
+			t("var b = a + []\n\tb.push(3)\n\treturn [a, b]").unwrap(),
+			"[[1, 2], [1, 2, 3]]"
+		);
+		assert_eq!(t("return d + {3: 5, 6: 7}").unwrap(), "{1: 2, 3: 5, 6: 7}");
+		assert_eq!(
+			t("var e = d + {}\n\te[1] = 0\n\treturn [d, e]").unwrap(),
+			"[{1: 2, 3: 4}, {1: 0, 3: 4}]"
+		);
+		assert_eq!(
+			t("return a + d").unwrap_err().to_string(),
+			"Type is not compatible"
+		);
+		assert_eq!(
+			t("return a + 1").unwrap_err().to_string(),
+			"Cannot add Array and Integer"
+		);
+	}
+
+	#[test]
+	fn try_expression() {
+		let t = |s: &str| {
+			run(&format!(
+				"{}\n\nfn fail(x)\n\tfor i in [1, 2]\n\t\tfor j in 3\n\t\t\tx = x / 0",
+				s
+			))
+			.unwrap()
+		};
+		assert_eq!(t("fn main()\n\treturn try (1 / 0)"), Variant::None);
+		assert_eq!(t("fn main()\n\treturn try (1 / 1)"), Variant::Integer(1));
+		assert_eq!(
+			t("fn main()\n\tvar x = 5\n\tx = try (x - \"a\")\n\treturn x"),
+			Variant::None
+		);
+		assert_eq!(t("fn main()\n\treturn try (\"a\" - 1)"), Variant::None);
+		// Iterators left behind by the failed call are removed
+		assert_eq!(
+			t("fn main()\n\tvar r = []\n\tfor i in [1, 2]\n\t\tfor j in 2\n\t\t\tr.push([try self.fail(i), i, j])\n\treturn r")
+				.to_string(),
+			"[[none, 1, 0], [none, 1, 1], [none, 2, 0], [none, 2, 1]]"
+		);
+		// Errors outside the expression are not caught
+		assert!(run("fn main()\n\tvar x = try (1 / 0)\n\treturn 1 / 0").is_err());
+		// The result may be unused
+		assert_eq!(
+			t("fn main()\n\ttry self.fail(1)\n\ttry (1 / 0)\n\treturn 2"),
+			Variant::Integer(2)
+		);
+	}
+
+	#[test]
+	fn dictionary_iter() {
+		let t = |s: &str| {
+			let d = "{5: \"x\", \"b\": none, 1.5: [1]}";
+			let s = format!(
+				"fn main()\n\tvar d = {}\n\tvar r = []\n\t{}\n\treturn r",
+				d, s
+			);
+			run(&s).unwrap().to_string()
+		};
+		assert_eq!(t("for k in d\n\t\tr.push(k)"), r#"[5, "b", 1.5]"#);
+		assert_eq!(
+			t("for kv in d.items()\n\t\tr.push(kv)"),
+			r#"[[5, "x"], ["b", none], [1.5, [1]]]"#
+		);
+		assert_eq!(
+			t("for kv in d.items()\n\t\tr.push(d[kv[0]] == kv[1])"),
+			"[true, true, true]"
+		);
+		// The pairs are a snapshot, so the dictionary can be modified while iterating them
+		assert_eq!(
+			t("for kv in d.items()\n\t\td.remove(kv[0])\n\tr.push(d.len())"),
+			"[0]"
+		);
+		assert_eq!(t("r = {}.items()"), "[]");
+		assert!(run("fn main()\n\treturn {}.items(1)").is_err());
+	}
+
+	#[test]
+	fn coalesce() {
+		let t = |s: &str| {
+			let s = format!("fn main()\n\tvar n = [].pop()\n\tvar x = 1\n\t{}", s);
+			run(&s).unwrap().to_string()
+		};
+		assert_eq!(t("return n ?? 2"), "2");
+		assert_eq!(t("return x ?? 2"), "1");
+		assert_eq!(t("return n ?? n ?? 3"), "3");
+		assert_eq!(t("return n ?? x + 4"), "5");
+		assert_eq!(t("x = n ?? x\n\treturn x"), "1");
+		// The right operand is only evaluated if the left operand is none
+		assert_eq!(t("return x ?? env.undefined()"), "1");
+		assert!(run("fn main()\n\treturn [].pop() ?? env.undefined()").is_err());
+		// The result may be unused
+		assert_eq!(t("n ?? x\n\tx ?? env.undefined()\n\treturn n ?? x"), "1");
+
+		// A single `?` is not an operator
+		let e = compile_error("fn main()\n\treturn 1 ? 2");
+		assert_eq!(e, "Invalid operator at line 2, column 11");
+	}
+
+	#[test]
+	fn none() {
+		let t = |s: &str| run(&format!("fn main()\n\tvar x = [].pop()\n\treturn {}", s)).unwrap();
+		assert_eq!(t("none == none"), Variant::Bool(true));
+		assert_eq!(t("5 == none"), Variant::Bool(false));
+		assert_eq!(t("none != 5"), Variant::Bool(true));
+		assert_eq!(t("x == none"), Variant::Bool(true));
+		assert_eq!(t("x.is_none()"), Variant::Bool(true));
+		assert_eq!(t("(5).is_none()"), Variant::Bool(false));
+		assert_eq!(t("none ?? 3"), Variant::Integer(3));
+		assert!(matches!(t("none"), Variant::None));
+	}
+
+	#[test]
+	fn default_parameters() {
+		let f = "\n\nfn f(a, b = 10, c = \"x\")\n\treturn a + b + c";
+		let t = |s: &str| run(&format!("fn main()\n\treturn self.{}{}", s, f));
+		assert_eq!(t("f(1)").unwrap().to_string(), "11x");
+		assert_eq!(t("f(1, 2)").unwrap().to_string(), "3x");
+		assert_eq!(t("f(1, 2, \"y\")").unwrap().to_string(), "3y");
+
+		let class = compile(&f[2..]);
+		let call = |args: &[&Variant]| class.instance().call("f", args, &Environment::new());
+		assert_eq!(call(&[&Variant::Integer(2)]).unwrap().to_string(), "12x");
+		assert!(call(&[]).is_err());
+
+		assert!(!compiles("fn f(a = 1, b)\n\tpass"));
+		assert!(!compiles("fn f(a = 1 + 2)\n\tpass"));
+		let e = compile_error("fn f(a, b = -a)\n\tpass");
+		assert_eq!(e, "Default values must be literals at line 1, column 12");
+		assert!(compiles("fn f(a = -1, b = none)\n\tpass"));
+		assert!(!compiles(&format!("fn main()\n\treturn self.f(){}", f)));
+		assert!(!compiles(&format!(
+			"fn main()\n\treturn self.f(1, 2, 3, 4){}",
+			f
+		)));
+	}
+
+	#[test]
+	fn variadic_parameters() {
+		let f = "\n\nfn sum(a, b = 10, *nums)\n\tfor n in nums\n\t\ta += n\n\treturn a + b";
+		let t = |s: &str| run(&format!("fn main()\n\treturn self.{}{}", s, f));
+		assert_eq!(t("sum(1)").unwrap().to_string(), "11");
+		assert_eq!(t("sum(1, 2)").unwrap().to_string(), "3");
+		assert_eq!(t("sum(1, 2, 3, 4, 5)").unwrap().to_string(), "15");
+
+		let class = compile(&f[2..]);
+		let call = |args: &[&Variant]| class.instance().call("sum", args, &Environment::new());
+		let n = |i| Variant::Integer(i);
+		assert_eq!(call(&[&n(1), &n(2), &n(3)]).unwrap().to_string(), "6");
+		assert!(call(&[]).is_err());
+
+		assert!(compiles("fn f(*a)\n\treturn a"));
+		assert!(!compiles("fn f(*a, b)\n\tpass"));
+		assert!(!compiles("fn f(*a = 1)\n\tpass"));
+		assert!(!compiles(&format!("fn main()\n\treturn self.sum(){}", f)));
+	}
+
+	#[test]
+	fn unreachable_code() {
+		let parse = |s: &str| {
+			parse::<Variant, ()>(s, &mut Default::default(), ())
+				.map(|_| ())
+				.map_err(|e| (e.line, e.column))
+		};
+		assert_eq!(parse("fn f()\n\treturn 1\n\tvar a = 2"), Err((2, 1)));
+		assert_eq!(
+			parse("fn f()\n\twhile true\n\t\tbreak\n\t\tenv.f()"),
+			Err((3, 2))
+		);
+		assert_eq!(
+			parse("fn f()\n\tfor i in 3\n\t\tcontinue\n\t\treturn"),
+			Err((3, 2))
+		);
+		assert_eq!(parse("fn f()\n\tif true\n\t\treturn 2\n\treturn 1"), Ok(()));
+	}
+
+	#[test]
+	fn interned_across_functions() {
+		let src = "fn main()\n\treturn self.a() + self.b()\n\nfn a()\n\treturn [1].len()\n\nfn b()\n\treturn \"len\".len()";
+		let calls = Recorder::default();
+		let mut string_map = Default::default();
+		let class = parse::<Variant, _>(src, &mut string_map, calls.clone())
+			.unwrap_or_else(|e| panic!("{}", e));
+		let r = class.instance().call("main", &[], &Environment::new());
+		assert_eq!(r.unwrap(), Variant::Integer(4));
+
+		let recording = calls.0.borrow();
+		let calls = &recording.calls;
+		assert_eq!(calls.len(), 2);
+		let len = string_map.get("len").unwrap();
+		assert!(calls.iter().all(|c| Rc::ptr_eq(c, len)));
+	}
+
+	#[test]
+	fn condition_register_preserved() {
+		let t = |src: &str, arg: Variant| {
+			let peek = Recorder::default();
+			let class = peek.compile(src);
+			let r = class.instance().call("main", &[&arg], &Environment::new());
+			let regs = peek
+				.0
+				.borrow()
+				.variables
+				.iter()
+				.map(|v| v[0].clone())
+				.collect::<Vec<_>>();
+			(r, regs)
+		};
+		for src in [
+			"fn main(a)\n\tif a\n\t\treturn 1\n\treturn 2",
+			"fn main(a)\n\twhile a\n\t\treturn 1\n\treturn 2",
+		] {
+			let (r, regs) = t(src, Variant::Integer(5));
+			assert!(r.is_err());
+			assert!(regs.iter().all(|v| *v == Variant::Integer(5)), "{:?}", regs);
+			let (r, regs) = t(src, Variant::Bool(false));
+			assert_eq!(r.unwrap(), Variant::Integer(2));
+			assert!(
+				regs.iter().all(|v| *v == Variant::Bool(false)),
+				"{:?}",
+				regs
+			);
+		}
+	}
+
+	#[test]
+	fn logic_chain_registers_preserved() {
+		for (src, results) in [
+			("a and b and c and d", [1, 2, 2, 2]),
+			("a or b or c or d", [1, 1, 1, 2]),
+			("a and b or c and d", [1, 2, 1, 2]),
+		] {
+			let src = format!(
+				"fn main(a, b, c, d)\n\tif {}\n\t\treturn 1\n\treturn 2",
+				src
+			);
+			let peek = Recorder::default();
+			let class = peek.compile(&src);
+			let args = [
+				[true, true, true, true],
+				[true, true, true, false],
+				[false, true, true, true],
+				[false, false, false, false],
+			];
+			for (args, &result) in args.iter().zip(&results) {
+				let args = args.iter().map(|&b| Variant::Bool(b)).collect::<Vec<_>>();
+				let refs = args.iter().collect::<Vec<_>>();
+				peek.0.borrow_mut().variables.clear();
+				let r = class.instance().call("main", &refs, &Environment::new());
+				assert_eq!(r.unwrap(), Variant::Integer(result), "{} {:?}", src, args);
+				let peeks = peek.0.borrow();
+				assert!(
+					peeks.variables.iter().all(|p| p[..4] == args[..]),
+					"{}",
+					src
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn call_undefined_function() {
+		let class = compile("fn main()\n\treturn 1");
+		let instance = class.instance();
+		let env = Environment::new();
+		let e = instance.call("mian", &[], &env).unwrap_err();
+		assert!(matches!(
+			e.downcast_ref(),
+			Some(CallError::UndefinedFunction(name)) if name == "mian"
+		));
+		assert_eq!(e.to_string(), "Undefined function 'mian'");
+		assert_eq!(
+			instance.call("main", &[], &env).unwrap(),
+			Variant::Integer(1)
+		);
+	}
+
+	#[test]
+	fn function_names() {
+		let src = "fn on_init()\n\tpass\n\nfn on_update(delta, scale = 1.0)\n\tpass";
+		let class = compile(src);
+		assert_eq!(
+			class.function_names().collect::<Vec<_>>(),
+			[
+				("on_init", Arity::Exact(0)),
+				("on_update", Arity::Range(1, 2))
+			]
+		);
+		let src = "fn log(level, *messages)\n\tpass";
+		let class = compile(src);
+		assert_eq!(
+			class.function_names().collect::<Vec<_>>(),
+			[("log", Arity::Range(1, usize::MAX))]
+		);
+	}
+
+	#[cfg(feature = "debug-info")]
+	#[test]
+	fn variable_names() {
+		let names = Recorder::default();
+		let src = "fn main(a)\n\tvar b = a * 2\n\tfor i in 3\n\t\tb += i\n\treturn b";
+		let class = names.compile(src);
+		let r = class
+			.instance()
+			.call("main", &[&Variant::Integer(5)], &Environment::new());
+		assert_eq!(r.unwrap(), Variant::Integer(13));
+		let seen = |name: &str, value: Integer| {
+			let entry = format!("{} = {}", name, value);
+			names.0.borrow().names.contains(&entry)
+		};
+		assert!(seen("a", 5));
+		assert!(seen("b", 10));
+		assert!(seen("b", 13));
+		assert!(seen("i", 2));
+		assert!(!seen("i", 3));
+	}
+
+	#[test]
+	fn switch() {
+		// `k == x` isn't turned into a jump table, `x == k` is.
+		let chain = |cond: &dyn Fn(Integer) -> String| {
+			let mut src = String::from("fn main(x)\n\tif x == -1\n\t\treturn -1\n");
+			for (i, k) in [3, 0, 9, 1, 4, 3, 7, 2, 6, 10].iter().enumerate() {
+				src += &format!("\telif {}\n\t\treturn {}\n", cond(*k), i);
+			}
+			src + "\telse\n\t\treturn 100"
+		};
+		let switch = compile(&chain(&|k| format!("x == {}", k)));
+		let linear = compile(&chain(&|k| format!("{} == x", k)));
+		let has_switch = |c: &Class<Variant, ()>| c.disassemble().contains("switch");
+		assert!(has_switch(&switch));
+		assert!(!has_switch(&linear));
+
+		let call = |c: &Class<Variant, ()>, x: &Variant| {
+			c.instance()
+				.call("main", &[x], &Environment::new())
+				.unwrap()
+		};
+		let mut args = (-3..13).map(Variant::Integer).collect::<Vec<_>>();
+		args.extend([Variant::Real(4.0), Variant::Real(4.5), Variant::Bool(true)]);
+		args.push(Variant::String("x".into()));
+		for x in args.iter() {
+			assert_eq!(call(&switch, x), call(&linear, x), "{:?}", x);
+		}
+		assert_eq!(call(&switch, &Variant::Integer(3)), Variant::Integer(0));
+		assert_eq!(call(&switch, &Variant::Integer(10)), Variant::Integer(9));
+		assert_eq!(call(&switch, &Variant::Integer(5)), Variant::Integer(100));
+	}
+
+	#[test]
+	fn interned_dictionary_keys() {
+		let src = "fn main()\n\tvar d = {\"a\": 0}\n\tfor i in 1000\n\t\td[\"a\"] = d[\"a\"] + i\n\t\td[\"b\"] = d[\"a\"]\n\treturn d";
+		let mut string_map = Default::default();
+		let class =
+			parse::<Variant, ()>(src, &mut string_map, ()).unwrap_or_else(|e| panic!("{}", e));
+		let d = class
+			.instance()
+			.call("main", &[], &Environment::new())
+			.unwrap();
+
+		// The keys are the same strings as the interned constants.
+		let keys = d.iter().unwrap().collect::<Vec<_>>();
+		assert_eq!(keys.len(), 2);
+		for (k, s) in keys.iter().zip(["a", "b"]) {
+			match k {
+				Variant::String(k) => assert!(Rc::ptr_eq(k, string_map.get(s).unwrap())),
+				k => panic!("{:?}", k),
+			}
+		}
+		// Strings that aren't interned still find the same entries.
+		let key = Variant::String(String::from("b").into());
+		assert_eq!(d.index(&key).unwrap(), Variant::Integer(499500));
+	}
+
+	#[test]
+	fn discard_variable() {
+		let t = |s: &str| run(&format!("fn main()\n\tvar n = 0\n{}\n\treturn n", s));
+		assert_eq!(t("\tfor _ in 5\n\t\tn += 1").unwrap(), Variant::Integer(5));
+		assert_eq!(
+			t("\tfor _ in 3\n\t\tfor _ in 4\n\t\t\tn += 1").unwrap(),
+			Variant::Integer(12)
+		);
+		let src = "\tvar a = []\n\tvar _ = a.push(1)\n\tvar _\n\t_ = a.push(2)\n\tn = a.len()";
+		assert_eq!(t(src).unwrap(), Variant::Integer(2));
+
+		assert!(!compiles("fn main()\n\tfor _ in 5\n\t\treturn _"));
+		assert!(!compiles("fn main()\n\tvar _ = 1\n\treturn _"));
+		assert!(!compiles("fn main()\n\t_ += 1"));
+	}
+
+	#[test]
+	fn self_move_removed() {
+		let src = "fn main(a)\n\tvar n = 0\n\twhile n < 5\n\t\tn = n\n\t\tn += 1\n\tif a\n\t\ta = a\n\telse\n\t\tn = n\n\treturn n";
+		let class = compile(src);
+		let code = class.disassemble();
+		assert!(!code.contains("move    0, 0"), "{}", code);
+		assert!(!code.contains("move    1, 1"), "{}", code);
+		for a in [true, false] {
+			let r = class
+				.instance()
+				.call("main", &[&Variant::Bool(a)], &Environment::new());
+			assert_eq!(r.unwrap(), Variant::Integer(5));
+		}
+	}
+
+	#[test]
+	fn while_layout() {
+		let src = "fn main()\n\tvar i = 0\n\twhile i < 10\n\t\ti += 1\n\treturn i";
+		let class = compile(src);
+		let code = class.disassemble();
+		// One `Jmp` into the condition, then the condition with a single jump back at the bottom.
+		// The constants used in the loop are loaded once before it.
+		let ops = code
+			.lines()
+			.filter_map(|l| {
+				let mut w = l.split_whitespace();
+				w.next()?.parse::<usize>().ok()?;
+				w.find(|w| !w.ends_with(':'))
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			ops,
+			["loadc", "loadc", "loadc", "jp", "add", "less", "jpnif", "ret"],
+			"{}",
+			code
+		);
+		let r = class.instance().call("main", &[], &Environment::new());
+		assert_eq!(r.unwrap(), Variant::Integer(10));
+	}
+
+	#[test]
+	fn disassemble() {
+		let src = "fn main()\n\tvar i = 0\n\twhile i < 10\n\t\ti += 1\n\treturn i";
+		let class = compile(src);
+		let expected = "\
+main:
+  parameters 0, registers 4
+  const 0 = 0
+  const 1 = 1
+  const 2 = 10
+   0        loadc   2, 1
+   1        loadc   3, 2
+   2        loadc   0, 0
+   3        jp      L1
+   4  L0:   add     0, 0, 2
+   5  L1:   less    1, 0, 3
+   6        jpnif   1, L0
+   7        ret     0
+";
+		assert_eq!(class.disassemble(), expected);
+	}
+
+	#[test]
+	fn debug_jump_targets() {
+		let src = "fn main()\n\tvar i = 0\n\twhile i < 10\n\t\ti += 1\n\treturn i";
+		let class = compile(src);
+		let expected = "[parameters: 0, mutable variables: 4, \
+consts: 0: 0, 1: 1, 2: 10, \
+code: 0: loadc   2, 1, 1: loadc   3, 2, 2: loadc   0, 0, 3: jp      5, \
+4: add     0, 0, 2, 5: less    1, 0, 3, 6: jpnif   1, 4, 7: ret     0]";
+		assert_eq!(format!("{:?}", class), expected);
+	}
+
+	#[test]
+	fn loop_constants() {
+		let src = "fn main()
+	var s = 0
+	for i in 4
+		var a = [1, 2]
+		var j = 0
+		while j < 3
+			j += 1
+			if j == 2
+				continue
+			s += a[1] * 10
+		s += i
+	return s";
+		let class = compile(src);
+		let code = class.disassemble();
+		// No constant is loaded after the first instruction that isn't a `LoadConst`
+		let mut ops = code
+			.lines()
+			.filter_map(|l| {
+				let mut w = l.split_whitespace();
+				w.next()?.parse::<usize>().ok()?;
+				w.find(|w| !w.ends_with(':'))
+			})
+			.skip_while(|&op| op == "loadc");
+		assert!(ops.all(|op| op != "loadc"), "{}", code);
+		let r = class.instance().call("main", &[], &Environment::new());
+		assert_eq!(r.unwrap(), Variant::Integer(166));
+	}
+
+	#[test]
+	fn disassemble_jump_targets() {
+		let src = "fn main()\n\tvar n = 0\n\tfor x in [1, 2, 3, 4]\n\t\tif x > 3\n\t\t\tbreak\n\t\tvar i = 0\n\t\twhile i < x\n\t\t\ti += 1\n\t\t\tif i == 2\n\t\t\t\tcontinue\n\t\t\tn += i\n\treturn n";
+		let ips = Recorder::default();
+		let class = ips.compile(src);
+		let r = class.instance().call("main", &[], &Environment::new());
+		assert_eq!(r.unwrap(), Variant::Integer(6));
+
+		// Map each label to the index of the instruction it is on and each jump to its label
+		let code = class.disassemble();
+		let (mut labels, mut jumps) = (FxHashMap::default(), FxHashMap::default());
+		for line in code.lines().filter(|l| l.starts_with(' ')) {
+			let mut words = line.split_whitespace();
+			let ip = match words.next().map(str::parse::<u32>) {
+				Some(Ok(ip)) => ip,
+				_ => continue,
+			};
+			let words = words.collect::<Vec<_>>();
+			if let Some(label) = words.first().and_then(|w| w.strip_suffix(':')) {
+				labels.insert(label.to_string(), ip);
+			}
+			if let Some(target) = words.last().filter(|w| w.starts_with('L')) {
+				jumps.insert(ip, target.to_string());
+			}
+		}
+
+		// Every time the next instruction isn't the one after, a jump to it must have occured
+		let recording = ips.0.borrow();
+		let ips = &recording.ips;
+		let mut taken = 0;
+		for w in ips.windows(2) {
+			if w[1] != w[0] + 1 {
+				let target = jumps
+					.get(&w[0])
+					.unwrap_or_else(|| panic!("{}\n{}", w[0], code));
+				assert_eq!(labels[target], w[1], "{}", code);
+				taken += 1;
+			}
+		}
+		assert!(taken > 10, "{}", taken);
+	}
+
+	#[test]
+	fn run_error_location() {
+		let src = "fn main()\n\tvar n = 2\n\treturn self.a(n)\n\nfn a(x)\n\tvar y = x * 2\n\treturn self.b(y)\n\nfn b(x)\n\tx += 1\n\tx *= 3\n\treturn x - \"y\"";
+		let err = run(src).unwrap_err();
+		let mut trace = Vec::new();
+		let mut e: &(dyn std::error::Error + 'static) = err.as_ref();
+		while let Some(r) = e.downcast_ref::<RunError>() {
+			trace.push((&**r.function().unwrap(), r.ip().unwrap()));
+			e = r.error();
+		}
+		assert_eq!(trace, [("main", 1), ("a", 2), ("b", 5)]);
+		assert_eq!(e.to_string(), "Cannot subtract Integer and String");
+		assert_eq!(err.to_string(), "Cannot subtract Integer and String");
+	}
+
+	#[test]
+	fn run_error_display() {
+		assert_eq!(RunError::DivideByZero.to_string(), "Division by zero");
+		assert_eq!(RunError::Overflow.to_string(), "Integer overflow");
+		assert_eq!(format!("{:#}", RunError::Overflow), "Integer overflow");
+		let src = "fn main()\n\treturn self.a(0)\n\nfn a(x)\n\treturn 1 / x";
+		let err = run(src).unwrap_err();
+		assert_eq!(err.to_string(), "Division by zero");
+		assert_eq!(
+			format!("{:#}", err),
+			"Division by zero\n  in main at instruction 1\n  in a at instruction 1"
+		);
+		let err = run("fn main()\n\treturn env.nope()").unwrap_err();
+		assert_eq!(
+			format!("{:#}", err),
+			"Undefined function 'nope'\n  in main at instruction 0"
+		);
+	}
+
+	#[test]
+	fn call_error_display() {
+		let t = |e: &str| {
+			let s = format!("fn main()\n\tvar i = 1\n\tvar s = \"a\"\n\treturn {}", e);
+			run(&s).unwrap_err().to_string()
+		};
+		assert_eq!(t("i + [2]"), "Cannot add Integer and Array");
+		assert_eq!(t("s * 2.5"), "Cannot multiply String and Real");
+		assert_eq!(
+			t("{} % none"),
+			"Cannot take the remainder of Dictionary and None"
+		);
+		assert_eq!(t("(i == 1) << i"), "Cannot shift Bool and Integer");
+		assert_eq!(t("i.nope()"), "Undefined function 'nope'");
+		assert_eq!(t("[].nope(1)"), "Undefined function 'nope'");
+		assert_eq!(t("env.nope()"), "Undefined function 'nope'");
+		assert_eq!(t("-s"), "Type is not compatible");
+		let e = CallError::incompatible_types("add", "Integer", "String");
+		assert_eq!(e.to_string(), "Cannot add Integer and String");
+	}
+
+	#[test]
+	fn shift_right_unsigned() {
+		let t = |e: &str| {
+			run(&format!("fn main()\n\tvar x = -8\n\treturn {}", e)).map(|v| v.to_string())
+		};
+		assert_eq!(t("-8 >> 1").unwrap(), "-4");
+		assert_eq!(t("x >> 1").unwrap(), "-4");
+		let max = (Integer::MAX - 3).to_string();
+		assert_eq!(t("-8 >>> 1").unwrap(), max);
+		assert_eq!(t("x >>> 1").unwrap(), max);
+		assert_eq!(t("[8 >>> 1, -x >>> 2, x >>> 0]").unwrap(), "[4, 2, -8]");
+		assert_eq!(t("1 + 15 >>> 2").unwrap(), "4");
+		assert_eq!(t("x >>> 1000").unwrap_err().to_string(), "Integer overflow");
+		// The specialized variant must not panic either
+		let x = specialized::CopyVariant::Integer(-8);
+		for s in [64, 1000, -1] {
+			let e = x.rhs_unsigned(&specialized::CopyVariant::Integer(s));
+			assert_eq!(e.unwrap_err().to_string(), "Integer overflow");
+		}
+		assert_eq!(
+			t("x >>> 0.5").unwrap_err().to_string(),
+			"Cannot shift Integer and Real"
+		);
+	}
+
+	#[test]
+	fn real_special_values() {
+		// Dividing reals by zero doesn't fail, both when folded and at runtime
+		let src = "fn main()\n\tvar z = 0.0\n\tvar i = 2\n\treturn [(1.0 / 0.0).is_infinite(), (0.0 / 0.0).is_nan(), (1.0 / z).is_infinite(), (z / z).is_finite(), i.is_finite()]";
+		assert_eq!(
+			run(src).unwrap().to_string(),
+			"[true, true, true, false, true]"
+		);
+	}
+
+	#[test]
+	fn arithmetic_faults() {
+		let t = |e: &str| {
+			let src = format!("fn main(x)\n\treturn {}", e);
+			let class = compile(&src);
+			let x = Variant::Integer(0);
+			let err = class
+				.instance()
+				.call("main", &[&x], &Environment::new())
+				.unwrap_err();
+			match RunError::cause_of(&*err) {
+				Some(RunError::DivideByZero) => "zero",
+				Some(RunError::Overflow) => "overflow",
+				_ => panic!("{:?}", err),
+			}
+		};
+		assert_eq!(t("1 / x"), "zero");
+		assert_eq!(t("1 % x"), "zero");
+		// Faults without a location are their own error
+		let e = RunError::Overflow;
+		assert_eq!((e.function(), e.ip()), (None, None));
+		assert!(matches!(e.error().downcast_ref(), Some(RunError::Overflow)));
+		assert!(matches!(RunError::cause_of(&e), Some(RunError::Overflow)));
+		assert_eq!(t(&format!("{} + 1 + x", Integer::MAX)), "overflow");
+		assert_eq!(t(&format!("x - 2 - {}", Integer::MAX)), "overflow");
+		assert_eq!(t(&format!("(x + {}) * 2", Integer::MAX)), "overflow");
+		assert_eq!(
+			t(&format!("(x - {} - 1) / (x - 1)", Integer::MAX)),
+			"overflow"
+		);
+		assert_eq!(t(&format!("-(x - {} - 1)", Integer::MAX)), "overflow");
+		assert_eq!(t("1 << (x + 200)"), "overflow");
+		assert_eq!(
+			run("fn main()\n\treturn (-5).pow(99)")
+				.unwrap_err()
+				.to_string(),
+			"Integer overflow"
+		);
+		let e = run("fn main()\n\treturn (-5).pow(99)").unwrap_err();
+		assert!(RunError::cause_of(&*e).is_none());
+	}
+
+	#[test]
+	fn short_circuit() {
+		let t = |body: &str| {
+			let src = format!("fn main(t, f, x)\n\t{}", body);
+			let class = compile(&src);
+			let args = [
+				Variant::Bool(true),
+				Variant::Bool(false),
+				Variant::Integer(0),
+			];
+			class
+				.instance()
+				.call("main", &[&args[0], &args[1], &args[2]], &Environment::new())
+				.unwrap()
+				.to_string()
+		};
+		assert_eq!(t("return t and not f"), "true");
+		assert_eq!(t("return t && f"), "false");
+		assert_eq!(t("return f or t"), "true");
+		assert_eq!(t("return f || not t"), "false");
+		// The right operand must not be evaluated if the left operand decides the result
+		assert_eq!(t("return f and 1 / x == 0"), "false");
+		assert_eq!(t("return t or 1 / x == 0"), "true");
+		// The right operand may read the register the result is stored in
+		assert_eq!(t("x = t\n\tx = f or x\n\treturn x"), "true");
+		assert_eq!(t("x = f\n\tx = t and x\n\treturn x"), "false");
+		// The result may be unused
+		assert_eq!(t("t and false\n\tt or 1 / x == 0\n\treturn x"), "0");
+		assert_eq!(t("if t and not f\n\t\treturn 1\n\treturn 2"), "1");
+		assert_eq!(t("while f or x < 3\n\t\tx += 1\n\treturn x"), "3");
+	}
+
+	#[test]
+	fn truthy_conditions() {
+		let run = |cond: &str, truthy: bool| {
+			let src = format!(
+				"fn main()\n\tvar n = 0\n\tif {0}\n\t\tn += 1\n\twhile {0}\n\t\tn += 1\n\t\tif n >= 3\n\t\t\tbreak\n\treturn n",
+				cond
+			);
+			let class = compile(&src);
+			let mut env = Environment::new();
+			env.set_truthy(truthy);
+			class.instance().call("main", &[], &env)
+		};
+		let t = |cond| run(cond, true).unwrap().to_string();
+		assert_eq!(t("1"), "3");
+		assert_eq!(t("0"), "0");
+		assert_eq!(t("\"\""), "0");
+		assert_eq!(t("\"a\""), "3");
+		assert_eq!(t("0.5"), "3");
+		assert_eq!(t("none"), "0");
+		assert_eq!(t("[]"), "3");
+		assert_eq!(t("true"), "3");
+		for cond in ["1", "0", "\"\"", "none"] {
+			let err = run(cond, false).unwrap_err();
+			assert_eq!(err.to_string(), "Type is not boolean", "{}", cond);
+		}
+	}
+
+	#[test]
+	fn method_cache() {
+		use core::cell::Cell;
+		use core::sync::atomic::{AtomicUsize, Ordering};
+
+		static RESOLVED: AtomicUsize = AtomicUsize::new(0);
+
+		struct Counter(Cell<Integer>);
+
+		impl ScriptType<Variant> for Counter {
+			fn call_self(
+				&self,
+				object: &ScriptObject<Variant>,
+				function: &str,
+				args: &[&Variant],
+				env: &Environment<Variant>,
+			) -> CallResult<Variant> {
+				match self.resolve(function) {
+					Some(method) => method(object, args, env),
+					None => Err(CallError::undefined_function(function)),
+				}
+			}
+
+			fn resolve(&self, function: &str) -> Option<Method<Variant>> {
+				RESOLVED.fetch_add(1, Ordering::Relaxed);
+				match function {
+					"next" => Some(|o, _, _| {
+						let c = &o.cast::<Counter>().unwrap().0;
+						c.set(c.get() + 1);
+						Ok(Variant::Integer(c.get()))
+					}),
+					"len" => Some(|_, _, _| Ok(Variant::Integer(10))),
+					_ => None,
+				}
+			}
+		}
+
+		let src = "fn main(c, a)\n\tfor _ in 1000\n\t\tc.next()\n\tvar n = 0\n\tfor o in [c, a, c, a]\n\t\tvar l = o.len()\n\t\tn += l\n\treturn [c.next(), n]";
+		let class = compile(src);
+		let c = Variant::Object(ScriptObject::new(Rc::new(Counter(Cell::new(0)))));
+		let a = run("fn main()\n\treturn [1, 2, 3]").unwrap();
+		let r = class
+			.instance()
+			.call("main", &[&c, &a], &Environment::new());
+		assert_eq!(r.unwrap().to_string(), "[1001, 26]");
+		// Once per call site, and again whenever the type differs from the previous call
+		assert_eq!(RESOLVED.load(Ordering::Relaxed), 4);
+	}
+
+	#[test]
+	fn compound_assign_index() {
+		let src = "var grid\nvar calls\n\nfn main()\n\tself.grid = [1, 2, 3]\n\tself.calls = 0\n\tfor _ in 3\n\t\tself.grid[1] += 1\n\tself.grid[self.index()] -= 10\n\tvar g = self.grid\n\tg[0] += g[2]\n\treturn [g, self.calls]\n\nfn index()\n\tself.calls += 1\n\treturn 2";
+		assert_eq!(run(src).unwrap().to_string(), "[[-6, 5, -7], 1]");
+		let src = "fn main()\n\tvar n = 1\n\tn += n + 1\n\treturn n";
+		assert_eq!(run(src).unwrap(), Variant::Integer(3));
+		let src = "var t\n\nfn main()\n\tself.t = 1\n\tvar y = 2\n\tself.t += y\n\treturn [t, y]";
+		assert_eq!(run(src).unwrap().to_string(), "[3, 2]");
+	}
+
+	#[test]
+	fn self_field_read() {
+		let src = |s: &str| format!("var x\n\nfn main()\n\tself.x = 4\n\t{}", s);
+		let t = |s: &str| run(&src(s)).unwrap();
+		assert_eq!(t("return self.x + 1"), Variant::Integer(5));
+		assert_eq!(t("return 10 - self.x * 2"), Variant::Integer(2));
+		assert_eq!(t("return -self.x"), Variant::Integer(-4));
+		assert_eq!(t("return [self.x, x]").to_string(), "[4, 4]");
+		assert_eq!(t("self.x\n\tx\n\treturn 1"), Variant::Integer(1));
+		assert!(!compiles(&src("return self.y + 1")));
+		let code = compile(&src("return self.x + 1")).disassemble();
+		assert!(code.contains("load    1, 0"), "{}", code);
+	}
+
+	#[test]
+	fn for_guard() {
+		let f = |s: &str| {
+			run(&format!(
+				"fn main()\n\tvar s = 0\n\t{}\n\t\ts += x\n\treturn s",
+				s
+			))
+		};
+		assert_eq!(
+			f("for x in [1, 2, 3, 4, 5, 6] if x % 2 == 0").unwrap(),
+			Variant::Integer(12)
+		);
+		assert_eq!(
+			f("for x in 1 to 10 step 3 if x > 1").unwrap(),
+			Variant::Integer(11)
+		);
+		assert_eq!(f("for x in 10 if false").unwrap(), Variant::Integer(0));
+		let r = run("fn main()\n\tvar s = 0\n\tfor x in 6 if x != 2\n\t\ts += x\n\telse\n\t\ts *= 2\n\treturn s");
+		assert_eq!(r.unwrap(), Variant::Integer(26));
+	}
+
+	#[test]
+	fn self_call_argument_count() {
+		let f = "\n\nfn f(a, b)\n\treturn a + b";
+		assert_eq!(
+			compile_error(&format!("fn main()\n\treturn self.f(1, 2, 3){}", f)),
+			"Wrong amount of arguments for function 'f' at line 2, column 23"
+		);
+		assert!(!compiles(&format!("fn main()\n\treturn self.f(1){}", f)));
+		let r = run(&format!("fn main()\n\treturn self.f(1, 2){}", f));
+		assert_eq!(r.unwrap(), Variant::Integer(3));
+		assert_eq!(
+			compile_error("fn f()\n\tpass\n\nfn f()\n\tpass"),
+			"Duplicate function 'f' at line 4, column 1"
+		);
+	}
+
+	#[test]
+	fn constant_folding() {
+		let src = |s: &str| format!("fn main(x)\n\treturn {}", s);
+		let t = |s: &str| {
+			let class = compile(&src(s));
+			let r = class
+				.instance()
+				.call("main", &[&Variant::Integer(7)], &Environment::new());
+			(class.disassemble(), r.unwrap())
+		};
+
+		let (code, r) = t("2 * 60 * 1000");
+		assert_eq!(r, Variant::Integer(120000));
+		assert!(!code.contains("mul"), "{}", code);
+		let expected = "\
+main:
+  parameters 1, registers 2
+  const 0 = 120000
+   0        loadc   1, 0
+   1        ret     1
+";
+		assert_eq!(code, expected);
+
+		assert_eq!(t("-(1 + 2) * 3").1, Variant::Integer(-9));
+		assert_eq!(t("\"a\" + \"b\"").1, Variant::String("ab".into()));
+		assert_eq!(t("1 < 2 == !false").1, Variant::Bool(true));
+		assert_eq!(t("none ?? 4 + 1").1, Variant::Integer(5));
+		let (code, r) = t("2 * 3 + x");
+		assert_eq!(r, Variant::Integer(13));
+		assert!(!code.contains("mul"), "{}", code);
+
+		assert!(!compiles(&src("\"a\" - 1")));
+		assert!(compiles(&src("x - \"a\"")));
+		// Division by zero and overflow are left to the interpreter
+		let code = |s: &str| compile(&src(s)).disassemble();
+		assert!(code("1 / 0").contains("div"));
+		assert!(code(&format!("{} + 1", Integer::MAX)).contains("add"));
+	}
+
+	#[test]
+	fn many_constants() {
+		let mut src = String::from("fn main()\n\tvar s = 0");
+		for i in 0..300 {
+			src += &format!("\n\ts += {}", i * 1000);
+		}
+		src += "\n\tvar a = [";
+		for i in 0..300 {
+			src += &format!("\"{}\", ", i);
+		}
+		src += "]\n\treturn [s, a[299], a.len()]";
+		let r = run(&src).unwrap();
+		assert_eq!(r.to_string(), "[44850000, \"299\", 300]");
+	}
+
+	#[test]
+	fn many_registers() {
+		let mut src = String::from("fn main()");
+		for i in 0..400 {
+			src += &format!("\n\tvar v{} = {}", i, i);
+		}
+		src += "\n\tvar s = v0";
+		for i in 1..400 {
+			src += &format!("\n\ts += v{} * 2", i);
+		}
+		src += "\n\treturn s";
+		let class = compile(&src);
+		let code = class.disassemble();
+		assert!(code.contains("registers 403"), "{}", code);
+		let r = class.instance().call("main", &[], &Environment::new());
+		assert_eq!(r.unwrap(), Variant::Integer(159600));
+	}
+
+	#[test]
+	fn downcast_object() {
+		#[derive(Debug, PartialEq)]
+		struct Point(Integer, Integer);
+
+		impl ScriptType<Variant> for Point {
+			fn call_self(
+				&self,
+				_: &ScriptObject<Variant>,
+				function: &str,
+				_: &[&Variant],
+				_: &Environment<Variant>,
+			) -> CallResult<Variant> {
+				match function {
+					"x" => Ok(Variant::Integer(self.0)),
+					_ => Err(CallError::undefined_function(function)),
+				}
+			}
+		}
+
+		let mut env = Environment::new();
+		env.add_function("point".into(), |args: &[&Variant]| {
+			let x = args[0]
+				.as_integer()
+				.map_err(|_| CallError::bad_argument())?;
+			let y = args[1]
+				.as_integer()
+				.map_err(|_| CallError::bad_argument())?;
+			Ok(Variant::Object(ScriptObject::new(Rc::new(Point(x, y)))))
+		})
+		.unwrap();
+		let source =
+			"fn main()\n\tvar a = [env.point(3, 4)]\n\treturn a[0]\n\nfn id(o)\n\treturn o";
+		let class = compile(source);
+		let script = class.instance();
+
+		let p = script.call("main", &[], &env).unwrap();
+		assert_eq!(p.downcast_ref::<Point>(), Some(&Point(3, 4)));
+		assert_eq!(p.call("x", &[], &env).unwrap(), Variant::Integer(3));
+		assert!(p.downcast_ref::<Array<Variant>>().is_none());
+		assert!(Variant::Integer(3).downcast_ref::<Point>().is_none());
+
+		let q = script.call("id", &[&p], &env).unwrap();
+		let (p, q) = (p.downcast_ref::<Point>(), q.downcast_ref::<Point>());
+		assert!(core::ptr::eq(p.unwrap(), q.unwrap()));
+	}
+
+	#[test]
+	fn literal_elements() {
+		let t = |e: &str| run(&format!("fn main()\n\tvar k = 1\n\treturn {}", e)).unwrap();
+		assert_eq!(t("[true, false, none]").to_string(), "[true, false, none]");
+		assert_eq!(t("{\"k\": none}").to_string(), "{\"k\": none}");
+		assert_eq!(
+			t("{true: false, false: none}").to_string(),
+			"{true: false, false: none}"
+		);
+		assert_eq!(t("{k: none}").to_string(), "{1: none}");
+		assert_eq!(
+			t("[[none], {false: [true]}]").to_string(),
+			"[[none], {false: [true]}]"
+		);
+		assert_eq!(t("{true: 2}[true]"), Variant::Integer(2));
+		assert_eq!(t("none"), Variant::None);
+	}
+}
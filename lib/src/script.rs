@@ -5,11 +5,10 @@
 use crate::bytecode::{ByteCode, CallResult};
 use crate::std_types::*;
 use crate::Rc;
-use crate::{Environment, Tracer, VariantType};
+use crate::{Arity, Environment, Tracer, VariantType};
 use core::any::{Any, TypeId};
 use core::cell::RefCell;
 use core::fmt;
-use std::error::Error;
 
 pub struct Class<V, T>(Arc<Script<V, T>>)
 where
@@ -21,6 +20,18 @@ pub struct ScriptObject<V>(pub(crate) Rc<dyn ScriptType<V>>)
 where
 	V: VariantType;
 
+/// An iterator over the elements of a value, such as the elements of an array or the
+/// characters of a string. This allows hosts to consume values produced by scripts, e.g.
+///
+/// ```ignore
+/// for v in ScriptIter::new(&value)? {
+///     println!("{}", v);
+/// }
+/// ```
+pub struct ScriptIter<V>(Box<dyn Iterator<Item = V>>)
+where
+	V: VariantType;
+
 impl<V> ScriptObject<V>
 where
 	V: VariantType,
@@ -29,6 +40,20 @@ where
 	pub fn new(rc: Rc<dyn ScriptType<V>>) -> Self {
 		Self(rc)
 	}
+
+	/// Takes the underlying value out of the object if it is a `T` and there are no other
+	/// references to it. Otherwise the object is returned unchanged.
+	pub fn try_into_inner<T>(self) -> Result<T, Self>
+	where
+		T: ScriptType<V>,
+	{
+		if !self.0.is::<T>() {
+			return Err(self);
+		}
+		// SAFETY: `is` confirmed that the underlying type of the trait object is indeed T.
+		let rc = unsafe { Rc::from_raw(Rc::into_raw(self.0) as *const T) };
+		Rc::try_unwrap(rc).map_err(|rc| Self(rc))
+	}
 }
 
 impl<V> core::ops::Deref for ScriptObject<V>
@@ -76,14 +101,39 @@ where
 	variables: RefCell<Box<[V]>>,
 }
 
+/// A method resolved ahead of time with [`ScriptType::resolve`].
+pub type Method<V> = fn(&ScriptObject<V>, &[&V], &Environment<V>) -> CallResult<V>;
+
 #[derive(Debug)]
-pub(crate) enum CallError {
-	UndefinedFunction,
+pub enum CallError {
+	/// No function or method with the given name exists.
+	UndefinedFunction(String),
+	/// No environment constant with the given name exists.
+	UndefinedConstant(String),
+	/// An environment constant was called as a function.
+	NotAFunction(String),
+	/// An environment function was read as a constant.
+	NotAConstant(String),
+	/// A dictionary was indexed with a key it doesn't contain. Holds the key as a string.
+	KeyNotFound(String),
 	BadArgument,
 	BadArgumentCount,
 	/// This is specifically intended for operations on `()` AKA "null"
 	IsEmpty,
 	IncompatibleType,
+	/// An operator can't be applied to the types of its operands, e.g. adding an integer and
+	/// an array.
+	IncompatibleTypes {
+		operation: &'static str,
+		left: &'static str,
+		right: &'static str,
+	},
+	Overflow,
+	DivideByZero,
+	/// Raised by the `assert` and `assert_eq` builtins, along with a message.
+	AssertionFailed(String),
+	/// Bytes that were converted to a string aren't valid UTF-8.
+	InvalidUtf8,
 }
 
 pub trait ScriptType<V>: 'static
@@ -104,6 +154,15 @@ where
 		Any::type_id(self)
 	}
 
+	/// Looks up a method so that repeated calls on objects of the same type can skip
+	/// [`call_self`](ScriptType::call_self). The method must behave the same as `call_self`
+	/// and will only be called with objects of this type.
+	#[inline]
+	fn resolve(&self, function: &str) -> Option<Method<V>> {
+		let _ = function;
+		None
+	}
+
 	#[inline]
 	fn index(&self, index: &V) -> CallResult<V> {
 		let _ = index;
@@ -125,6 +184,21 @@ where
 	fn iter(&self) -> CallResult<Box<dyn Iterator<Item = V>>> {
 		Err(CallError::incompatible_type())
 	}
+
+	/// Checks if this object is equivalent to another object.
+	#[inline]
+	fn equals(&self, rhs: &ScriptObject<V>) -> bool {
+		let _ = rhs;
+		false
+	}
+
+	/// Implements `self + rhs` for two objects. The operands must not be modified, as they
+	/// may be shared.
+	#[inline]
+	fn add(&self, rhs: &ScriptObject<V>) -> CallResult<ScriptObject<V>> {
+		let _ = rhs;
+		Err(CallError::incompatible_type())
+	}
 }
 
 /// Copied from [`Any`](std::any::Any). As casting between trait objects is not possible
@@ -174,7 +248,7 @@ where
 		let func = self
 			.function_map
 			.get(function)
-			.ok_or_else(CallError::undefined_function)?;
+			.ok_or_else(|| CallError::undefined_function(function))?;
 		let function = &self.functions[*func as usize];
 		function.run(object, &self.functions, locals, args, &env, &self.tracer)
 	}
@@ -193,6 +267,19 @@ where
 			variables: RefCell::new(locals.into_boxed_slice()),
 		}))
 	}
+
+	/// Returns the name and arity of every function in the script in the order they are
+	/// declared.
+	pub fn function_names(&self) -> impl Iterator<Item = (&str, Arity)> {
+		self.0.functions.iter().map(|f| (&**f.name(), f.arity()))
+	}
+
+	/// Returns a listing of the instructions of all functions, see
+	/// [`ByteCode::disassemble`].
+	pub fn disassemble(&self) -> String {
+		let functions = self.0.functions.iter().map(ByteCode::disassemble);
+		functions.collect::<Vec<_>>().join("\n")
+	}
 }
 
 impl<V, T> From<Script<V, T>> for Class<V, T>
@@ -246,16 +333,67 @@ where
 	}
 }
 
+impl<V> ScriptIter<V>
+where
+	V: VariantType,
+{
+	/// Creates an iterator over the given value. Returns an error if the value can't be
+	/// iterated.
+	pub fn new(value: &V) -> CallResult<Self> {
+		value.iter().map(Self)
+	}
+}
+
+impl<V> Iterator for ScriptIter<V>
+where
+	V: VariantType,
+{
+	type Item = V;
+
+	#[inline]
+	fn next(&mut self) -> Option<V> {
+		self.0.next()
+	}
+}
+
 impl Error for CallError {}
 
 impl fmt::Display for CallError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self {
-			CallError::UndefinedFunction => f.write_str("Undefined function"),
+			CallError::UndefinedFunction(name) => write!(f, "Undefined function '{}'", name),
+			CallError::UndefinedConstant(name) => write!(f, "Undefined constant '{}'", name),
+			CallError::NotAFunction(name) => write!(f, "'{}' is a constant, not a function", name),
+			CallError::NotAConstant(name) => write!(f, "'{}' is a function, not a constant", name),
+			CallError::KeyNotFound(key) => write!(f, "Key not found: {}", key),
 			CallError::BadArgumentCount => f.write_str("Bad argument count"),
 			CallError::IncompatibleType => f.write_str("Type is not compatible"),
+			CallError::IncompatibleTypes {
+				operation,
+				left,
+				right,
+			} => {
+				let verb = match *operation {
+					"add" => "add",
+					"sub" => "subtract",
+					"mul" => "multiply",
+					"div" => "divide",
+					"rem" => "take the remainder of",
+					"bitand" => "and",
+					"bitor" => "or",
+					"bitxor" => "xor",
+					"shl" | "shr" => "shift",
+					op => op,
+				};
+				write!(f, "Cannot {} {} and {}", verb, left, right)
+			}
 			CallError::IsEmpty => f.write_str("Type is none"),
 			CallError::BadArgument => f.write_str("Bad argument type"),
+			CallError::Overflow => f.write_str("Integer overflow"),
+			CallError::DivideByZero => f.write_str("Division by zero"),
+			CallError::AssertionFailed(msg) if msg.is_empty() => f.write_str("Assertion failed"),
+			CallError::AssertionFailed(msg) => write!(f, "Assertion failed: {}", msg),
+			CallError::InvalidUtf8 => f.write_str("Bytes are not valid UTF-8"),
 		}
 	}
 }
@@ -267,6 +405,20 @@ impl CallError {
 		Box::new(CallError::IncompatibleType)
 	}
 
+	#[inline(never)]
+	#[cold]
+	pub fn incompatible_types(
+		operation: &'static str,
+		left: &'static str,
+		right: &'static str,
+	) -> Box<dyn Error> {
+		Box::new(CallError::IncompatibleTypes {
+			operation,
+			left,
+			right,
+		})
+	}
+
 	#[inline(never)]
 	#[cold]
 	pub fn empty() -> Box<dyn Error> {
@@ -275,8 +427,32 @@ impl CallError {
 
 	#[inline(never)]
 	#[cold]
-	pub fn undefined_function() -> Box<dyn Error> {
-		Box::new(CallError::UndefinedFunction)
+	pub fn undefined_function(name: &str) -> Box<dyn Error> {
+		Box::new(CallError::UndefinedFunction(name.into()))
+	}
+
+	#[inline(never)]
+	#[cold]
+	pub fn undefined_constant(name: &str) -> Box<dyn Error> {
+		Box::new(CallError::UndefinedConstant(name.into()))
+	}
+
+	#[inline(never)]
+	#[cold]
+	pub fn not_a_function(name: &str) -> Box<dyn Error> {
+		Box::new(CallError::NotAFunction(name.into()))
+	}
+
+	#[inline(never)]
+	#[cold]
+	pub fn not_a_constant(name: &str) -> Box<dyn Error> {
+		Box::new(CallError::NotAConstant(name.into()))
+	}
+
+	#[inline(never)]
+	#[cold]
+	pub fn key_not_found(key: String) -> Box<dyn Error> {
+		Box::new(CallError::KeyNotFound(key))
 	}
 
 	#[inline(never)]
@@ -290,4 +466,28 @@ impl CallError {
 	pub fn bad_argument() -> Box<dyn Error> {
 		Box::new(CallError::BadArgument)
 	}
+
+	#[inline(never)]
+	#[cold]
+	pub fn overflow() -> Box<dyn Error> {
+		Box::new(CallError::Overflow)
+	}
+
+	#[inline(never)]
+	#[cold]
+	pub fn divide_by_zero() -> Box<dyn Error> {
+		Box::new(CallError::DivideByZero)
+	}
+
+	#[inline(never)]
+	#[cold]
+	pub fn assertion_failed(message: String) -> Box<dyn Error> {
+		Box::new(CallError::AssertionFailed(message))
+	}
+
+	#[inline(never)]
+	#[cold]
+	pub fn invalid_utf8() -> Box<dyn Error> {
+		Box::new(CallError::InvalidUtf8)
+	}
 }
@@ -5,6 +5,8 @@
 #[cfg(not(feature = "std"))]
 use crate::std_types::*;
 use crate::util;
+use core::convert::TryFrom;
+use core::fmt;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub(crate) enum Op {
@@ -27,8 +29,10 @@ pub(crate) enum Op {
 	Greater,
 	ShiftLeft,
 	ShiftRight,
+	ShiftRightUnsigned,
 	Access,
 	Index,
+	Coalesce,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -44,11 +48,19 @@ pub(crate) enum AssignOp {
 	Xor,
 }
 
+/// A part of a string literal with embedded expressions, e.g. `"x = {x}"`
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum FormatSegment<'src> {
+	Str(util::Str<'src>),
+	Expr(&'src str),
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) enum Token<'src> {
 	Number(&'src str),
 	Name(&'src str),
 	String(util::Str<'src>),
+	FormatString(Vec<FormatSegment<'src>>),
 	Var,
 	BracketRoundOpen,
 	BracketRoundClose,
@@ -62,6 +74,7 @@ pub(crate) enum Token<'src> {
 	Elif,
 	Else,
 	While,
+	Loop,
 	For,
 	In,
 	Fn,
@@ -78,6 +91,7 @@ pub(crate) enum Token<'src> {
 	Catch,
 	True,
 	False,
+	None,
 	To,
 	Step,
 	_Self,
@@ -93,23 +107,56 @@ pub enum TokenError {
 	UnterminatedString,
 	InvalidAssignOp,
 	SpaceInIndent,
+	TabInIndent,
+	IndentationMismatch,
 	IndentationOverflow,
-	InvalidEscapeSequence,
+	/// An escape sequence in a string is invalid. Holds the offset of its backslash in bytes.
+	InvalidEscapeSequence(u32),
+	UnterminatedFormatExpression,
+	InvalidOperator,
+}
+
+/// The characters lines are indented with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indentation {
+	/// One tab per level.
+	Tabs,
+	/// The given amount of spaces per level, which must not be zero.
+	Spaces(u8),
+}
+
+impl Indentation {
+	/// The character used for indentation and how many of them make up one level.
+	fn unit(self) -> (char, u8) {
+		match self {
+			Indentation::Tabs => ('\t', 1),
+			Indentation::Spaces(n) => (' ', n),
+		}
+	}
 }
 
+/// The maximum depth expressions can be nested to if no other limit is set.
+pub(crate) const DEFAULT_MAX_DEPTH: u16 = 128;
+
 #[derive(Debug)]
 pub(crate) struct TokenStream<'src> {
 	tokens: Vec<(Token<'src>, u32, u32)>,
 	current_index: usize,
+	/// The current nesting depth of the parser
+	depth: u16,
+	/// The maximum nesting depth of the parser
+	max_depth: u16,
 }
 
 #[derive(Debug, PartialEq)]
-pub(crate) enum TokenStreamError {
-	TokenError(TokenError),
+pub(crate) struct TokenStreamError {
+	pub error: TokenError,
+	pub line: u32,
+	pub column: u32,
 }
 
 impl Op {
-	fn precedence(&self) -> i8 {
+	pub(crate) fn precedence(&self) -> i8 {
 		use Op::*;
 		match *self {
 			Access => 13,
@@ -117,15 +164,21 @@ impl Op {
 			Not => 11,
 			Mul | Div | Rem => 10,
 			Add | Sub => 9,
-			ShiftRight | ShiftLeft => 8,
+			ShiftRight | ShiftRightUnsigned | ShiftLeft => 8,
 			And => 7,
 			Xor => 6,
 			Or => 5,
 			Less | Greater | LessEq | GreaterEq => 4,
 			Eq | Neq => 3,
 			AndThen | OrElse => 2,
+			Coalesce => 1,
 		}
 	}
+
+	/// Whether this operator can be used between two operands
+	pub(crate) fn is_binary(&self) -> bool {
+		!matches!(self, Op::Not | Op::Access | Op::Index)
+	}
 }
 
 impl PartialOrd for Op {
@@ -135,10 +188,15 @@ impl PartialOrd for Op {
 }
 
 impl Token<'_> {
-	const OPERATORS: &'static str = "=+-*/%&|^!<>.";
+	const OPERATORS: &'static str = "=+-*/%&|^!<>.?";
 	const BRACKETS: &'static str = "()[]{}";
 
-	fn parse(source: &str, start_of_file: bool) -> Result<(Token, u32), TokenError> {
+	fn parse(
+		source: &str,
+		start_of_file: bool,
+		indentation: Indentation,
+	) -> Result<(Token, u32), TokenError> {
+		let (unit, width) = indentation.unit();
 		let mut chars = source.char_indices().map(|(i, c)| (i as u32, c)).peekable();
 		while let Some((start, c)) = chars.next() {
 			return match c {
@@ -148,18 +206,25 @@ impl Token<'_> {
 					}
 					continue;
 				}
-				' ' | '\t' if (c != '\t' || !start_of_file) => continue,
-				'\n' | '\t' => {
-					let s = if c == '\t' { 1 } else { 0 };
-					for i in s.. {
-						return match chars.next() {
-							Some((_, '\t')) => continue,
-							Some((_, ' ')) => Err(TokenError::SpaceInIndent),
-							Some(_) => Ok((Token::Indent(i), start + (i + 1 - s) as u32)),
-							None => Err(TokenError::Empty),
-						};
+				' ' | '\t' if (c != unit || !start_of_file) => continue,
+				'\n' | ' ' | '\t' => {
+					let mut n = u32::from(c != '\n');
+					let end = loop {
+						match chars.next() {
+							Some((_, c)) if c == unit => n += 1,
+							Some((_, ' ')) => return Err(TokenError::SpaceInIndent),
+							Some((_, '\t')) => return Err(TokenError::TabInIndent),
+							Some((_, c)) => break c,
+							None => return Err(TokenError::Empty),
+						}
+					};
+					// Empty lines and comments are skipped, so their indentation doesn't matter
+					if n % u32::from(width) != 0 && !matches!(end, '\n' | '\r' | '#') {
+						return Err(TokenError::IndentationMismatch);
 					}
-					return Err(TokenError::IndentationOverflow);
+					let level = u8::try_from(n / u32::from(width))
+						.map_err(|_| TokenError::IndentationOverflow)?;
+					Ok((Token::Indent(level), start + u32::from(c == '\n') + n))
 				}
 				// Just ignore it, I can't be arsed with Windows legacy crap
 				'\r' => continue,
@@ -171,20 +236,78 @@ impl Token<'_> {
 				'}' => Ok((Token::BracketCurlyClose, start + 1)),
 				',' => Ok((Token::Comma, start + 1)),
 				':' => Ok((Token::Colon, start + 1)),
+				// Raw strings are copied verbatim, without escapes or embedded expressions.
+				'r' if chars.peek().map(|v| v.1) == Some('"') => {
+					let begin = start as usize + 2;
+					match source[begin..].find('"') {
+						Some(len) => {
+							let s = util::Str::Slice(&source[begin..begin + len]);
+							Ok((Token::String(s), (begin + len + 1) as u32))
+						}
+						None => Err(TokenError::UnterminatedString),
+					}
+				}
 				'"' => {
-					let mut start = start as usize + 1;
+					// Triple-quoted strings end only at the next `"""`, so they can contain
+					// single quotes.
+					let quotes = if source[start as usize..].starts_with("\"\"\"") {
+						chars.next();
+						chars.next();
+						3
+					} else {
+						1
+					};
+					let mut start = start as usize + quotes;
 					let mut s = String::new();
+					let mut segments = Vec::new();
 					loop {
 						if let Some((i, c)) = chars.next() {
-							if c == '"' {
+							if c == '"'
+								&& (quotes == 1 || source[i as usize..].starts_with("\"\"\""))
+							{
+								let end = i + quotes as u32;
 								let s = if s.is_empty() {
 									util::Str::Slice(&source[start..i as usize])
 								} else {
 									s.push_str(&source[start..i as usize]);
 									util::Str::Alloc(s.into())
 								};
-								break Ok((Token::String(s), i + 1));
+								if segments.is_empty() {
+									break Ok((Token::String(s), end));
+								}
+								if !s.is_empty() {
+									segments.push(FormatSegment::Str(s));
+								}
+								break Ok((Token::FormatString(segments), end));
+							} else if (c == '{' || c == '}') && chars.peek().map(|v| v.1) == Some(c)
+							{
+								// `{{` and `}}` are escaped braces
+								s.push_str(&source[start..=i as usize]);
+								chars.next();
+								start = i as usize + 2;
+							} else if c == '{' {
+								s.push_str(&source[start..i as usize]);
+								let end = loop {
+									match chars.next() {
+										Some((e, '}')) => break e as usize,
+										Some((_, '"')) | Some((_, '\n')) | None => {
+											return Err(TokenError::UnterminatedFormatExpression)
+										}
+										Some(_) => (),
+									}
+								};
+								let expr = &source[i as usize + 1..end];
+								if expr.trim().is_empty() {
+									return Err(TokenError::UnterminatedFormatExpression);
+								}
+								if !s.is_empty() {
+									let s = core::mem::take(&mut s);
+									segments.push(FormatSegment::Str(util::Str::Alloc(s.into())));
+								}
+								segments.push(FormatSegment::Expr(expr));
+								start = end + 1;
 							} else if c == '\\' {
+								let invalid = || TokenError::InvalidEscapeSequence(i);
 								s.push_str(&source[start..i as usize]);
 								start = i as usize + 2;
 								s.push(match chars.next().map(|(_, c)| c) {
@@ -204,29 +327,27 @@ impl Token<'_> {
 										let b = chars
 											.next()
 											.and_then(|(_, c)| c.to_digit(8))
-											.ok_or(TokenError::InvalidEscapeSequence)?;
+											.ok_or_else(invalid)?;
 										let c = chars
 											.next()
 											.and_then(|(_, c)| c.to_digit(8))
-											.ok_or(TokenError::InvalidEscapeSequence)?;
+											.ok_or_else(invalid)?;
 										start += 2;
 										let n = a << 6 | b << 3 | c;
-										char::from_u32(n)
-											.ok_or(TokenError::InvalidEscapeSequence)?
+										char::from_u32(n).ok_or_else(invalid)?
 									}
 									Some('x') => {
 										let a = chars
 											.next()
 											.and_then(|(_, c)| c.to_digit(16))
-											.ok_or(TokenError::InvalidEscapeSequence)?;
+											.ok_or_else(invalid)?;
 										let b = chars
 											.next()
 											.and_then(|(_, c)| c.to_digit(16))
-											.ok_or(TokenError::InvalidEscapeSequence)?;
+											.ok_or_else(invalid)?;
 										start += 2;
 										let n = a << 4 | b;
-										char::from_u32(n)
-											.ok_or(TokenError::InvalidEscapeSequence)?
+										char::from_u32(n).ok_or_else(invalid)?
 									}
 									Some('u') => {
 										let mut n = 0;
@@ -234,29 +355,23 @@ impl Token<'_> {
 											let a = chars
 												.next()
 												.and_then(|(_, c)| c.to_digit(16))
-												.ok_or(TokenError::InvalidEscapeSequence)?;
+												.ok_or_else(invalid)?;
 											n = (n << 4) | a;
 											start += 1;
 										}
-										char::from_u32(n)
-											.ok_or(TokenError::InvalidEscapeSequence)?
+										char::from_u32(n).ok_or_else(invalid)?
 									}
 									Some('U') => {
 										let mut n = 0;
 										for _ in 0..8 {
-											let (_, a) = chars
-												.next()
-												.ok_or(TokenError::InvalidEscapeSequence)?;
-											let a = a
-												.to_digit(16)
-												.ok_or(TokenError::InvalidEscapeSequence)?;
+											let (_, a) = chars.next().ok_or_else(invalid)?;
+											let a = a.to_digit(16).ok_or_else(invalid)?;
 											n = (n << 4) | a;
 											start += 1;
 										}
-										char::from_u32(n)
-											.ok_or(TokenError::InvalidEscapeSequence)?
+										char::from_u32(n).ok_or_else(invalid)?
 									}
-									Some(_) => return Err(TokenError::InvalidEscapeSequence),
+									Some(_) => return Err(invalid()),
 									None => return Err(TokenError::UnterminatedString),
 								})
 							}
@@ -313,6 +428,9 @@ impl Token<'_> {
 						'^' => Ok((Token::Op(Op::Xor), i)),
 						'=' => Ok((Token::Assign(AssignOp::None), i)),
 						'<' => Ok(either3('<', Op::ShiftLeft, '=', Op::LessEq, Op::Less)),
+						'>' if source[i as usize..].starts_with(">>") => {
+							Ok((Token::Op(Op::ShiftRightUnsigned), start + 3))
+						}
 						'>' => Ok(either3(
 							'>',
 							Op::ShiftRight,
@@ -322,6 +440,8 @@ impl Token<'_> {
 						)),
 						'!' => Ok((Token::Op(Op::Not), i)),
 						'.' => Ok((Token::Op(Op::Access), i)),
+						'?' if cn == Some('?') => Ok((Token::Op(Op::Coalesce), start + 2)),
+						'?' => Err(TokenError::InvalidOperator),
 						c => unreachable!("operator '{}' not covered", c),
 					}
 				}
@@ -355,7 +475,7 @@ impl Token<'_> {
 						if let Some((i, c)) = chars.next() {
 							if c.is_whitespace()
 								|| Self::OPERATORS.contains(c) || Self::BRACKETS.contains(c)
-								|| c == ','
+								|| c == ',' || c == ':'
 							{
 								break (&source[start..i as usize], i);
 							}
@@ -369,6 +489,7 @@ impl Token<'_> {
 							"else" => Token::Else,
 							"elif" => Token::Elif,
 							"while" => Token::While,
+							"loop" => Token::Loop,
 							"for" => Token::For,
 							"in" => Token::In,
 							"var" => Token::Var,
@@ -379,10 +500,14 @@ impl Token<'_> {
 							"break" => Token::Break,
 							"as" => Token::As,
 							"is" => Token::Is,
+							"and" => Token::Op(Op::AndThen),
+							"or" => Token::Op(Op::OrElse),
+							"not" => Token::Op(Op::Not),
 							"try" => Token::Try,
 							"catch" => Token::Catch,
 							"true" => Token::True,
 							"false" => Token::False,
+							"none" => Token::None,
 							"to" => Token::To,
 							"step" => Token::Step,
 							"int" => Token::Int,
@@ -401,25 +526,57 @@ impl Token<'_> {
 	}
 }
 
+impl fmt::Display for TokenError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			TokenError::Empty => "Unexpected end of file",
+			TokenError::UnterminatedString => "Unterminated string",
+			TokenError::InvalidAssignOp => "Invalid assignment operator",
+			TokenError::SpaceInIndent => "Spaces can't be used for indentation",
+			TokenError::TabInIndent => "Tabs can't be used for indentation",
+			TokenError::IndentationMismatch => {
+				"Indentation is not a multiple of the indentation width"
+			}
+			TokenError::IndentationOverflow => "Indentation is too deep",
+			TokenError::InvalidEscapeSequence(_) => "Invalid escape sequence",
+			TokenError::UnterminatedFormatExpression => "Unterminated expression in string",
+			TokenError::InvalidOperator => "Invalid operator",
+		})
+	}
+}
+
 impl<'src> TokenStream<'src> {
-	pub(crate) fn parse(mut source: &'src str) -> Result<Self, TokenStreamError> {
+	pub(crate) fn parse(
+		mut source: &'src str,
+		indentation: Indentation,
+	) -> Result<Self, TokenStreamError> {
+		let (_, width) = indentation.unit();
 		let mut line = 0;
 		let mut column = 0;
 		let mut tokens = Vec::new();
 		let mut start = true;
 		loop {
-			match Token::parse(source, start) {
+			match Token::parse(source, start, indentation) {
 				Ok((tk, len)) => {
 					let prev_col = if let Token::Indent(i) = tk {
 						line += 1;
-						column = i as u32;
+						column = u32::from(i) * u32::from(width);
 						0
 					} else {
 						let c = column;
 						column += len;
 						c
 					};
-					tokens.push((tk, line, prev_col));
+					let prev_line = line;
+					// Strings may span multiple lines
+					let text = &source[..len as usize];
+					if let (Token::String(_) | Token::FormatString(_), Some(nl)) =
+						(&tk, text.rfind('\n'))
+					{
+						line += text.matches('\n').count() as u32;
+						column = len - nl as u32 - 1;
+					}
+					tokens.push((tk, prev_line, prev_col));
 					source = &source[len as usize..];
 					start = false;
 				}
@@ -429,10 +586,28 @@ impl<'src> TokenStream<'src> {
 						Ok(Self {
 							tokens,
 							current_index: 0,
+							depth: 0,
+							max_depth: DEFAULT_MAX_DEPTH,
 						})
 					} else {
-						Err(TokenStreamError::TokenError(e))
-					}
+						// Indentation errors are found after the newline
+						let (line, column) = match e {
+							TokenError::SpaceInIndent
+							| TokenError::TabInIndent
+							| TokenError::IndentationMismatch
+							| TokenError::IndentationOverflow => (line + 1, 0),
+							TokenError::InvalidEscapeSequence(offset) => (line, column + offset),
+							_ => {
+								let spaces = source.len() - source.trim_start_matches(' ').len();
+								(line, column + spaces as u32)
+							}
+						};
+						Err(TokenStreamError {
+							error: e,
+							line,
+							column,
+						})
+					};
 				}
 			}
 		}
@@ -455,6 +630,40 @@ impl<'src> TokenStream<'src> {
 		}
 	}
 
+	/// Sets the maximum nesting depth.
+	pub(crate) fn set_max_depth(&mut self, max: u16) {
+		self.max_depth = max;
+	}
+
+	/// Returns how many more levels can be entered.
+	pub(crate) fn remaining_depth(&self) -> u16 {
+		self.max_depth - self.depth
+	}
+
+	/// Increases the nesting depth. Returns `false` if the depth would exceed the maximum.
+	pub(crate) fn enter(&mut self) -> bool {
+		if self.depth >= self.max_depth {
+			return false;
+		}
+		self.depth += 1;
+		true
+	}
+
+	/// Decreases the nesting depth.
+	pub(crate) fn leave(&mut self) {
+		self.depth -= 1;
+	}
+
+	/// Returns the current nesting depth.
+	pub(crate) fn depth(&self) -> u16 {
+		self.depth
+	}
+
+	/// Restores the nesting depth to one returned by [`Self::depth`].
+	pub(crate) fn leave_to(&mut self, depth: u16) {
+		self.depth = depth;
+	}
+
 	/// Returns the line and column of the current token
 	pub(crate) fn position(&self) -> (u32, u32) {
 		let e = &self.tokens[self.current_index - 1];
@@ -508,147 +717,231 @@ mod test {
 	mod token {
 		use super::*;
 
+		fn parse_tabs(source: &str) -> Result<(Token<'_>, u32), TokenError> {
+			Token::parse(source, true, Indentation::Tabs)
+		}
+
 		#[test]
 		fn empty() {
-			assert_eq!(Token::parse("", true), Err(TokenError::Empty));
-			assert_eq!(
-				Token::parse("# This is a comment", true),
-				Err(TokenError::Empty)
-			);
+			assert_eq!(parse_tabs(""), Err(TokenError::Empty));
+			assert_eq!(parse_tabs("# This is a comment"), Err(TokenError::Empty));
 		}
 
 		#[test]
 		fn number() {
-			assert_eq!(Token::parse("0", true), Ok((Token::Number("0"), 1)));
-			assert_eq!(
-				Token::parse("42_i32", true),
-				Ok((Token::Number("42_i32"), 6))
-			);
-			assert_eq!(
-				Token::parse("0b10101", true),
-				Ok((Token::Number("0b10101"), 7))
-			);
-			assert_eq!(Token::parse("13.37", true), Ok((Token::Number("13.37"), 5)));
+			assert_eq!(parse_tabs("0"), Ok((Token::Number("0"), 1)));
+			assert_eq!(parse_tabs("42_i32"), Ok((Token::Number("42_i32"), 6)));
+			assert_eq!(parse_tabs("0b10101"), Ok((Token::Number("0b10101"), 7)));
+			assert_eq!(parse_tabs("13.37"), Ok((Token::Number("13.37"), 5)));
 		}
 
 		#[test]
 		fn string() {
 			assert_eq!(
-				Token::parse("\"foo bar 42\"", true),
-				Ok((Token::String("foo bar 42"), 12))
+				parse_tabs("\"foo bar 42\""),
+				Ok((Token::String("foo bar 42".into()), 12))
 			);
 		}
 
 		#[test]
-		fn control() {
-			assert_eq!(Token::parse("if", true), Ok((Token::If, 2)));
-			assert_eq!(Token::parse("else", true), Ok((Token::Else, 4)));
-			assert_eq!(Token::parse("elif", true), Ok((Token::Elif, 4)));
-			assert_eq!(Token::parse("while", true), Ok((Token::While, 5)));
-			assert_eq!(Token::parse("for", true), Ok((Token::For, 3)));
-			assert_eq!(Token::parse("in", true), Ok((Token::In, 2)));
-			assert_eq!(Token::parse("return", true), Ok((Token::Return, 6)));
-		}
+		fn triple_quoted_string() {
+			assert_eq!(
+				parse_tabs("\"\"\"one\n\ttwo\nthree\"\"\" + 1"),
+				Ok((Token::String("one\n\ttwo\nthree".into()), 20))
+			);
+			assert_eq!(
+				parse_tabs("\"\"\"say \"hi\" and \"\"bye\"\"\"\"\""),
+				Ok((Token::String("say \"hi\" and \"\"bye".into()), 24))
+			);
+			assert_eq!(
+				parse_tabs("\"\"\"a\\tb\\\"\"\"\"\""),
+				Ok((Token::String("a\tb\"".into()), 12))
+			);
+			assert_eq!(
+				parse_tabs("\"\"\"{x}\n\"\"\""),
+				Ok((
+					Token::FormatString(vec![
+						FormatSegment::Expr("x"),
+						FormatSegment::Str("\n".into())
+					]),
+					10
+				))
+			);
+			assert_eq!(
+				parse_tabs("\"\"\"a\"\""),
+				Err(TokenError::UnterminatedString)
+			);
+			assert_eq!(parse_tabs("\"\"\""), Err(TokenError::UnterminatedString));
+			assert_eq!(parse_tabs("\"\" + 1"), Ok((Token::String("".into()), 2)));
 
-		#[test]
-		fn brackets() {
-			assert_eq!(Token::parse("(", true), Ok((Token::BracketRoundOpen, 1)));
-			assert_eq!(Token::parse(")", true), Ok((Token::BracketRoundClose, 1)));
-			assert_eq!(Token::parse("[", true), Ok((Token::BracketSquareOpen, 1)));
-			assert_eq!(Token::parse("]", true), Ok((Token::BracketSquareClose, 1)));
-			assert_eq!(Token::parse("{", true), Ok((Token::BracketCurlyOpen, 1)));
-			assert_eq!(Token::parse("}", true), Ok((Token::BracketCurlyClose, 1)));
+			// Tokens after the string are on the line the string ends on.
+			let src = "fn f()\n\treturn \"\"\"a\nb\n  c\"\"\" + x";
+			let mut tks = TokenStream::parse(src, Indentation::Tabs).unwrap();
+			let mut positions = Vec::new();
+			while let Some(tk) = tks.next() {
+				if let Token::String(_) | Token::Name("x") = tk {
+					positions.push(tks.position());
+				}
+			}
+			assert_eq!(positions, [(1, 7), (3, 8)]);
 		}
 
 		#[test]
-		fn op() {
-			assert_eq!(Token::parse("+", true), Ok((Token::Op(Op::Add), 1)));
-			assert_eq!(Token::parse("-", true), Ok((Token::Op(Op::Sub), 1)));
-			assert_eq!(Token::parse("*", true), Ok((Token::Op(Op::Mul), 1)));
-			assert_eq!(Token::parse("/", true), Ok((Token::Op(Op::Div), 1)));
-			assert_eq!(Token::parse("%", true), Ok((Token::Op(Op::Rem), 1)));
-			assert_eq!(Token::parse("&", true), Ok((Token::Op(Op::And), 1)));
-			assert_eq!(Token::parse("|", true), Ok((Token::Op(Op::Or), 1)));
-			assert_eq!(Token::parse("^", true), Ok((Token::Op(Op::Xor), 1)));
-			assert_eq!(Token::parse("!", true), Ok((Token::Op(Op::Not), 1)));
-			assert_eq!(Token::parse("<", true), Ok((Token::Op(Op::Less), 1)));
-			assert_eq!(Token::parse(">", true), Ok((Token::Op(Op::Greater), 1)));
-			assert_eq!(Token::parse("!=", true), Ok((Token::Op(Op::Neq), 2)));
-			assert_eq!(Token::parse("<=", true), Ok((Token::Op(Op::LessEq), 2)));
-			assert_eq!(Token::parse(">=", true), Ok((Token::Op(Op::GreaterEq), 2)));
+		fn raw_string() {
+			assert_eq!(parse_tabs("r\"\\n\""), Ok((Token::String("\\n".into()), 5)));
+			assert_eq!(
+				parse_tabs("r\"C:\\path\\{to}\\\" + x"),
+				Ok((Token::String("C:\\path\\{to}\\".into()), 16))
+			);
+			assert_eq!(parse_tabs("r\"\""), Ok((Token::String("".into()), 3)));
+			assert_eq!(parse_tabs("r\"abc"), Err(TokenError::UnterminatedString));
+			assert_eq!(parse_tabs("r + 1"), Ok((Token::Name("r"), 1)));
+			assert_eq!(parse_tabs("r(1)"), Ok((Token::Name("r"), 1)));
 		}
 
 		#[test]
-		fn assign_op() {
+		fn invalid_escape_sequence() {
 			assert_eq!(
-				Token::parse("=", true),
-				Ok((Token::Assign(AssignOp::None), 1))
+				parse_tabs("\"ab\\qc\""),
+				Err(TokenError::InvalidEscapeSequence(3))
 			);
 			assert_eq!(
-				Token::parse("+=", true),
-				Ok((Token::Assign(AssignOp::Add), 2))
+				parse_tabs("  \"\\x4\""),
+				Err(TokenError::InvalidEscapeSequence(3))
 			);
 			assert_eq!(
-				Token::parse("-=", true),
-				Ok((Token::Assign(AssignOp::Sub), 2))
+				parse_tabs("\"\\n\\u12\""),
+				Err(TokenError::InvalidEscapeSequence(3))
 			);
+			assert_eq!(parse_tabs("\"é\\"), Err(TokenError::UnterminatedString));
+
+			let src = "fn main()\n\treturn \"a\\tb\\qc\"";
+			let err = TokenStream::parse(src, Indentation::Tabs).unwrap_err();
+			assert_eq!(err.error, TokenError::InvalidEscapeSequence(6));
+			assert_eq!((err.line, err.column), (1, 13));
+		}
+
+		#[test]
+		fn format_string() {
 			assert_eq!(
-				Token::parse("*=", true),
-				Ok((Token::Assign(AssignOp::Mul), 2))
+				parse_tabs("\"sum={a + b}!\""),
+				Ok((
+					Token::FormatString(vec![
+						FormatSegment::Str("sum=".into()),
+						FormatSegment::Expr("a + b"),
+						FormatSegment::Str("!".into()),
+					]),
+					14
+				))
 			);
 			assert_eq!(
-				Token::parse("/=", true),
-				Ok((Token::Assign(AssignOp::Div), 2))
+				parse_tabs("\"{{x}}\""),
+				Ok((Token::String("{x}".into()), 7))
 			);
 			assert_eq!(
-				Token::parse("%=", true),
-				Ok((Token::Assign(AssignOp::Rem), 2))
+				parse_tabs("\"{x\""),
+				Err(TokenError::UnterminatedFormatExpression)
 			);
 			assert_eq!(
-				Token::parse("&=", true),
-				Ok((Token::Assign(AssignOp::And), 2))
+				parse_tabs("\"{ }\""),
+				Err(TokenError::UnterminatedFormatExpression)
 			);
+		}
+
+		#[test]
+		fn control() {
+			assert_eq!(parse_tabs("if"), Ok((Token::If, 2)));
+			assert_eq!(parse_tabs("else"), Ok((Token::Else, 4)));
+			assert_eq!(parse_tabs("elif"), Ok((Token::Elif, 4)));
+			assert_eq!(parse_tabs("while"), Ok((Token::While, 5)));
+			assert_eq!(parse_tabs("loop"), Ok((Token::Loop, 4)));
+			assert_eq!(parse_tabs("for"), Ok((Token::For, 3)));
+			assert_eq!(parse_tabs("in"), Ok((Token::In, 2)));
+			assert_eq!(parse_tabs("return"), Ok((Token::Return, 6)));
+		}
+
+		#[test]
+		fn brackets() {
 			assert_eq!(
-				Token::parse("|=", true),
-				Ok((Token::Assign(AssignOp::Or), 2))
+				Token::parse("(", true, Indentation::Tabs),
+				Ok((Token::BracketRoundOpen, 1))
 			);
 			assert_eq!(
-				Token::parse("^=", true),
-				Ok((Token::Assign(AssignOp::Xor), 2))
+				Token::parse(")", true, Indentation::Tabs),
+				Ok((Token::BracketRoundClose, 1))
 			);
+			assert_eq!(parse_tabs("["), Ok((Token::BracketSquareOpen, 1)));
+			assert_eq!(parse_tabs("]"), Ok((Token::BracketSquareClose, 1)));
+			assert_eq!(parse_tabs("{"), Ok((Token::BracketCurlyOpen, 1)));
+			assert_eq!(parse_tabs("}"), Ok((Token::BracketCurlyClose, 1)));
+		}
+
+		#[test]
+		fn op() {
+			assert_eq!(parse_tabs("+"), Ok((Token::Op(Op::Add), 1)));
+			assert_eq!(parse_tabs("-"), Ok((Token::Op(Op::Sub), 1)));
+			assert_eq!(parse_tabs("*"), Ok((Token::Op(Op::Mul), 1)));
+			assert_eq!(parse_tabs("/"), Ok((Token::Op(Op::Div), 1)));
+			assert_eq!(parse_tabs("%"), Ok((Token::Op(Op::Rem), 1)));
+			assert_eq!(parse_tabs("&"), Ok((Token::Op(Op::And), 1)));
+			assert_eq!(parse_tabs("|"), Ok((Token::Op(Op::Or), 1)));
+			assert_eq!(parse_tabs("^"), Ok((Token::Op(Op::Xor), 1)));
+			assert_eq!(parse_tabs("!"), Ok((Token::Op(Op::Not), 1)));
+			assert_eq!(parse_tabs("<"), Ok((Token::Op(Op::Less), 1)));
+			assert_eq!(parse_tabs(">"), Ok((Token::Op(Op::Greater), 1)));
+			assert_eq!(parse_tabs("!="), Ok((Token::Op(Op::Neq), 2)));
+			assert_eq!(parse_tabs("<="), Ok((Token::Op(Op::LessEq), 2)));
+			assert_eq!(parse_tabs(">="), Ok((Token::Op(Op::GreaterEq), 2)));
+			assert_eq!(parse_tabs("&&"), Ok((Token::Op(Op::AndThen), 2)));
+			assert_eq!(parse_tabs("||"), Ok((Token::Op(Op::OrElse), 2)));
+		}
+
+		#[test]
+		fn word_op() {
+			assert_eq!(parse_tabs("and"), Ok((Token::Op(Op::AndThen), 3)));
+			assert_eq!(parse_tabs("or"), Ok((Token::Op(Op::OrElse), 2)));
+			assert_eq!(parse_tabs("not"), Ok((Token::Op(Op::Not), 3)));
+			assert_eq!(parse_tabs("android"), Ok((Token::Name("android"), 7)));
+			assert_eq!(parse_tabs("nothing"), Ok((Token::Name("nothing"), 7)));
+		}
+
+		#[test]
+		fn assign_op() {
+			assert_eq!(parse_tabs("="), Ok((Token::Assign(AssignOp::None), 1)));
+			assert_eq!(parse_tabs("+="), Ok((Token::Assign(AssignOp::Add), 2)));
+			assert_eq!(parse_tabs("-="), Ok((Token::Assign(AssignOp::Sub), 2)));
+			assert_eq!(parse_tabs("*="), Ok((Token::Assign(AssignOp::Mul), 2)));
+			assert_eq!(parse_tabs("/="), Ok((Token::Assign(AssignOp::Div), 2)));
+			assert_eq!(parse_tabs("%="), Ok((Token::Assign(AssignOp::Rem), 2)));
+			assert_eq!(parse_tabs("&="), Ok((Token::Assign(AssignOp::And), 2)));
+			assert_eq!(parse_tabs("|="), Ok((Token::Assign(AssignOp::Or), 2)));
+			assert_eq!(parse_tabs("^="), Ok((Token::Assign(AssignOp::Xor), 2)));
 		}
 
 		#[test]
 		fn declare() {
-			assert_eq!(Token::parse("var foo", true), Ok((Token::Var, 3)));
+			assert_eq!(parse_tabs("var foo"), Ok((Token::Var, 3)));
 		}
 
 		#[test]
 		fn name() {
-			assert_eq!(Token::parse("foo", true), Ok((Token::Name("foo"), 3)));
-			assert_eq!(Token::parse("_4343", true), Ok((Token::Name("_4343"), 5)));
-			assert_eq!(
-				Token::parse("hunter2", true),
-				Ok((Token::Name("hunter2"), 7))
-			);
+			assert_eq!(parse_tabs("foo"), Ok((Token::Name("foo"), 3)));
+			assert_eq!(parse_tabs("_4343"), Ok((Token::Name("_4343"), 5)));
+			assert_eq!(parse_tabs("hunter2"), Ok((Token::Name("hunter2"), 7)));
 		}
 
 		#[test]
 		fn other() {
-			assert_eq!(Token::parse(" ", true), Err(TokenError::Empty));
-			assert_eq!(Token::parse("\n", true), Err(TokenError::Empty));
-			assert_eq!(Token::parse("\r\n", true), Err(TokenError::Empty));
-			//assert_eq!(Token::parse("\t\n\t", true), Err(TokenError::Empty));
-			assert_eq!(
-				Token::parse("\t\tblah blah", true),
-				Ok((Token::Indent(2), 2))
-			);
-			assert_eq!(
-				Token::parse(" blah blah", true),
-				Ok((Token::Name("blah"), 5))
-			);
-			assert_eq!(Token::parse(",", true), Ok((Token::Comma, 1)));
-			assert_eq!(Token::parse("pass", true), Ok((Token::Pass, 4)));
+			assert_eq!(parse_tabs(" "), Err(TokenError::Empty));
+			assert_eq!(parse_tabs("\n"), Err(TokenError::Empty));
+			assert_eq!(parse_tabs("\r\n"), Err(TokenError::Empty));
+			//assert_eq!(parse_tabs("\t\n\t"), Err(TokenError::Empty));
+			assert_eq!(parse_tabs("\t\tblah blah"), Ok((Token::Indent(2), 2)));
+			assert_eq!(parse_tabs(" blah blah"), Ok((Token::Name("blah"), 5)));
+			assert_eq!(parse_tabs(","), Ok((Token::Comma, 1)));
+			assert_eq!(parse_tabs("pass"), Ok((Token::Pass, 4)));
+			assert_eq!(parse_tabs("none:"), Ok((Token::None, 4)));
+			assert_eq!(parse_tabs("key: 1"), Ok((Token::Name("key"), 3)));
 		}
 	}
 
@@ -658,18 +951,21 @@ mod test {
 		#[test]
 		fn next_prev() {
 			let src = "fn";
-			let mut s = TokenStream::parse(src).expect("Failed to parse source");
-			assert_eq!(s.prev(), false);
+			let mut s = TokenStream::parse(src, Indentation::Tabs).expect("Failed to parse source");
+			// Going back before the first token does nothing
+			s.prev();
 			assert_eq!(s.next(), Some(Token::Fn));
 			assert_eq!(s.next(), None);
-			assert_eq!(s.prev(), true);
-			assert_eq!(s.prev(), false);
+			s.prev();
+			s.prev();
+			s.prev();
+			assert_eq!(s.next(), Some(Token::Fn));
 		}
 
 		#[test]
 		fn hello_world() {
 			let src = "fn main()\n\tprintln(\"Hello, world!\")";
-			let mut s = TokenStream::parse(src).expect("Failed to parse source");
+			let mut s = TokenStream::parse(src, Indentation::Tabs).expect("Failed to parse source");
 			assert_eq!(s.next(), Some(Token::Fn));
 			assert_eq!(s.next(), Some(Token::Name("main")));
 			assert_eq!(s.next(), Some(Token::BracketRoundOpen));
@@ -677,7 +973,7 @@ mod test {
 			assert_eq!(s.next(), Some(Token::Indent(1)));
 			assert_eq!(s.next(), Some(Token::Name("println")));
 			assert_eq!(s.next(), Some(Token::BracketRoundOpen));
-			assert_eq!(s.next(), Some(Token::String("Hello, world!")));
+			assert_eq!(s.next(), Some(Token::String("Hello, world!".into())));
 			assert_eq!(s.next(), Some(Token::BracketRoundClose));
 			assert_eq!(s.next(), None);
 		}
@@ -685,7 +981,7 @@ mod test {
 		#[test]
 		fn vector_len() {
 			let src = "fn vec2_len(x, y)\n\treturn x * x + y * y";
-			let mut s = TokenStream::parse(src).expect("Failed to parse source");
+			let mut s = TokenStream::parse(src, Indentation::Tabs).expect("Failed to parse source");
 			assert_eq!(s.next(), Some(Token::Fn));
 			assert_eq!(s.next(), Some(Token::Name("vec2_len")));
 			assert_eq!(s.next(), Some(Token::BracketRoundOpen));
@@ -704,5 +1000,36 @@ mod test {
 			assert_eq!(s.next(), Some(Token::Name("y")));
 			assert_eq!(s.next(), None);
 		}
+
+		#[test]
+		fn space_indentation() {
+			let indents = |src: &str, width| {
+				let mut s = TokenStream::parse(src, Indentation::Spaces(width))
+					.expect("Failed to parse source");
+				let mut indents = Vec::new();
+				while let Some(tk) = s.next() {
+					if let Token::Indent(i) = tk {
+						// The position of the first token on the line
+						s.next();
+						indents.push((i, s.position()));
+					}
+				}
+				indents
+			};
+			let src = "fn f()\n    if a\n        pass\n\n  # comment\n    pass";
+			assert_eq!(indents(src, 4), [(1, (1, 4)), (2, (2, 8)), (1, (5, 4))]);
+			let src = "fn f()\n  if a\n    pass\n  pass";
+			assert_eq!(indents(src, 2), [(1, (1, 2)), (2, (2, 4)), (1, (3, 2))]);
+			let err = |src: &str| {
+				TokenStream::parse(src, Indentation::Spaces(4))
+					.unwrap_err()
+					.error
+			};
+			assert_eq!(err("fn f()\n   pass"), TokenError::IndentationMismatch);
+			assert_eq!(err("fn f()\n    \tpass"), TokenError::TabInIndent);
+			assert_eq!(err("fn f()\n\tpass"), TokenError::TabInIndent);
+			let err = TokenStream::parse("fn f()\n\t pass", Indentation::Tabs).unwrap_err();
+			assert_eq!(err.error, TokenError::SpaceInIndent);
+		}
 	}
 }
@@ -7,21 +7,29 @@
 //! It may be better to have some sort of custom `Array` type that is unsized, but AFAICT that isn't
 //! possible (yet?).
 
-use crate::std_types::hash_map;
+#[cfg(not(feature = "std"))]
+use crate::std_types::*;
+use crate::util;
 use crate::Rc;
-use crate::{CallError, CallResult, Environment, ScriptObject, ScriptType, VariantType};
+use crate::{
+	CallError, CallResult, Environment, Integer, Method, Real, ScriptObject, ScriptType,
+	VariantType,
+};
 use core::cell::{Ref, RefCell};
+use core::convert::TryFrom;
 use core::{fmt, mem};
 
-#[cfg(feature = "std")]
+/// Dictionaries preserve insertion order so that iterating them is deterministic.
+///
+/// Without `std` there is no `RandomState`, so the fast hasher is always used.
 mod dict {
-	#[cfg(feature = "fast-dictionary")]
-	pub use crate::std_types::FxHashMap as HashMap;
-	#[cfg(not(feature = "fast-dictionary"))]
-	pub use std::collections::HashMap;
+	#[cfg(any(feature = "fast-dictionary", not(feature = "std")))]
+	pub type IndexMap<K, V> =
+		indexmap::IndexMap<K, V, core::hash::BuildHasherDefault<rustc_hash::FxHasher>>;
+	#[cfg(not(any(feature = "fast-dictionary", not(feature = "std"))))]
+	pub use indexmap::IndexMap;
 }
-#[cfg(feature = "std")]
-use dict::HashMap;
+use dict::IndexMap;
 
 #[derive(Clone, Debug)]
 pub struct Array<V>(Rc<RefCell<Vec<V>>>)
@@ -29,15 +37,26 @@ where
 	V: VariantType;
 
 #[derive(Clone, Debug)]
-pub struct Dictionary<V>(Rc<RefCell<HashMap<VariantOrd, V>>>)
+pub struct Dictionary<V>(Rc<RefCell<IndexMap<VariantOrd, V>>>)
 where
 	V: VariantType;
 
+/// An immutable sequence of integers from `start` up to but not including `stop`
+#[derive(Clone, Copy, Debug)]
+pub struct Range {
+	start: Integer,
+	stop: Integer,
+	step: Integer,
+}
+
 /// A Variant type with only types that implement Ord and are not interiorly mutable
 #[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 enum VariantOrd {
 	Bool(bool),
-	Integer(isize),
+	Integer(Integer),
+	/// The bits of a real, see [`VariantOrd::real_bits`]
+	Real(u64),
+	Char(char),
 	String(Rc<str>),
 }
 
@@ -58,8 +77,8 @@ struct DictionaryIter<'a, V>
 where
 	V: VariantType,
 {
-	iter: hash_map::Keys<'a, VariantOrd, V>,
-	_borrow: Ref<'a, HashMap<VariantOrd, V>>,
+	iter: indexmap::map::Keys<'a, VariantOrd, V>,
+	_borrow: Ref<'a, IndexMap<VariantOrd, V>>,
 	_dictionary: Dictionary<V>,
 }
 
@@ -72,6 +91,15 @@ macro_rules! borrow {
 	};
 }
 
+/// Casts the object a resolved [`Method`] is called with back to `Self`.
+macro_rules! this {
+	($object:ident) => {
+		$object
+			.cast::<Self>()
+			.ok_or_else(CallError::incompatible_type)?
+	};
+}
+
 macro_rules! check_arg_count {
 	($args:ident, $count:expr) => {
 		if $args.len() != $count {
@@ -97,6 +125,164 @@ where
 		v.resize(n, V::default());
 		Self(Rc::new(RefCell::new(v)))
 	}
+
+	pub(crate) fn from_vec(v: Vec<V>) -> Self {
+		Self(Rc::new(RefCell::new(v)))
+	}
+
+	/// Takes the elements out of the array if there are no other references to it. Otherwise
+	/// the array is returned unchanged.
+	pub fn try_into_vec(self) -> Result<Vec<V>, Self> {
+		Rc::try_unwrap(self.0)
+			.map(RefCell::into_inner)
+			.map_err(Self)
+	}
+
+	pub fn len(&self) -> usize {
+		self.0.borrow().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.borrow().is_empty()
+	}
+
+	/// Returns the element at the given index, if any.
+	pub(crate) fn get(&self, index: usize) -> Option<V> {
+		self.0.borrow().get(index).cloned()
+	}
+
+	/// Creates a new array with the result of `f` for each element.
+	pub fn map<F>(&self, mut f: F) -> CallResult<Self>
+	where
+		F: FnMut(&V) -> CallResult<V>,
+	{
+		// Copy the elements so the callback can modify the array.
+		let array = borrow!(self).clone();
+		let array = array.iter().map(&mut f).collect::<Result<_, _>>()?;
+		Ok(Self(Rc::new(RefCell::new(array))))
+	}
+
+	/// Creates a new array with only the elements for which `f` returns `true`.
+	pub fn filter<F>(&self, mut f: F) -> CallResult<Self>
+	where
+		F: FnMut(&V) -> CallResult<V>,
+	{
+		let mut array = borrow!(self).clone();
+		let mut keep = Vec::with_capacity(array.len());
+		for e in array.iter() {
+			keep.push(
+				f(e)?
+					.as_bool()
+					.map_err(|_| CallError::incompatible_type())?,
+			);
+		}
+		let mut keep = keep.into_iter();
+		array.retain(|_| keep.next().unwrap());
+		Ok(Self(Rc::new(RefCell::new(array))))
+	}
+
+	/// Folds all elements into a single value with `f`, starting with `init`.
+	pub fn reduce<F>(&self, init: V, mut f: F) -> CallResult<V>
+	where
+		F: FnMut(&V, &V) -> CallResult<V>,
+	{
+		let array = borrow!(self).clone();
+		array.iter().try_fold(init, |acc, e| f(&acc, e))
+	}
+
+	/// Creates a new array with the elements in the range `[start, end)`. Negative indices
+	/// count from the end and out of range indices are clamped.
+	pub fn slice(&self, start: Integer, end: Integer) -> CallResult<Self> {
+		let array = borrow!(self);
+		let start = clamp_index(start, array.len());
+		let end = clamp_index(end, array.len()).max(start);
+		Ok(Self(Rc::new(RefCell::new(array[start..end].to_vec()))))
+	}
+
+	/// Sorts the array in ascending order with NaNs last, see [`VariantType::sort_cmp`].
+	/// Returns an error if any two elements can't be compared, in which case the array is
+	/// left unmodified.
+	pub fn sort(&self) -> CallResult<()> {
+		let mut array = borrow!(self).clone();
+		util::try_sort_by(&mut array, &mut |a, b| {
+			a.sort_cmp(b).ok_or_else(CallError::incompatible_type)
+		})?;
+		*borrow!(mut self) = array;
+		Ok(())
+	}
+
+	/// Reverses the order of the elements in the array.
+	pub fn reverse(&self) -> CallResult<()> {
+		borrow!(mut self).reverse();
+		Ok(())
+	}
+
+	/// Concatenates the string representations of all elements with `separator` in between.
+	pub fn join(&self, separator: &str) -> CallResult<String> {
+		let mut s = String::new();
+		for (i, e) in borrow!(self).iter().enumerate() {
+			if i > 0 {
+				s.push_str(separator);
+			}
+			s.push_str(&format!("{}", e));
+		}
+		Ok(s)
+	}
+
+	/// Formats the array, replacing any arrays and dictionaries in `seen` with `[...]`
+	/// and `{...}` respectively to prevent infinite recursion.
+	fn fmt_nested(&self, seen: &mut Vec<*const ()>, s: &mut String) {
+		let ptr = Rc::as_ptr(&self.0) as *const ();
+		if seen.contains(&ptr) {
+			s.push_str("[...]");
+			return;
+		}
+		seen.push(ptr);
+		s.push('[');
+		for (i, e) in self.0.borrow().iter().enumerate() {
+			if i > 0 {
+				s.push_str(", ");
+			}
+			fmt_element(e, seen, s);
+		}
+		s.push(']');
+		seen.pop();
+	}
+
+	/// Compares the array element-wise. Pairs in `seen` are assumed to be equal to
+	/// prevent infinite recursion.
+	fn eq_nested(&self, rhs: &Self, seen: &mut Vec<(*const (), *const ())>) -> bool {
+		let pair = (
+			Rc::as_ptr(&self.0) as *const (),
+			Rc::as_ptr(&rhs.0) as *const (),
+		);
+		if seen.contains(&pair) {
+			return true;
+		}
+		seen.push(pair);
+		let (a, b) = (self.0.borrow(), rhs.0.borrow());
+		let eq = a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| eq_element(a, b, seen));
+		seen.pop();
+		eq
+	}
+
+	/// Creates a deep copy of the array. Objects in `copies` are reused so that shared and
+	/// cyclic references are preserved in the copy.
+	fn copy_nested(&self, copies: &mut Vec<(*const (), V)>) -> CallResult<V> {
+		let ptr = Rc::as_ptr(&self.0) as *const ();
+		if let Some((_, v)) = copies.iter().find(|(p, _)| *p == ptr) {
+			return Ok(v.clone());
+		}
+		let copy = Self::new();
+		let obj = V::new_object(ScriptObject(Rc::new(copy.clone())));
+		copies.push((ptr, obj.clone()));
+		let array = borrow!(self)
+			.iter()
+			.map(|e| copy_element(e, copies))
+			.collect::<CallResult<_>>()?;
+		*copy.0.borrow_mut() = array;
+		Ok(obj)
+	}
 }
 
 impl<V> Default for Array<V>
@@ -114,35 +300,94 @@ where
 {
 	fn call_self(
 		&self,
-		_: &ScriptObject<V>,
+		object: &ScriptObject<V>,
 		function: &str,
 		args: &[&V],
-		_: &Environment<V>,
+		env: &Environment<V>,
 	) -> CallResult<V> {
+		if let Some(method) = ScriptType::<V>::resolve(self, function) {
+			return method(object, args, env);
+		}
 		match function {
-			"len" => {
-				check_arg_count!(args, 0);
-				Ok(V::new_integer(borrow!(self).len() as isize))
+			// Callbacks to functions of the script itself are handled by the interpreter.
+			"map" => {
+				check_arg_count!(args, 1);
+				let f = callback_name(args[0])?;
+				let array = self.map(|e| env.call(&f, &[e]))?;
+				Ok(V::new_object(ScriptObject(Rc::new(array))))
 			}
-			"push" => {
+			"filter" => {
 				check_arg_count!(args, 1);
-				borrow!(mut self).push(args[0].clone());
-				Ok(V::default())
+				let f = callback_name(args[0])?;
+				let array = self.filter(|e| env.call(&f, &[e]))?;
+				Ok(V::new_object(ScriptObject(Rc::new(array))))
 			}
-			// TODO is it fine to default to None?
-			"pop" => {
-				check_arg_count!(args, 0);
-				Ok(borrow!(mut self).pop().unwrap_or_default())
+			"reduce" => {
+				check_arg_count!(args, 2);
+				let f = callback_name(args[0])?;
+				self.reduce(args[1].clone(), |acc, e| env.call(&f, &[acc, e]))
 			}
-			_ => Err(CallError::undefined_function()),
+			_ => Err(CallError::undefined_function(function)),
 		}
 	}
 
+	fn resolve(&self, function: &str) -> Option<Method<V>> {
+		Some(match function {
+			"len" => |o, args, _| {
+				check_arg_count!(args, 0);
+				let array = this!(o);
+				Ok(V::new_integer(borrow!(array).len() as Integer))
+			},
+			"push" => |o, args, _| {
+				check_arg_count!(args, 1);
+				let array = this!(o);
+				borrow!(mut array).push(args[0].clone());
+				Ok(V::default())
+			},
+			// TODO is it fine to default to None?
+			"pop" => |o, args, _| {
+				check_arg_count!(args, 0);
+				let array = this!(o);
+				Ok(borrow!(mut array).pop().unwrap_or_default())
+			},
+			"copy" => |o, args, _| {
+				check_arg_count!(args, 0);
+				this!(o).copy_nested(&mut Vec::new())
+			},
+			"slice" => |o, args, _| {
+				check_arg_count!(args, 2);
+				let index = |v: &V| v.as_integer().map_err(|_| CallError::bad_argument());
+				let array = this!(o).slice(index(args[0])?, index(args[1])?)?;
+				Ok(V::new_object(ScriptObject(Rc::new(array))))
+			},
+			"sort" => |o, args, _| {
+				check_arg_count!(args, 0);
+				this!(o).sort()?;
+				Ok(V::default())
+			},
+			"reverse" => |o, args, _| {
+				check_arg_count!(args, 0);
+				this!(o).reverse()?;
+				Ok(V::default())
+			},
+			"join" => |o, args, _| {
+				check_arg_count!(args, 1);
+				let sep = args[0]
+					.clone()
+					.into_string()
+					.map_err(|_| CallError::bad_argument())?;
+				Ok(V::new_string(this!(o).join(&sep)?.into()))
+			},
+			_ => return None,
+		})
+	}
+
 	#[inline]
 	fn index(&self, index: &V) -> CallResult<V> {
 		if let Ok(v) = index.as_integer() {
-			borrow!(self)
-				.get(v as usize)
+			let array = borrow!(self);
+			resolve_index(v, array.len())
+				.and_then(|i| array.get(i))
 				.cloned()
 				.ok_or_else(CallError::bad_argument)
 		} else {
@@ -153,8 +398,9 @@ where
 	#[inline]
 	fn set_index(&self, index: &V, value: V) -> CallResult<()> {
 		if let Ok(v) = index.as_integer() {
-			borrow!(mut self)
-				.get_mut(v as usize)
+			let mut array = borrow!(mut self);
+			let i = resolve_index(v, array.len());
+			i.and_then(|i| array.get_mut(i))
 				.map(|v| *v = value)
 				.ok_or_else(CallError::bad_argument)
 		} else {
@@ -169,16 +415,28 @@ where
 	}
 
 	#[inline]
-	fn to_string(&self) -> String {
-		let mut s = "[".to_string();
-		let a = self.0.borrow();
-		if let Some((last, a)) = a.split_last() {
-			for a in a.iter() {
-				s.extend(format!("{}, ", a).chars());
-			}
-			s.extend(format!("{}", last).chars());
+	fn equals(&self, rhs: &ScriptObject<V>) -> bool {
+		match rhs.cast::<Self>() {
+			Some(rhs) => self.eq_nested(rhs, &mut Vec::new()),
+			None => false,
 		}
-		s.push(']');
+	}
+
+	/// Concatenates two arrays into a new array.
+	fn add(&self, rhs: &ScriptObject<V>) -> CallResult<ScriptObject<V>> {
+		let rhs = rhs
+			.cast::<Self>()
+			.ok_or_else(CallError::incompatible_type)?;
+		let mut v = Vec::with_capacity(self.len() + rhs.len());
+		v.extend(borrow!(self).iter().cloned());
+		v.extend(borrow!(rhs).iter().cloned());
+		Ok(ScriptObject(Rc::new(Self::from_vec(v))))
+	}
+
+	#[inline]
+	fn to_string(&self) -> String {
+		let mut s = String::new();
+		self.fmt_nested(&mut Vec::new(), &mut s);
 		s
 	}
 }
@@ -188,17 +446,82 @@ where
 	V: VariantType,
 {
 	pub fn new() -> Self {
-		Self(Rc::new(RefCell::new(HashMap::with_hasher(
+		Self(Rc::new(RefCell::new(IndexMap::with_hasher(
 			Default::default(),
 		))))
 	}
 
 	pub fn with_capacity(n: usize) -> Self {
-		Self(Rc::new(RefCell::new(HashMap::with_capacity_and_hasher(
+		Self(Rc::new(RefCell::new(IndexMap::with_capacity_and_hasher(
 			n,
 			Default::default(),
 		))))
 	}
+
+	pub fn len(&self) -> usize {
+		self.0.borrow().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.borrow().is_empty()
+	}
+
+	/// Formats the dictionary, replacing any arrays and dictionaries in `seen` with `[...]`
+	/// and `{...}` respectively to prevent infinite recursion.
+	fn fmt_nested(&self, seen: &mut Vec<*const ()>, s: &mut String) {
+		let ptr = Rc::as_ptr(&self.0) as *const ();
+		if seen.contains(&ptr) {
+			s.push_str("{...}");
+			return;
+		}
+		seen.push(ptr);
+		s.push('{');
+		for (i, (k, v)) in self.0.borrow().iter().enumerate() {
+			if i > 0 {
+				s.push_str(", ");
+			}
+			s.push_str(&format!("{:?}: ", k));
+			fmt_element(v, seen, s);
+		}
+		s.push('}');
+		seen.pop();
+	}
+
+	/// Compares the keys and values of the dictionary. Pairs in `seen` are assumed to be
+	/// equal to prevent infinite recursion.
+	fn eq_nested(&self, rhs: &Self, seen: &mut Vec<(*const (), *const ())>) -> bool {
+		let pair = (
+			Rc::as_ptr(&self.0) as *const (),
+			Rc::as_ptr(&rhs.0) as *const (),
+		);
+		if seen.contains(&pair) {
+			return true;
+		}
+		seen.push(pair);
+		let (a, b) = (self.0.borrow(), rhs.0.borrow());
+		let eq = a.len() == b.len()
+			&& a.iter()
+				.all(|(k, v)| matches!(b.get(k), Some(w) if eq_element(v, w, seen)));
+		seen.pop();
+		eq
+	}
+
+	/// Creates a deep copy of the dictionary. Objects in `copies` are reused so that shared
+	/// and cyclic references are preserved in the copy.
+	fn copy_nested(&self, copies: &mut Vec<(*const (), V)>) -> CallResult<V> {
+		let ptr = Rc::as_ptr(&self.0) as *const ();
+		if let Some((_, v)) = copies.iter().find(|(p, _)| *p == ptr) {
+			return Ok(v.clone());
+		}
+		let copy = Self::with_capacity(self.len());
+		let obj = V::new_object(ScriptObject(Rc::new(copy.clone())));
+		copies.push((ptr, obj.clone()));
+		for (k, v) in borrow!(self).iter() {
+			let v = copy_element(v, copies)?;
+			copy.0.borrow_mut().insert(k.clone(), v);
+		}
+		Ok(obj)
+	}
 }
 
 impl<V> Default for Dictionary<V>
@@ -224,7 +547,7 @@ where
 		match function {
 			"len" => {
 				check_arg_count!(args, 0);
-				Ok(V::new_integer(borrow!(self).len() as isize))
+				Ok(V::new_integer(borrow!(self).len() as Integer))
 			}
 			"insert" => {
 				check_arg_count!(args, 2);
@@ -236,9 +559,38 @@ where
 			"remove" => {
 				check_arg_count!(args, 1);
 				let key = VariantOrd::from_variant(args[0].clone())?;
-				Ok(borrow!(mut self).remove(&key).unwrap_or_default())
+				Ok(borrow!(mut self).shift_remove(&key).unwrap_or_default())
+			}
+			"has" => {
+				check_arg_count!(args, 1);
+				let key = VariantOrd::from_variant(args[0].clone())?;
+				Ok(V::new_bool(borrow!(self).contains_key(&key)))
+			}
+			"get" => {
+				check_arg_count!(args, 2);
+				let key = VariantOrd::from_variant(args[0].clone())?;
+				Ok(borrow!(self)
+					.get(&key)
+					.cloned()
+					.unwrap_or_else(|| args[1].clone()))
+			}
+			"copy" => {
+				check_arg_count!(args, 0);
+				self.copy_nested(&mut Vec::new())
 			}
-			_ => Err(CallError::undefined_function()),
+			// Iterating a dictionary yields its keys, this yields `[key, value]` pairs instead.
+			"items" => {
+				check_arg_count!(args, 0);
+				let items = borrow!(self)
+					.iter()
+					.map(|(k, v)| {
+						let pair = Array::from_vec(vec![k.clone().into_variant(), v.clone()]);
+						V::new_object(ScriptObject(Rc::new(pair)))
+					})
+					.collect();
+				Ok(V::new_object(ScriptObject(Rc::new(Array::from_vec(items)))))
+			}
+			_ => Err(CallError::undefined_function(function)),
 		}
 	}
 
@@ -249,7 +601,7 @@ where
 		borrow!(self)
 			.get(&key)
 			.cloned()
-			.ok_or_else(CallError::bad_argument)
+			.ok_or_else(|| CallError::key_not_found(index.to_string()))
 	}
 
 	#[inline]
@@ -265,19 +617,137 @@ where
 		Ok(Box::new(iter))
 	}
 
+	#[inline]
+	fn equals(&self, rhs: &ScriptObject<V>) -> bool {
+		match rhs.cast::<Self>() {
+			Some(rhs) => self.eq_nested(rhs, &mut Vec::new()),
+			None => false,
+		}
+	}
+
+	/// Merges two dictionaries into a new dictionary. Values of `rhs` replace those of
+	/// `self` with the same key, but the key keeps its position.
+	fn add(&self, rhs: &ScriptObject<V>) -> CallResult<ScriptObject<V>> {
+		let rhs = rhs
+			.cast::<Self>()
+			.ok_or_else(CallError::incompatible_type)?;
+		let mut map = borrow!(self).clone();
+		for (k, v) in borrow!(rhs).iter() {
+			map.insert(k.clone(), v.clone());
+		}
+		Ok(ScriptObject(Rc::new(Self(Rc::new(RefCell::new(map))))))
+	}
+
 	#[inline]
 	fn to_string(&self) -> String {
-		let mut s = "{".to_string();
-		let d = self.0.borrow();
-		let last = d.len() - 1;
-		for (i, (k, v)) in d.iter().enumerate() {
-			s.extend(format!("{:?}: {:?}", k, v).chars());
-			if i != last {
-				s.push_str(", ");
+		let mut s = String::new();
+		self.fmt_nested(&mut Vec::new(), &mut s);
+		s
+	}
+}
+
+impl<V> fmt::Display for Array<V>
+where
+	V: VariantType,
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&ScriptType::to_string(self))
+	}
+}
+
+impl<V> fmt::Display for Dictionary<V>
+where
+	V: VariantType,
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&ScriptType::to_string(self))
+	}
+}
+
+/// Formats an element of an array or dictionary. Strings are quoted.
+fn fmt_element<V>(value: &V, seen: &mut Vec<*const ()>, s: &mut String)
+where
+	V: VariantType,
+{
+	if let Ok(o) = value.clone().into_object() {
+		if let Some(a) = o.cast::<Array<V>>() {
+			return a.fmt_nested(seen, s);
+		} else if let Some(d) = o.cast::<Dictionary<V>>() {
+			return d.fmt_nested(seen, s);
+		}
+	}
+	s.push_str(&format!("{:?}", value));
+}
+
+/// Copies an element of an array or dictionary. Arrays and dictionaries are copied recursively.
+fn copy_element<V>(value: &V, copies: &mut Vec<(*const (), V)>) -> CallResult<V>
+where
+	V: VariantType,
+{
+	if let Ok(o) = value.clone().into_object() {
+		if let Some(a) = o.cast::<Array<V>>() {
+			return a.copy_nested(copies);
+		} else if let Some(d) = o.cast::<Dictionary<V>>() {
+			return d.copy_nested(copies);
+		}
+	}
+	Ok(value.clone())
+}
+
+/// Compares an element of an array or dictionary.
+fn eq_element<V>(a: &V, b: &V, seen: &mut Vec<(*const (), *const ())>) -> bool
+where
+	V: VariantType,
+{
+	match (a.clone().into_object(), b.clone().into_object()) {
+		(Ok(a), Ok(b)) => {
+			if let (Some(a), Some(b)) = (a.cast::<Array<V>>(), b.cast::<Array<V>>()) {
+				a.eq_nested(b, seen)
+			} else if let (Some(a), Some(b)) =
+				(a.cast::<Dictionary<V>>(), b.cast::<Dictionary<V>>())
+			{
+				a.eq_nested(b, seen)
+			} else {
+				a.equals(&b)
 			}
 		}
-		s.push('}');
-		s
+		_ => a == b,
+	}
+}
+
+impl Range {
+	/// Creates a new range. Returns `None` if `step` is zero.
+	pub fn new(start: Integer, stop: Integer, step: Integer) -> Option<Self> {
+		(step != 0).then_some(Self { start, stop, step })
+	}
+}
+
+impl<V> ScriptType<V> for Range
+where
+	V: VariantType,
+{
+	fn call_self(
+		&self,
+		_: &ScriptObject<V>,
+		function: &str,
+		_: &[&V],
+		_: &Environment<V>,
+	) -> CallResult<V> {
+		Err(CallError::undefined_function(function))
+	}
+
+	#[inline]
+	fn iter(&self) -> CallResult<Box<dyn Iterator<Item = V>>> {
+		let Self { start, stop, step } = *self;
+		let iter = core::iter::successors(Some(start), move |i| i.checked_add(step))
+			.take_while(move |&i| if step > 0 { i < stop } else { i > stop })
+			.map(V::new_integer);
+		Ok(Box::new(iter))
+	}
+
+	#[inline]
+	fn to_string(&self) -> String {
+		format!("range({}, {}, {})", self.start, self.stop, self.step)
 	}
 }
 
@@ -324,7 +794,7 @@ where
 		// The iterator is valid as long as the borrow isn't dropped
 		unsafe {
 			let borrow = borrow!(dictionary);
-			let borrow: Ref<'_, HashMap<VariantOrd, V>> = mem::transmute(borrow);
+			let borrow: Ref<'_, IndexMap<VariantOrd, V>> = mem::transmute(borrow);
 			let iter = borrow.keys();
 			let iter = mem::transmute(iter);
 			Ok(Self {
@@ -347,21 +817,79 @@ where
 	}
 }
 
+/// Converts an index to an offset from the start. Negative indices are counted from the end,
+/// e.g. `-1` is the last element. Returns `None` if a negative index goes past the start.
+// The conversion is only needed if `Integer` is wider than `usize`, e.g. with `int64`.
+#[allow(clippy::useless_conversion)]
+pub(crate) fn resolve_index(index: Integer, len: usize) -> Option<usize> {
+	if index < 0 {
+		usize::try_from(index.unsigned_abs())
+			.ok()
+			.and_then(|i| len.checked_sub(i))
+	} else {
+		usize::try_from(index).ok()
+	}
+}
+
+/// Converts a possibly negative index to an index in `[0, len]`.
+// See `resolve_index`.
+#[allow(clippy::useless_conversion)]
+fn clamp_index(index: Integer, len: usize) -> usize {
+	if index < 0 {
+		usize::try_from(index.unsigned_abs()).map_or(0, |i| len.saturating_sub(i))
+	} else {
+		usize::try_from(index).map_or(len, |i| i.min(len))
+	}
+}
+
+/// Returns the name of the function to use as a callback.
+fn callback_name<V>(name: &V) -> CallResult<Rc<str>>
+where
+	V: VariantType,
+{
+	name.clone()
+		.into_string()
+		.map_err(|_| CallError::bad_argument())
+}
+
 impl VariantOrd {
 	fn from_variant<V>(var: V) -> Result<Self, CallError>
 	where
 		V: VariantType,
 	{
-		Ok(match var.as_bool() {
-			Ok(v) => Self::Bool(v),
-			Err(v) => match v.as_integer() {
-				Ok(v) => Self::Integer(v),
-				Err(_) => match var.into_string() {
-					Ok(v) => Self::String(v),
-					Err(_) => return Err(CallError::IncompatibleType),
-				},
-			},
-		})
+		if let Ok(v) = var.as_bool() {
+			Ok(Self::Bool(v))
+		} else if let Ok(v) = var.as_integer() {
+			Ok(Self::Integer(v))
+		} else if let Ok(v) = var.as_real() {
+			// Integral reals are equal to integers, so they must be the same key
+			let i = v as Integer;
+			if i as Real == v {
+				Ok(Self::Integer(i))
+			} else {
+				Ok(Self::Real(Self::real_bits(v)))
+			}
+		} else if let Ok(v) = var.as_char() {
+			Ok(Self::Char(v))
+		} else {
+			var.into_string()
+				.map(Self::String)
+				.map_err(|_| CallError::IncompatibleType)
+		}
+	}
+
+	/// Converts a real to bits such that equal reals have equal bits: all NaNs are
+	/// considered equal to each other and `-0.0` is considered equal to `0.0`.
+	// The conversion is only needed if `Real` is `f32` with the `real32` feature.
+	#[allow(clippy::useless_conversion)]
+	fn real_bits(r: Real) -> u64 {
+		if r.is_nan() {
+			u64::MAX
+		} else if r == 0.0 {
+			0
+		} else {
+			f64::from(r).to_bits()
+		}
 	}
 
 	fn into_variant<V>(self) -> V
@@ -371,6 +899,8 @@ impl VariantOrd {
 		match self {
 			Self::Bool(b) => V::new_bool(b),
 			Self::Integer(i) => V::new_integer(i),
+			Self::Real(r) => V::new_real(f64::from_bits(r) as Real),
+			Self::Char(c) => V::new_char(c),
 			Self::String(s) => V::new_string(s),
 		}
 	}
@@ -381,7 +911,71 @@ impl fmt::Debug for VariantOrd {
 		match self {
 			Self::Bool(b) => write!(f, "{}", b),
 			Self::Integer(b) => write!(f, "{}", b),
-			Self::String(b) => write!(f, "{}", b),
+			Self::Real(b) => write!(f, "{}", f64::from_bits(*b) as Real),
+			Self::Char(b) => write!(f, "{}", b),
+			Self::String(b) => write!(f, "\"{}\"", b),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::Variant;
+
+	#[test]
+	fn array_negative_index() {
+		let a = Array::<Variant>::with_len(3);
+		for i in 0..3 {
+			a.set_index(&Variant::Integer(i), Variant::Integer(i * 10))
+				.unwrap();
+		}
+		let get = |i| a.index(&Variant::Integer(i));
+		assert_eq!(get(-1).unwrap(), Variant::Integer(20));
+		assert_eq!(get(-3).unwrap(), Variant::Integer(0));
+		assert!(get(-4).is_err());
+		assert!(get(3).is_err());
+		a.set_index(&Variant::Integer(-2), Variant::Bool(true))
+			.unwrap();
+		assert_eq!(get(1).unwrap(), Variant::Bool(true));
+		assert!(a.set_index(&Variant::Integer(-4), Variant::None).is_err());
+	}
+
+	#[test]
+	fn dictionary_keys() {
+		let d = Dictionary::<Variant>::new();
+		let keys = [
+			Variant::Integer(3),
+			Variant::String("3".into()),
+			Variant::Char('3'),
+			Variant::Real(3.5),
+			Variant::Bool(true),
+		];
+		for (i, k) in keys.iter().enumerate() {
+			d.set_index(k, Variant::Integer(i as Integer)).unwrap();
+		}
+		assert_eq!(d.len(), keys.len());
+		for (i, k) in keys.iter().enumerate() {
+			assert_eq!(d.index(k).unwrap(), Variant::Integer(i as Integer));
 		}
+		d.set_index(&Variant::Real(Real::NAN), Variant::Integer(5))
+			.unwrap();
+		assert_eq!(
+			d.index(&Variant::Real(Real::NAN)).unwrap(),
+			Variant::Integer(5)
+		);
+		d.set_index(&Variant::Real(-0.0), Variant::Integer(6))
+			.unwrap();
+		assert_eq!(d.index(&Variant::Real(0.0)).unwrap(), Variant::Integer(6));
+		assert_eq!(d.index(&Variant::Integer(0)).unwrap(), Variant::Integer(6));
+		// Integral reals are the same key as the equal integer
+		assert_eq!(d.index(&Variant::Real(3.0)).unwrap(), Variant::Integer(0));
+		d.set_index(&Variant::Real(3.0), Variant::Integer(7))
+			.unwrap();
+		assert_eq!(d.index(&Variant::Integer(3)).unwrap(), Variant::Integer(7));
+		assert_eq!(d.len(), keys.len() + 2);
+		assert!(d.set_index(&Variant::None, Variant::None).is_err());
+		let array = Variant::Object(ScriptObject(Rc::new(Array::<Variant>::new())));
+		assert!(d.index(&array).is_err());
 	}
 }
@@ -1,5 +1,54 @@
+use crate::std_types::FxHashSet;
+#[cfg(not(feature = "std"))]
+use crate::std_types::*;
+use crate::Rc;
+use core::cmp;
 use core::ops::Deref;
-use std::rc::Rc;
+
+/// Returns the shared copy of a string, adding it to the map if it isn't in it yet.
+pub(crate) fn intern(
+	map: &mut FxHashSet<Rc<str>>,
+	string: impl Into<Rc<str>> + Deref<Target = str>,
+) -> Rc<str> {
+	if let Some(string) = map.get(&*string) {
+		string.clone()
+	} else {
+		let string: Rc<str> = string.into();
+		map.insert(string.clone());
+		string
+	}
+}
+
+/// Sorts a slice with a stable merge sort, stopping at the first error returned by `compare`.
+///
+/// Unlike [`slice::sort_by`] this doesn't panic if `compare` isn't a total order, which can't
+/// be guaranteed if elements of different types are compared.
+pub(crate) fn try_sort_by<T: Clone, E>(
+	slice: &mut [T],
+	compare: &mut impl FnMut(&T, &T) -> Result<cmp::Ordering, E>,
+) -> Result<(), E> {
+	if slice.len() < 2 {
+		return Ok(());
+	}
+	let mid = slice.len() / 2;
+	try_sort_by(&mut slice[..mid], compare)?;
+	try_sort_by(&mut slice[mid..], compare)?;
+	let (mut i, mut j) = (0, mid);
+	let mut merged = Vec::with_capacity(slice.len());
+	while i < mid && j < slice.len() {
+		if compare(&slice[j], &slice[i])? == cmp::Ordering::Less {
+			merged.push(slice[j].clone());
+			j += 1;
+		} else {
+			merged.push(slice[i].clone());
+			i += 1;
+		}
+	}
+	merged.extend_from_slice(&slice[i..mid]);
+	merged.extend_from_slice(&slice[j..]);
+	slice.clone_from_slice(&merged);
+	Ok(())
+}
 
 /// A string that is either a slice or a [`String`](std::str::String)
 #[derive(Clone, Debug)]
@@ -39,6 +88,6 @@ impl Deref for Str<'_> {
 
 impl PartialEq for Str<'_> {
 	fn eq(&self, rhs: &Self) -> bool {
-		self == rhs
+		**self == **rhs
 	}
 }
@@ -4,15 +4,33 @@
 
 pub mod specialized;
 
+use crate::ast::{parse_number, Atom};
 #[cfg(not(feature = "std"))]
 use crate::std_types::*;
-use crate::{CallError, CallResult, Environment, Rc, ScriptObject};
+use crate::types::resolve_index;
+use crate::{
+	Array, CallError, CallResult, Dictionary, Environment, Integer, Rc, Real, ScriptObject,
+	UInteger,
+};
+use core::convert::TryFrom;
 use core::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Rem, Shl, Shr, Sub};
 use core::{cmp, fmt};
 
 /// This trait must be implemented on custom Variant types.
 /// A custom variant is useful if you have a type that is common and needs to
 /// be able to be processed relatively quickly.
+///
+/// The `new_*` methods create values for literals and the results of builtin operations.
+/// The `as_*` and `into_*` methods return the value as the given type or give back the
+/// value itself if it is of a different type. The remaining methods implement operators,
+/// method calls and builtin functions. Those that have a default implementation are
+/// optional: they either derive their result from other methods or fail with
+/// [`CallError::IncompatibleType`].
+///
+/// Comparison operators use [`PartialEq`] and [`PartialOrd`]. These must treat integers and
+/// reals as numbers that can be compared with each other, e.g. `3 < 3.5` and `2 == 2.0` are
+/// `true`. [`VariantType::numeric_cmp`] implements this for any type, so only the other
+/// values need to be handled.
 pub trait VariantType
 where
 	Self:
@@ -20,30 +38,72 @@ where
 {
 	fn new_bool(value: bool) -> Self;
 
-	fn new_integer(value: isize) -> Self;
+	fn new_integer(value: Integer) -> Self;
 
-	fn new_real(value: f64) -> Self;
+	fn new_real(value: Real) -> Self;
+
+	fn new_char(value: char) -> Self;
 
 	fn new_string(value: Rc<str>) -> Self;
 
+	fn new_bytes(value: Rc<[u8]>) -> Self;
+
 	fn new_object(value: ScriptObject<Self>) -> Self;
 
+	/// Creates the value of the `none` literal.
+	#[inline]
+	fn new_none() -> Self {
+		Self::default()
+	}
+
+	fn is_none(&self) -> bool;
+
 	fn as_bool(&self) -> Result<bool, &Self>;
 
-	fn as_integer(&self) -> Result<isize, &Self>;
+	/// Whether the value is truthy, i.e. `true`, a nonzero number, a non-empty string or any
+	/// other value that isn't `none`. Used by conditions if [`Environment::set_truthy`] is
+	/// enabled.
+	fn as_bool_truthy(&self) -> bool {
+		if let Ok(b) = self.as_bool() {
+			b
+		} else if let Ok(i) = self.as_integer() {
+			i != 0
+		} else if let Ok(r) = self.as_real() {
+			r != 0.0
+		} else if self.is_none() {
+			false
+		} else {
+			self.clone().into_string().map_or(true, |s| !s.is_empty())
+		}
+	}
+
+	fn as_integer(&self) -> Result<Integer, &Self>;
+
+	fn as_real(&self) -> Result<Real, &Self>;
 
-	fn as_real(&self) -> Result<f64, &Self>;
+	fn as_char(&self) -> Result<char, &Self>;
 
 	fn into_string(self) -> Result<Rc<str>, Self>;
 
 	fn into_object(self) -> Result<ScriptObject<Self>, Self>;
 
+	/// Returns the object if methods called on this value are handled by it. This allows
+	/// the interpreter to cache the methods it resolves.
+	#[inline]
+	fn as_object(&self) -> Option<&ScriptObject<Self>> {
+		None
+	}
+
+	/// Calls a method on this value, e.g. `x.abs()`.
 	fn call(&self, function: &str, args: &[&Self], env: &Environment<Self>) -> CallResult<Self>;
 
+	/// Returns an iterator over the values of a `for` loop.
 	fn iter(&self) -> CallResult<Box<dyn Iterator<Item = Self>>>;
 
+	/// Implements `x[index]`.
 	fn index(&self, index: &Self) -> CallResult<Self>;
 
+	/// Implements `x[index] = value`.
 	fn set_index(&self, index: &Self, value: Self) -> CallResult<()>;
 
 	// TODO this is stupid as hell but I'm out of ideas
@@ -57,8 +117,58 @@ where
 	fn bitxor(&self, rhs: &Self) -> CallResult<Self>;
 	fn lhs(&self, rhs: &Self) -> CallResult<Self>;
 	fn rhs(&self, rhs: &Self) -> CallResult<Self>;
+	/// Shifts right while filling the high bits with zeroes instead of the sign bit. Used by
+	/// the `>>>` operator.
+	fn rhs_unsigned(&self, rhs: &Self) -> CallResult<Self> {
+		let _ = rhs;
+		Err(CallError::incompatible_type())
+	}
 	fn neg(&self) -> CallResult<Self>;
 	fn not(&self) -> CallResult<Self>;
+
+	/// Compares two values to sort them. Unlike [`PartialOrd::partial_cmp`] NaNs can be
+	/// compared: they are equal to each other, regardless of sign, and greater than any other
+	/// number. Returns `None` if the values can't be compared, e.g. a number and a string.
+	fn sort_cmp(&self, rhs: &Self) -> Option<cmp::Ordering> {
+		let nan = |v: &Self| v.as_real().is_ok_and(|r| r.is_nan());
+		let number = |v: &Self| v.as_real().is_ok() || v.as_integer().is_ok();
+		match (nan(self), nan(rhs)) {
+			(false, false) => self.partial_cmp(rhs),
+			(a, b) if number(self) && number(rhs) => Some(a.cmp(&b)),
+			_ => None,
+		}
+	}
+
+	/// Compares two numbers. An integer compared with a real is converted to a real first.
+	/// Returns `None` if either value is not a number or is NaN.
+	fn numeric_cmp(&self, rhs: &Self) -> Option<cmp::Ordering> {
+		let real = |v: &Self| {
+			v.as_real()
+				.ok()
+				.or_else(|| Some(v.as_integer().ok()? as Real))
+		};
+		match (self.as_integer(), rhs.as_integer()) {
+			(Ok(a), Ok(b)) => Some(a.cmp(&b)),
+			_ => real(self)?.partial_cmp(&real(rhs)?),
+		}
+	}
+
+	/// Returns the absolute value. Used by the `abs` builtin.
+	fn abs(&self) -> CallResult<Self> {
+		Err(CallError::incompatible_type())
+	}
+
+	/// Returns `-1`, `0` or `1` depending on the sign of the value. Used by the `sign`
+	/// builtin.
+	fn sign(&self) -> CallResult<Self> {
+		Err(CallError::incompatible_type())
+	}
+
+	/// Restricts the value to the range `min..=max`. Used by the `clamp` builtin.
+	fn clamp(&self, min: &Self, max: &Self) -> CallResult<Self> {
+		let _ = (min, max);
+		Err(CallError::incompatible_type())
+	}
 }
 
 #[derive(Clone)]
@@ -67,10 +177,11 @@ where
 pub enum Variant {
 	None,
 	Bool(bool),
-	Real(f64),
-	Integer(isize),
+	Real(Real),
+	Integer(Integer),
 	Char(char),
 	String(Rc<str>),
+	Bytes(Rc<[u8]>),
 	Object(ScriptObject<Self>),
 }
 
@@ -108,7 +219,13 @@ macro_rules! gen_op {
 			fn $fn(self, rhs: &'a $variant) -> Self::Output {
 				Ok(match (self, rhs) {
 					$((Variant::$lhs($left), Variant::$rhs($right)) => Variant::$out($code),)*
-					_ => return Err(CallError::incompatible_type()),
+					_ => {
+						return Err(CallError::incompatible_types(
+							stringify!($fn),
+							self.type_name(),
+							rhs.type_name(),
+						))
+					}
 				})
 			}
 		}
@@ -119,49 +236,68 @@ gen_op!(
 	Variant, Add, add
 	[rhs, lhs]
 	[Real, Real] => Real { rhs + lhs }
-	[Real, Integer] => Real { rhs + *lhs as f64 }
-	[Integer, Real] => Real { *rhs as f64 + lhs }
-	[Integer, Integer] => Integer { rhs + lhs }
+	[Real, Integer] => Real { rhs + *lhs as Real }
+	[Integer, Real] => Real { *rhs as Real + lhs }
+	[Integer, Integer] => Integer { rhs.checked_add(*lhs).ok_or_else(CallError::overflow)? }
 	[String, String] => String {
 		let mut out = rhs.to_string();
 		out.extend(lhs.chars());
 		out.into()
 	}
+	[String, Integer] => String { format!("{}{}", rhs, lhs).into() }
+	[String, Real] => String { format!("{}{}", rhs, lhs).into() }
+	[String, Bool] => String { format!("{}{}", rhs, lhs).into() }
+	[String, Char] => String { format!("{}{}", rhs, lhs).into() }
+	[Integer, String] => String { format!("{}{}", rhs, lhs).into() }
+	[Real, String] => String { format!("{}{}", rhs, lhs).into() }
+	[Bool, String] => String { format!("{}{}", rhs, lhs).into() }
+	[Char, String] => String { format!("{}{}", rhs, lhs).into() }
+	[Object, Object] => Object { rhs.add(lhs)? }
 );
 
 gen_op!(
 	Variant, Sub, sub
 	[rhs, lhs]
 	[Real, Real] => Real { rhs - lhs }
-	[Real, Integer] => Real { rhs - *lhs as f64 }
-	[Integer, Integer] => Integer { rhs - lhs }
+	[Real, Integer] => Real { rhs - *lhs as Real }
+	[Integer, Integer] => Integer { rhs.checked_sub(*lhs).ok_or_else(CallError::overflow)? }
 );
 
 gen_op!(
 	Variant, Mul, mul
 	[rhs, lhs]
 	[Real, Real] => Real { rhs * lhs }
-	[Real, Integer] => Real { rhs * *lhs as f64 }
-	[Integer, Real] => Real { *rhs as f64 * lhs }
-	[Integer, Integer] => Integer { rhs * lhs }
+	[Real, Integer] => Real { rhs * *lhs as Real }
+	[Integer, Real] => Real { *rhs as Real * lhs }
+	[Integer, Integer] => Integer { rhs.checked_mul(*lhs).ok_or_else(CallError::overflow)? }
 );
 
 gen_op!(
 	Variant, Div, div
 	[rhs, lhs]
 	[Real, Real] => Real { rhs / lhs }
-	[Real, Integer] => Real { rhs / *lhs as f64 }
-	[Integer, Real] => Real { *rhs as f64 / lhs }
-	[Integer, Integer] => Integer { rhs / lhs }
+	[Real, Integer] => Real { rhs / *lhs as Real }
+	[Integer, Real] => Real { *rhs as Real / lhs }
+	[Integer, Integer] => Integer {
+		if *lhs == 0 {
+			return Err(CallError::divide_by_zero());
+		}
+		rhs.checked_div(*lhs).ok_or_else(CallError::overflow)?
+	}
 );
 
 gen_op!(
 	Variant, Rem, rem
 	[rhs, lhs]
 	[Real, Real] => Real { rhs % lhs }
-	[Real, Integer] => Real { rhs % *lhs as f64 }
-	[Integer, Real] => Real { *rhs as f64 % lhs }
-	[Integer, Integer] => Integer { rhs % lhs }
+	[Real, Integer] => Real { rhs % *lhs as Real }
+	[Integer, Real] => Real { *rhs as Real % lhs }
+	[Integer, Integer] => Integer {
+		if *lhs == 0 {
+			return Err(CallError::divide_by_zero());
+		}
+		rhs.checked_rem(*lhs).ok_or_else(CallError::overflow)?
+	}
 );
 
 gen_op!(
@@ -188,13 +324,17 @@ gen_op!(
 gen_op!(
 	Variant, Shl, shl
 	[rhs, lhs]
-	[Integer, Integer] => Integer { rhs << lhs }
+	[Integer, Integer] => Integer {
+		u32::try_from(*lhs).ok().and_then(|s| rhs.checked_shl(s)).ok_or_else(CallError::overflow)?
+	}
 );
 
 gen_op!(
 	Variant, Shr, shr
 	[rhs, lhs]
-	[Integer, Integer] => Integer { rhs >> lhs }
+	[Integer, Integer] => Integer {
+		u32::try_from(*lhs).ok().and_then(|s| rhs.checked_shr(s)).ok_or_else(CallError::overflow)?
+	}
 );
 
 impl core::ops::Neg for &Variant {
@@ -204,7 +344,7 @@ impl core::ops::Neg for &Variant {
 	fn neg(self) -> Self::Output {
 		use Variant::*;
 		Ok(match self {
-			Integer(i) => Integer(-i),
+			Integer(i) => Integer(i.checked_neg().ok_or_else(CallError::overflow)?),
 			Real(r) => Real(-r),
 			_ => return Err(CallError::incompatible_type()),
 		})
@@ -232,13 +372,16 @@ impl PartialEq<Self> for Variant {
 	fn eq(&self, rhs: &Self) -> bool {
 		use Variant::*;
 		match (self, rhs) {
+			(None, None) => true,
 			(Bool(a), Bool(b)) => a == b,
 			(Real(a), Real(b)) => a == b,
-			(Real(a), Integer(b)) => *a == *b as f64,
-			(Integer(a), Real(b)) => *a as f64 == *b,
+			(Real(a), Integer(b)) => *a == *b as crate::Real,
+			(Integer(a), Real(b)) => *a as crate::Real == *b,
 			(Integer(a), Integer(b)) => a == b,
 			(String(a), String(b)) => a == b,
+			(Bytes(a), Bytes(b)) => a == b,
 			(Char(a), Char(b)) => a == b,
+			(Object(a), Object(b)) => a.equals(b),
 			_ => false,
 		}
 	}
@@ -252,10 +395,11 @@ impl PartialOrd<Self> for Variant {
 		match (self, rhs) {
 			(Bool(a), Bool(b)) => a.partial_cmp(b),
 			(Real(a), Real(b)) => a.partial_cmp(b),
-			(Real(a), Integer(b)) => a.partial_cmp(&(*b as f64)),
-			(Integer(a), Real(b)) => (*a as f64).partial_cmp(b),
+			(Real(a), Integer(b)) => a.partial_cmp(&(*b as crate::Real)),
+			(Integer(a), Real(b)) => (*a as crate::Real).partial_cmp(b),
 			(Integer(a), Integer(b)) => a.partial_cmp(b),
 			(String(a), String(b)) => a.partial_cmp(b),
+			(Bytes(a), Bytes(b)) => a.partial_cmp(b),
 			(Char(a), Char(b)) => a.partial_cmp(b),
 			_ => Option::None,
 		}
@@ -271,6 +415,81 @@ impl Variant {
 			Err(self)
 		}
 	}
+
+	/// Converts an integer or real argument to a real.
+	#[inline]
+	fn real_arg(&self) -> CallResult<Real> {
+		match self {
+			Self::Integer(i) => Ok(*i as Real),
+			Self::Real(r) => Ok(*r),
+			_ => Err(CallError::bad_argument()),
+		}
+	}
+
+	/// Converts an integer argument to the index of a bit in an [`Integer`].
+	#[inline]
+	fn bit_arg(&self) -> CallResult<u32> {
+		self.as_integer()
+			.ok()
+			.and_then(|i| u32::try_from(i).ok())
+			.filter(|&i| i < Integer::BITS)
+			.ok_or_else(CallError::bad_argument)
+	}
+
+	/// Returns the length of a string, array or dictionary. The length of a string is
+	/// the amount of characters, not bytes, so that it matches the amount of iterations.
+	pub fn length(&self) -> CallResult<usize> {
+		match self {
+			Self::String(s) => Ok(s.chars().count()),
+			Self::Bytes(b) => Ok(b.len()),
+			Self::Object(o) => {
+				if let Some(a) = o.cast::<Array<Self>>() {
+					Ok(a.len())
+				} else if let Some(d) = o.cast::<Dictionary<Self>>() {
+					Ok(d.len())
+				} else {
+					Err(CallError::incompatible_type())
+				}
+			}
+			_ => Err(CallError::incompatible_type()),
+		}
+	}
+
+	/// The name of the type of the value, as shown in error messages.
+	pub fn type_name(&self) -> &'static str {
+		match self {
+			Self::None => "None",
+			Self::Bool(_) => "Bool",
+			Self::Real(_) => "Real",
+			Self::Integer(_) => "Integer",
+			Self::Char(_) => "Char",
+			Self::String(_) => "String",
+			Self::Bytes(_) => "Bytes",
+			Self::Object(o) if o.cast::<Array<Self>>().is_some() => "Array",
+			Self::Object(o) if o.cast::<Dictionary<Self>>().is_some() => "Dictionary",
+			Self::Object(_) => "Object",
+		}
+	}
+
+	/// Returns a reference to the object if this is an object of type `T`. This allows
+	/// hosts to recover custom objects that were created or passed around by scripts.
+	#[inline]
+	pub fn downcast_ref<T: 'static>(&self) -> Option<&T> {
+		match self {
+			Self::Object(o) => o.cast(),
+			_ => None,
+		}
+	}
+
+	/// Some characters map to multiple characters when changing case (e.g. `ß` -> `SS`),
+	/// in which case a `String` is returned instead of a `Char`.
+	fn from_case_mapping(mut chars: impl ExactSizeIterator<Item = char>) -> Self {
+		if chars.len() == 1 {
+			Self::Char(chars.next().unwrap())
+		} else {
+			Self::String(chars.collect::<String>().into())
+		}
+	}
 }
 
 impl VariantType for Variant {
@@ -280,25 +499,45 @@ impl VariantType for Variant {
 	}
 
 	#[inline]
-	fn new_integer(value: isize) -> Self {
+	fn new_integer(value: Integer) -> Self {
 		Self::Integer(value)
 	}
 
 	#[inline]
-	fn new_real(value: f64) -> Self {
+	fn new_real(value: Real) -> Self {
 		Self::Real(value)
 	}
 
+	#[inline]
+	fn new_char(value: char) -> Self {
+		Self::Char(value)
+	}
+
 	#[inline]
 	fn new_string(value: Rc<str>) -> Self {
 		Self::String(value)
 	}
 
+	#[inline]
+	fn new_bytes(value: Rc<[u8]>) -> Self {
+		Self::Bytes(value)
+	}
+
 	#[inline]
 	fn new_object(value: ScriptObject<Self>) -> Self {
 		Self::Object(value)
 	}
 
+	#[inline]
+	fn new_none() -> Self {
+		Self::None
+	}
+
+	#[inline]
+	fn is_none(&self) -> bool {
+		matches!(self, Self::None)
+	}
+
 	#[inline]
 	fn as_bool(&self) -> Result<bool, &Self> {
 		if let Self::Bool(b) = self {
@@ -309,7 +548,20 @@ impl VariantType for Variant {
 	}
 
 	#[inline]
-	fn as_integer(&self) -> Result<isize, &Self> {
+	fn as_bool_truthy(&self) -> bool {
+		match self {
+			Self::None => false,
+			Self::Bool(b) => *b,
+			Self::Integer(i) => *i != 0,
+			Self::Real(r) => *r != 0.0,
+			Self::String(s) => !s.is_empty(),
+			Self::Bytes(b) => !b.is_empty(),
+			Self::Char(_) | Self::Object(_) => true,
+		}
+	}
+
+	#[inline]
+	fn as_integer(&self) -> Result<Integer, &Self> {
 		if let Self::Integer(b) = self {
 			Ok(*b)
 		} else {
@@ -318,7 +570,7 @@ impl VariantType for Variant {
 	}
 
 	#[inline]
-	fn as_real(&self) -> Result<f64, &Self> {
+	fn as_real(&self) -> Result<Real, &Self> {
 		if let Self::Real(b) = self {
 			Ok(*b)
 		} else {
@@ -326,6 +578,15 @@ impl VariantType for Variant {
 		}
 	}
 
+	#[inline]
+	fn as_char(&self) -> Result<char, &Self> {
+		if let Self::Char(b) = self {
+			Ok(*b)
+		} else {
+			Err(self)
+		}
+	}
+
 	#[inline]
 	fn into_string(self) -> Result<Rc<str>, Self> {
 		if let Self::String(b) = self {
@@ -344,7 +605,28 @@ impl VariantType for Variant {
 		}
 	}
 
+	#[inline]
+	fn as_object(&self) -> Option<&ScriptObject<Self>> {
+		if let Self::Object(b) = self {
+			Some(b)
+		} else {
+			None
+		}
+	}
+
 	fn call(&self, function: &str, args: &[&Self], env: &Environment<Self>) -> CallResult<Self> {
+		// Methods that are available on every variant
+		match function {
+			"str" => {
+				check_arg_count!(args, 0);
+				return Ok(Self::String(self.to_string().into()));
+			}
+			"is_none" => {
+				check_arg_count!(args, 0);
+				return Ok(Self::Bool(self.is_none()));
+			}
+			_ => (),
+		}
 		Ok(match self {
 			Self::None => return Err(CallError::empty()),
 			Self::Real(r) => match function {
@@ -356,19 +638,154 @@ impl VariantType for Variant {
 					check_arg_count!(args, 0);
 					Self::Real(r.sqrt())
 				}
-				_ => return Err(CallError::undefined_function()),
+				"hypot" => {
+					check_arg_count!(args, 1);
+					Self::Real(r.hypot(args[0].real_arg()?))
+				}
+				"abs_diff" => {
+					check_arg_count!(args, 1);
+					Self::Real((r - args[0].real_arg()?).abs())
+				}
+				"round_to" => {
+					check_arg_count!(args, 1);
+					let digits = args[0]
+						.as_integer()
+						.map_err(|_| CallError::bad_argument())?;
+					// Limit the length of the string to something sensible.
+					let digits = u8::try_from(digits).map_err(|_| CallError::bad_argument())?;
+					Self::String(format!("{:.*}", usize::from(digits), r).into())
+				}
+				"is_nan" => {
+					check_arg_count!(args, 0);
+					Self::Bool(r.is_nan())
+				}
+				"is_infinite" => {
+					check_arg_count!(args, 0);
+					Self::Bool(r.is_infinite())
+				}
+				"is_finite" => {
+					check_arg_count!(args, 0);
+					Self::Bool(r.is_finite())
+				}
+				_ => return Err(CallError::undefined_function(function)),
 			},
 			Self::Integer(i) => match function {
 				"abs" => {
 					check_arg_count!(args, 0);
-					Self::Integer(i.abs())
+					Self::Integer(i.checked_abs().ok_or_else(CallError::overflow)?)
 				}
-				_ => return Err(CallError::undefined_function()),
+				"sqrt" => {
+					check_arg_count!(args, 0);
+					Self::Real((*i as Real).sqrt())
+				}
+				"hypot" => {
+					check_arg_count!(args, 1);
+					Self::Real((*i as Real).hypot(args[0].real_arg()?))
+				}
+				"abs_diff" => {
+					check_arg_count!(args, 1);
+					match args[0] {
+						Self::Integer(j) => Self::Integer(
+							Integer::try_from(i.abs_diff(*j)).map_err(|_| CallError::overflow())?,
+						),
+						Self::Real(r) => Self::Real((*i as Real - r).abs()),
+						_ => return Err(CallError::bad_argument()),
+					}
+				}
+				"pow" => {
+					check_arg_count!(args, 1);
+					let exp = args[0]
+						.as_integer()
+						.map_err(|_| CallError::bad_argument())?;
+					let exp = u32::try_from(exp).map_err(|_| CallError::bad_argument())?;
+					Self::Integer(i.checked_pow(exp).ok_or_else(CallError::overflow)?)
+				}
+				"to_char" => {
+					check_arg_count!(args, 0);
+					let c = u32::try_from(*i).ok().and_then(char::from_u32);
+					Self::Char(c.ok_or_else(CallError::incompatible_type)?)
+				}
+				"is_nan" | "is_infinite" => {
+					check_arg_count!(args, 0);
+					Self::Bool(false)
+				}
+				"is_finite" => {
+					check_arg_count!(args, 0);
+					Self::Bool(true)
+				}
+				"count_ones" => {
+					check_arg_count!(args, 0);
+					Self::Integer(i.count_ones() as Integer)
+				}
+				"leading_zeros" => {
+					check_arg_count!(args, 0);
+					Self::Integer(i.leading_zeros() as Integer)
+				}
+				"trailing_zeros" => {
+					check_arg_count!(args, 0);
+					Self::Integer(i.trailing_zeros() as Integer)
+				}
+				"bit" => {
+					check_arg_count!(args, 1);
+					Self::Bool(i >> args[0].bit_arg()? & 1 == 1)
+				}
+				"with_bit" => {
+					check_arg_count!(args, 2);
+					let bit = 1 << args[0].bit_arg()?;
+					match args[1].as_bool() {
+						Ok(true) => Self::Integer(i | bit),
+						Ok(false) => Self::Integer(i & !bit),
+						Err(_) => return Err(CallError::bad_argument()),
+					}
+				}
+				_ => return Err(CallError::undefined_function(function)),
+			},
+			Self::Char(c) => match function {
+				"to_int" => {
+					check_arg_count!(args, 0);
+					Self::Integer(*c as Integer)
+				}
+				"is_digit" => {
+					check_arg_count!(args, 0);
+					Self::Bool(c.is_ascii_digit())
+				}
+				"is_alpha" => {
+					check_arg_count!(args, 0);
+					Self::Bool(c.is_alphabetic())
+				}
+				"is_whitespace" => {
+					check_arg_count!(args, 0);
+					Self::Bool(c.is_whitespace())
+				}
+				"to_upper" => {
+					check_arg_count!(args, 0);
+					Self::from_case_mapping(c.to_uppercase())
+				}
+				"to_lower" => {
+					check_arg_count!(args, 0);
+					Self::from_case_mapping(c.to_lowercase())
+				}
+				_ => return Err(CallError::undefined_function(function)),
 			},
 			Self::String(s) => match function {
 				"len" => {
 					check_arg_count!(args, 0);
-					Variant::Integer(s.len() as isize)
+					Variant::Integer(self.length()? as Integer)
+				}
+				"parse_int" => {
+					check_arg_count!(args, 0);
+					match parse_number(s) {
+						Ok(Atom::Integer(i)) => Self::Integer(i),
+						_ => return Err(CallError::incompatible_type()),
+					}
+				}
+				"parse_real" => {
+					check_arg_count!(args, 0);
+					match parse_number(s) {
+						Ok(Atom::Real(r)) => Self::Real(r),
+						Ok(Atom::Integer(i)) => Self::Real(i as Real),
+						_ => return Err(CallError::incompatible_type()),
+					}
 				}
 				"strip" => {
 					check_arg_count!(args <= 2);
@@ -406,10 +823,26 @@ impl VariantType for Variant {
 					};
 					Variant::String(s.trim_start_matches(|c| lpat.contains(c)).into())
 				}
-				_ => return Err(CallError::undefined_function()),
+				"to_bytes" => {
+					check_arg_count!(args, 0);
+					Self::Bytes(s.as_bytes().into())
+				}
+				_ => return Err(CallError::undefined_function(function)),
+			},
+			Self::Bytes(b) => match function {
+				"len" => {
+					check_arg_count!(args, 0);
+					Self::Integer(b.len() as Integer)
+				}
+				"to_str" => {
+					check_arg_count!(args, 0);
+					let s = core::str::from_utf8(b).map_err(|_| CallError::invalid_utf8())?;
+					Self::String(s.into())
+				}
+				_ => return Err(CallError::undefined_function(function)),
 			},
 			Self::Object(o) => return o.call(function, args, env),
-			_ => return Err(CallError::undefined_function()),
+			_ => return Err(CallError::undefined_function(function)),
 		})
 	}
 
@@ -424,6 +857,12 @@ impl VariantType for Variant {
 				}
 			}
 			Variant::String(s) => Ok(Box::new(StringIter::new(s.clone()))),
+			Variant::Bytes(b) => {
+				let b = b.clone();
+				Ok(Box::new(
+					(0..b.len()).map(move |i| Variant::Integer(Integer::from(b[i]))),
+				))
+			}
 			Variant::Object(o) => o.iter(),
 			_ => Err(CallError::incompatible_type()),
 		}
@@ -433,6 +872,20 @@ impl VariantType for Variant {
 	fn index(&self, index: &Self) -> CallResult<Self> {
 		match self {
 			Self::Object(obj) => obj.index(index),
+			Self::String(s) => {
+				let i = index.as_integer().map_err(|_| CallError::bad_argument())?;
+				resolve_index(i, s.chars().count())
+					.and_then(|i| s.chars().nth(i))
+					.map(Self::Char)
+					.ok_or_else(CallError::bad_argument)
+			}
+			Self::Bytes(b) => {
+				let i = index.as_integer().map_err(|_| CallError::bad_argument())?;
+				resolve_index(i, b.len())
+					.and_then(|i| b.get(i))
+					.map(|&b| Self::Integer(Integer::from(b)))
+					.ok_or_else(CallError::bad_argument)
+			}
 			_ => Err(CallError::incompatible_type()),
 		}
 	}
@@ -486,6 +939,21 @@ impl VariantType for Variant {
 		self >> rhs
 	}
 	#[inline]
+	fn rhs_unsigned(&self, rhs: &Self) -> CallResult<Self> {
+		match (self, rhs) {
+			(Variant::Integer(a), Variant::Integer(b)) => u32::try_from(*b)
+				.ok()
+				.and_then(|s| (*a as UInteger).checked_shr(s))
+				.map(|r| Variant::Integer(r as Integer))
+				.ok_or_else(CallError::overflow),
+			_ => Err(CallError::incompatible_types(
+				"shr",
+				self.type_name(),
+				rhs.type_name(),
+			)),
+		}
+	}
+	#[inline]
 	fn neg(&self) -> CallResult<Self> {
 		-self
 	}
@@ -493,6 +961,18 @@ impl VariantType for Variant {
 	fn not(&self) -> CallResult<Self> {
 		!self
 	}
+
+	fn abs(&self) -> CallResult<Self> {
+		number::abs(self)
+	}
+
+	fn sign(&self) -> CallResult<Self> {
+		number::sign(self)
+	}
+
+	fn clamp(&self, min: &Self, max: &Self) -> CallResult<Self> {
+		number::clamp(self, min, max)
+	}
 }
 
 impl fmt::Debug for Variant {
@@ -510,6 +990,7 @@ impl fmt::Debug for Variant {
 				f.write_str(n)?;
 				f.write_char('"')
 			}
+			Variant::Bytes(n) => fmt_bytes(n, f),
 			Variant::Object(n) => f.write_str(n.to_string().as_str()),
 		}
 	}
@@ -526,11 +1007,25 @@ impl fmt::Display for Variant {
 			Variant::Integer(n) => f.write_str(n.to_string().as_str()),
 			Variant::Char(n) => f.write_char(*n),
 			Variant::String(n) => f.write_str(n),
+			Variant::Bytes(n) => fmt_bytes(n, f),
 			Variant::Object(n) => f.write_str(n.to_string().as_str()),
 		}
 	}
 }
 
+/// Formats bytes as `b"..."`. Bytes that aren't printable ASCII characters are escaped.
+fn fmt_bytes(bytes: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str("b\"")?;
+	for &b in bytes {
+		match b {
+			b'"' | b'\\' => write!(f, "\\{}", b as char)?,
+			0x20..=0x7e => write!(f, "{}", b as char)?,
+			_ => write!(f, "\\x{:02x}", b)?,
+		}
+	}
+	f.write_str("\"")
+}
+
 impl From<bool> for Variant {
 	fn from(var: bool) -> Self {
 		Variant::Bool(var)
@@ -564,3 +1059,325 @@ impl Iterator for StringIter<'_> {
 		self.iter.next().map(Variant::Char)
 	}
 }
+
+/// Implementations of the numeric methods of [`VariantType`] shared by variants that have both
+/// integers and reals.
+mod number {
+	use super::*;
+
+	pub(super) fn abs<V: VariantType>(v: &V) -> CallResult<V> {
+		match (v.as_integer(), v.as_real()) {
+			(Ok(i), _) => i
+				.checked_abs()
+				.map(V::new_integer)
+				.ok_or_else(CallError::overflow),
+			(_, Ok(r)) => Ok(V::new_real(r.abs())),
+			_ => Err(CallError::incompatible_type()),
+		}
+	}
+
+	pub(super) fn sign<V: VariantType>(v: &V) -> CallResult<V> {
+		match (v.as_integer(), v.as_real()) {
+			(Ok(i), _) => Ok(V::new_integer(i.signum())),
+			// `signum` returns 1 for 0
+			(_, Ok(r)) => Ok(V::new_real(if r == 0.0 { 0.0 } else { r.signum() })),
+			_ => Err(CallError::incompatible_type()),
+		}
+	}
+
+	pub(super) fn clamp<V: VariantType>(v: &V, min: &V, max: &V) -> CallResult<V> {
+		let real = |v: &V| {
+			v.as_real()
+				.or_else(|v| v.as_integer().map(|i| i as Real))
+				.map_err(|_| CallError::incompatible_type())
+		};
+		match (v.as_integer(), min.as_integer(), max.as_integer()) {
+			(Ok(x), Ok(min), Ok(max)) if min <= max => Ok(V::new_integer(x.clamp(min, max))),
+			_ => {
+				let (x, min, max) = (real(v)?, real(min)?, real(max)?);
+				// This also rejects NaN bounds
+				if min <= max {
+					Ok(V::new_real(x.clamp(min, max)))
+				} else {
+					Err(CallError::bad_argument())
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn call(var: &Variant, function: &str) -> Variant {
+		var.call(function, &[], &Environment::new()).unwrap()
+	}
+
+	#[test]
+	fn char_classify() {
+		let chars = Variant::String("a1 ".into())
+			.iter()
+			.unwrap()
+			.collect::<Vec<_>>();
+		let class = |f| chars.iter().map(|c| call(c, f)).collect::<Vec<_>>();
+		let (t, f) = (Variant::Bool(true), Variant::Bool(false));
+		assert_eq!(class("is_alpha"), [t.clone(), f.clone(), f.clone()]);
+		assert_eq!(class("is_digit"), [f.clone(), t.clone(), f.clone()]);
+		assert_eq!(class("is_whitespace"), [f.clone(), f, t]);
+		assert_eq!(
+			class("to_int"),
+			[
+				Variant::Integer(97),
+				Variant::Integer(49),
+				Variant::Integer(32)
+			]
+		);
+	}
+
+	#[test]
+	fn char_case() {
+		assert_eq!(call(&Variant::Char('a'), "to_upper"), Variant::Char('A'));
+		assert_eq!(call(&Variant::Char('A'), "to_lower"), Variant::Char('a'));
+		assert_eq!(call(&Variant::Char('1'), "to_upper"), Variant::Char('1'));
+		assert_eq!(
+			call(&Variant::Char('ß'), "to_upper"),
+			Variant::String("SS".into())
+		);
+	}
+
+	#[test]
+	fn string_concat() {
+		let s = |s: &str| Variant::String(s.into());
+		let add = |a: Variant, b: Variant| (&a + &b).unwrap();
+		assert_eq!(add(s("n: "), Variant::Integer(42)), s("n: 42"));
+		assert_eq!(add(s("n: "), Variant::Real(0.5)), s("n: 0.5"));
+		assert_eq!(add(s("n: "), Variant::Bool(true)), s("n: true"));
+		assert_eq!(add(s("n: "), Variant::Char('c')), s("n: c"));
+		assert_eq!(add(Variant::Integer(42), s(" apples")), s("42 apples"));
+		assert_eq!(add(Variant::Real(0.5), s(" apples")), s("0.5 apples"));
+		assert_eq!(add(Variant::Bool(false), s(" apples")), s("false apples"));
+		assert_eq!(add(Variant::Char('c'), s("at")), s("cat"));
+		assert_eq!(
+			add(Variant::Integer(1), Variant::Integer(2)),
+			Variant::Integer(3)
+		);
+		assert!((&Variant::None + &s("x")).is_err());
+	}
+
+	#[test]
+	fn length() {
+		assert_eq!(Variant::String("héllo".into()).length().unwrap(), 5);
+		let a = Array::<Variant>::with_len(2);
+		let a = Variant::Object(ScriptObject::new(Rc::new(a)));
+		assert_eq!(a.length().unwrap(), 2);
+		assert_eq!(call(&a, "len"), Variant::Integer(2));
+		let d = Dictionary::<Variant>::new();
+		let d = Variant::Object(ScriptObject::new(Rc::new(d)));
+		assert_eq!(d.length().unwrap(), 0);
+		assert!(Variant::Integer(3).length().is_err());
+	}
+
+	#[test]
+	fn string_index() {
+		let s = Variant::String("abç".into());
+		let index = |i| s.index(&Variant::Integer(i));
+		assert_eq!(index(0).unwrap(), Variant::Char('a'));
+		assert_eq!(index(2).unwrap(), Variant::Char('ç'));
+		assert_eq!(index(-1).unwrap(), Variant::Char('ç'));
+		assert_eq!(index(-3).unwrap(), Variant::Char('a'));
+		assert!(index(3).is_err());
+		assert!(index(-4).is_err());
+		assert!(s.index(&Variant::Real(0.0)).is_err());
+		assert!(s
+			.set_index(&Variant::Integer(0), Variant::Char('x'))
+			.is_err());
+	}
+
+	#[test]
+	fn neg() {
+		assert_eq!((-&Variant::Integer(5)).unwrap(), Variant::Integer(-5));
+		assert_eq!((-&Variant::Real(2.5)).unwrap(), Variant::Real(-2.5));
+		assert!((-&Variant::Integer(Integer::MIN)).is_err());
+		assert!((-&Variant::String("x".into())).is_err());
+		assert!((-&Variant::Char('x')).is_err());
+		assert!((-&Variant::Bool(true)).is_err());
+		assert!((-&Variant::None).is_err());
+	}
+
+	#[test]
+	fn integer_math() {
+		let env = Environment::new();
+		let int = Variant::Integer;
+		assert_eq!(call(&int(-5), "abs"), int(5));
+		assert!(int(Integer::MIN).call("abs", &[], &env).is_err());
+		assert_eq!(call(&int(9), "sqrt"), Variant::Real(3.0));
+		assert_eq!(int(3).call("pow", &[&int(4)], &env).unwrap(), int(81));
+		assert_eq!(int(-2).call("pow", &[&int(3)], &env).unwrap(), int(-8));
+		assert_eq!(int(7).call("pow", &[&int(0)], &env).unwrap(), int(1));
+		assert!(int(2).call("pow", &[&int(64)], &env).is_err());
+		assert!(int(2).call("pow", &[&int(-1)], &env).is_err());
+		assert!(int(2).call("pow", &[&Variant::Real(2.0)], &env).is_err());
+	}
+
+	#[test]
+	fn special_reals() {
+		let env = Environment::new();
+		let t = |v: Variant, f| call(&v, f);
+		let (inf, nan) = (Variant::Real(Real::INFINITY), Variant::Real(Real::NAN));
+		assert_eq!(t(inf.clone(), "is_infinite"), Variant::Bool(true));
+		assert_eq!(t(inf.clone(), "is_finite"), Variant::Bool(false));
+		assert_eq!(t(inf, "is_nan"), Variant::Bool(false));
+		assert_eq!(t(nan.clone(), "is_nan"), Variant::Bool(true));
+		assert_eq!(t(nan, "is_finite"), Variant::Bool(false));
+		assert_eq!(t(Variant::Real(1.5), "is_finite"), Variant::Bool(true));
+		assert_eq!(t(Variant::Integer(3), "is_finite"), Variant::Bool(true));
+		assert_eq!(t(Variant::Integer(3), "is_nan"), Variant::Bool(false));
+		assert_eq!(t(Variant::Integer(3), "is_infinite"), Variant::Bool(false));
+		let one = Variant::Real(1.0);
+		assert!(one.call("is_nan", &[&one], &env).is_err());
+	}
+
+	#[test]
+	fn integer_bits() {
+		let env = Environment::new();
+		let int = Variant::Integer;
+		let bits = Integer::BITS as Integer;
+		assert_eq!(call(&int(0b1011), "count_ones"), int(3));
+		assert_eq!(call(&int(-1), "count_ones"), int(bits));
+		assert_eq!(call(&int(1), "leading_zeros"), int(bits - 1));
+		assert_eq!(call(&int(-1), "leading_zeros"), int(0));
+		assert_eq!(call(&int(0b1000), "trailing_zeros"), int(3));
+		assert_eq!(call(&int(0), "trailing_zeros"), int(bits));
+
+		let bit = |i, n| int(i).call("bit", &[&int(n)], &env);
+		assert_eq!(bit(0b100, 2).unwrap(), Variant::Bool(true));
+		assert_eq!(bit(0b100, 1).unwrap(), Variant::Bool(false));
+		assert_eq!(bit(-1, bits - 1).unwrap(), Variant::Bool(true));
+		assert!(bit(1, bits).is_err());
+		assert!(bit(1, -1).is_err());
+
+		let with_bit = |i, n, v| int(i).call("with_bit", &[&int(n), &v], &env);
+		assert_eq!(with_bit(0b100, 0, Variant::Bool(true)).unwrap(), int(0b101));
+		assert_eq!(
+			with_bit(0b101, 2, Variant::Bool(false)).unwrap(),
+			int(0b001)
+		);
+		assert_eq!(with_bit(0b101, 0, Variant::Bool(true)).unwrap(), int(0b101));
+		assert_eq!(
+			with_bit(0, bits - 1, Variant::Bool(true)).unwrap(),
+			int(Integer::MIN)
+		);
+		assert!(with_bit(0, bits, Variant::Bool(true)).is_err());
+		assert!(with_bit(0, 0, int(1)).is_err());
+	}
+
+	#[test]
+	fn real_round_to() {
+		let env = Environment::new();
+		let round_to =
+			|r: Real, n| Variant::Real(r).call("round_to", &[&Variant::Integer(n)], &env);
+		let s = |s: &str| Variant::String(s.into());
+		assert_eq!(round_to(1.0 / 3.0, 2).unwrap(), s("0.33"));
+		assert_eq!(round_to(0.1 + 0.2, 3).unwrap(), s("0.300"));
+		assert_eq!(round_to(2.5, 0).unwrap(), s("2"));
+		assert_eq!(round_to(-1.005, 1).unwrap(), s("-1.0"));
+		assert!(round_to(1.0, -1).is_err());
+		assert!(round_to(1.0, 256).is_err());
+		assert!(Variant::Real(1.0)
+			.call("round_to", &[&Variant::Real(2.0)], &env)
+			.is_err());
+		assert!(Variant::Real(1.0).call("round_to", &[], &env).is_err());
+	}
+
+	#[test]
+	fn none_eq() {
+		assert_eq!(Variant::None, Variant::None);
+		assert_ne!(Variant::None, Variant::Integer(0));
+		assert_ne!(Variant::Bool(false), Variant::None);
+		assert_eq!(call(&Variant::None, "is_none"), Variant::Bool(true));
+		assert_eq!(call(&Variant::Integer(5), "is_none"), Variant::Bool(false));
+	}
+
+	#[test]
+	fn integer_width() {
+		#[cfg(feature = "int32")]
+		assert_eq!(Integer::BITS, 32);
+		#[cfg(feature = "int64")]
+		assert_eq!(Integer::BITS, 64);
+		#[cfg(not(any(feature = "int32", feature = "int64")))]
+		assert_eq!(Integer::BITS, usize::BITS);
+
+		let env = Environment::new();
+		let int = Variant::Integer;
+		let bits = Integer::BITS as Integer;
+		let pow = |e| int(2).call("pow", &[&int(e)], &env);
+		assert_eq!(pow(bits - 2).unwrap(), int(1 << (bits - 2)));
+		assert!(pow(bits - 1).is_err());
+		assert!((-&int(Integer::MIN)).is_err());
+		assert!(int(Integer::MIN).call("abs", &[], &env).is_err());
+		let max = Integer::MAX.to_string();
+		assert_eq!(
+			crate::ast::parse_number(&max),
+			Ok(Atom::Integer(Integer::MAX))
+		);
+		let over = (Integer::MAX as i128 + 1).to_string();
+		assert!(crate::ast::parse_number(&over).is_err());
+	}
+
+	#[test]
+	fn distance() {
+		let env = Environment::new();
+		let (int, real) = (Variant::Integer, Variant::Real);
+		let call = |v: Variant, f, a: Variant| v.call(f, &[&a], &env);
+		assert_eq!(call(int(3), "hypot", int(4)).unwrap(), real(5.0));
+		assert_eq!(call(real(3.0), "hypot", int(4)).unwrap(), real(5.0));
+		assert_eq!(call(int(-3), "hypot", real(-4.0)).unwrap(), real(5.0));
+		assert_eq!(call(int(-3), "abs_diff", int(4)).unwrap(), int(7));
+		assert_eq!(call(int(4), "abs_diff", int(-3)).unwrap(), int(7));
+		assert_eq!(call(real(-1.5), "abs_diff", int(1)).unwrap(), real(2.5));
+		assert_eq!(call(int(1), "abs_diff", real(-1.5)).unwrap(), real(2.5));
+		assert!(call(int(Integer::MIN), "abs_diff", int(1)).is_err());
+		assert!(call(int(1), "hypot", Variant::Bool(true)).is_err());
+	}
+
+	#[test]
+	fn integer_to_char() {
+		assert_eq!(call(&Variant::Integer(97), "to_char"), Variant::Char('a'));
+		let env = Environment::new();
+		assert!(Variant::Integer(-1).call("to_char", &[], &env).is_err());
+		assert!(Variant::Integer(0xd800).call("to_char", &[], &env).is_err());
+	}
+
+	#[test]
+	fn parse_number() {
+		let s = |s: &str| Variant::String(s.into());
+		let env = Environment::new();
+		assert_eq!(call(&s("0x1f"), "parse_int"), Variant::Integer(31));
+		assert_eq!(call(&s("1_000"), "parse_int"), Variant::Integer(1000));
+		assert_eq!(call(&s("-42"), "parse_int"), Variant::Integer(-42));
+		assert_eq!(call(&s("2.5"), "parse_real"), Variant::Real(2.5));
+		assert_eq!(call(&s("1_000"), "parse_real"), Variant::Real(1000.0));
+		assert!(s("abc").call("parse_int", &[], &env).is_err());
+		assert!(s("abc").call("parse_real", &[], &env).is_err());
+		assert!(s("2.5").call("parse_int", &[], &env).is_err());
+		assert!(s("").call("parse_int", &[], &env).is_err());
+	}
+
+	#[test]
+	fn str() {
+		let s = |v: Variant| call(&v, "str");
+		let string = |s: &str| Variant::String(s.into());
+		assert_eq!(s(Variant::None), string("none"));
+		assert_eq!(s(Variant::Bool(true)), string("true"));
+		assert_eq!(s(Variant::Real(2.5)), string("2.5"));
+		assert_eq!(s(Variant::Integer(-3)), string("-3"));
+		assert_eq!(s(Variant::Char('x')), string("x"));
+		assert_eq!(s(string("abc")), string("abc"));
+		let a = Array::<Variant>::with_len(2);
+		let a = Variant::Object(ScriptObject::new(Rc::new(a)));
+		assert_eq!(s(a), string("[none, none]"));
+		let env = Environment::new();
+		assert!(Variant::None.call("str", &[&Variant::None], &env).is_err());
+	}
+}
@@ -4,11 +4,12 @@
 
 #[cfg(not(feature = "std"))]
 use crate::std_types::*;
-use crate::{CallError, CallResult, Environment, Rc, ScriptObject};
+use crate::{CallError, CallResult, Environment, Integer, Rc, Real, ScriptObject, UInteger};
+use core::convert::TryFrom;
 use core::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Rem, Shl, Shr, Sub};
 use core::{cmp, fmt};
 
-use super::{check_arg_count, gen_op, VariantType};
+use super::{check_arg_count, gen_op, number, VariantType};
 
 #[derive(Clone, Copy)]
 /// This is a special variant type that implements only types that implement Copy.
@@ -17,8 +18,8 @@ use super::{check_arg_count, gen_op, VariantType};
 pub enum Variant {
 	None,
 	Bool(bool),
-	Real(f64),
-	Integer(isize),
+	Real(Real),
+	Integer(Integer),
 	Char(char),
 }
 
@@ -32,8 +33,8 @@ gen_op!(
 	Variant, Add, add
 	[rhs, lhs]
 	[Real, Real] => Real { rhs + lhs }
-	[Real, Integer] => Real { rhs + *lhs as f64 }
-	[Integer, Real] => Real { *rhs as f64 + lhs }
+	[Real, Integer] => Real { rhs + *lhs as Real }
+	[Integer, Real] => Real { *rhs as Real + lhs }
 	[Integer, Integer] => Integer { rhs + lhs }
 );
 
@@ -41,7 +42,7 @@ gen_op!(
 	Variant, Sub, sub
 	[rhs, lhs]
 	[Real, Real] => Real { rhs - lhs }
-	[Real, Integer] => Real { rhs - *lhs as f64 }
+	[Real, Integer] => Real { rhs - *lhs as Real }
 	[Integer, Integer] => Integer { rhs - lhs }
 );
 
@@ -49,8 +50,8 @@ gen_op!(
 	Variant, Mul, mul
 	[rhs, lhs]
 	[Real, Real] => Real { rhs * lhs }
-	[Real, Integer] => Real { rhs * *lhs as f64 }
-	[Integer, Real] => Real { *rhs as f64 * lhs }
+	[Real, Integer] => Real { rhs * *lhs as Real }
+	[Integer, Real] => Real { *rhs as Real * lhs }
 	[Integer, Integer] => Integer { rhs * lhs }
 );
 
@@ -58,8 +59,8 @@ gen_op!(
 	Variant, Div, div
 	[rhs, lhs]
 	[Real, Real] => Real { rhs / lhs }
-	[Real, Integer] => Real { rhs / *lhs as f64 }
-	[Integer, Real] => Real { *rhs as f64 / lhs }
+	[Real, Integer] => Real { rhs / *lhs as Real }
+	[Integer, Real] => Real { *rhs as Real / lhs }
 	[Integer, Integer] => Integer { rhs / lhs }
 );
 
@@ -67,8 +68,8 @@ gen_op!(
 	Variant, Rem, rem
 	[rhs, lhs]
 	[Real, Real] => Real { rhs % lhs }
-	[Real, Integer] => Real { rhs % *lhs as f64 }
-	[Integer, Real] => Real { *rhs as f64 % lhs }
+	[Real, Integer] => Real { rhs % *lhs as Real }
+	[Integer, Real] => Real { *rhs as Real % lhs }
 	[Integer, Integer] => Integer { rhs % lhs }
 );
 
@@ -111,7 +112,7 @@ impl core::ops::Neg for &Variant {
 	fn neg(self) -> Self::Output {
 		use Variant::*;
 		Ok(match self {
-			Integer(i) => Integer(-i),
+			Integer(i) => Integer(i.checked_neg().ok_or_else(CallError::overflow)?),
 			Real(r) => Real(-r),
 			_ => return Err(CallError::incompatible_type()),
 		})
@@ -131,6 +132,19 @@ impl core::ops::Not for &Variant {
 	}
 }
 
+impl Variant {
+	/// The name of the type of the value, as shown in error messages.
+	pub fn type_name(&self) -> &'static str {
+		match self {
+			Self::None => "None",
+			Self::Bool(_) => "Bool",
+			Self::Real(_) => "Real",
+			Self::Integer(_) => "Integer",
+			Self::Char(_) => "Char",
+		}
+	}
+}
+
 impl PartialEq<Self> for Variant {
 	#[inline]
 	// FIXME should we return bool or should we implement a custom form
@@ -138,10 +152,11 @@ impl PartialEq<Self> for Variant {
 	fn eq(&self, rhs: &Self) -> bool {
 		use Variant::*;
 		match (self, rhs) {
+			(None, None) => true,
 			(Bool(a), Bool(b)) => a == b,
 			(Real(a), Real(b)) => a == b,
-			(Real(a), Integer(b)) => *a == *b as f64,
-			(Integer(a), Real(b)) => *a as f64 == *b,
+			(Real(a), Integer(b)) => *a == *b as crate::Real,
+			(Integer(a), Real(b)) => *a as crate::Real == *b,
 			(Integer(a), Integer(b)) => a == b,
 			_ => false,
 		}
@@ -156,8 +171,8 @@ impl PartialOrd<Self> for Variant {
 		match (self, rhs) {
 			(Bool(a), Bool(b)) => a.partial_cmp(b),
 			(Real(a), Real(b)) => a.partial_cmp(b),
-			(Real(a), Integer(b)) => a.partial_cmp(&(*b as f64)),
-			(Integer(a), Real(b)) => (*a as f64).partial_cmp(b),
+			(Real(a), Integer(b)) => a.partial_cmp(&(*b as crate::Real)),
+			(Integer(a), Real(b)) => (*a as crate::Real).partial_cmp(b),
 			(Integer(a), Integer(b)) => a.partial_cmp(b),
 			_ => Option::None,
 		}
@@ -171,25 +186,40 @@ impl VariantType for Variant {
 	}
 
 	#[inline]
-	fn new_integer(value: isize) -> Self {
+	fn new_integer(value: Integer) -> Self {
 		Self::Integer(value)
 	}
 
 	#[inline]
-	fn new_real(value: f64) -> Self {
+	fn new_real(value: Real) -> Self {
 		Self::Real(value)
 	}
 
+	#[inline]
+	fn new_char(value: char) -> Self {
+		Self::Char(value)
+	}
+
 	#[inline]
 	fn new_string(_: Rc<str>) -> Self {
 		unimplemented!()
 	}
 
+	#[inline]
+	fn new_bytes(_: Rc<[u8]>) -> Self {
+		unimplemented!()
+	}
+
 	#[inline]
 	fn new_object(_: ScriptObject<Self>) -> Self {
 		unimplemented!()
 	}
 
+	#[inline]
+	fn is_none(&self) -> bool {
+		matches!(self, Self::None)
+	}
+
 	#[inline]
 	fn as_bool(&self) -> Result<bool, &Self> {
 		if let Self::Bool(b) = self {
@@ -200,7 +230,7 @@ impl VariantType for Variant {
 	}
 
 	#[inline]
-	fn as_integer(&self) -> Result<isize, &Self> {
+	fn as_integer(&self) -> Result<Integer, &Self> {
 		if let Self::Integer(b) = self {
 			Ok(*b)
 		} else {
@@ -209,7 +239,7 @@ impl VariantType for Variant {
 	}
 
 	#[inline]
-	fn as_real(&self) -> Result<f64, &Self> {
+	fn as_real(&self) -> Result<Real, &Self> {
 		if let Self::Real(b) = self {
 			Ok(*b)
 		} else {
@@ -217,6 +247,15 @@ impl VariantType for Variant {
 		}
 	}
 
+	#[inline]
+	fn as_char(&self) -> Result<char, &Self> {
+		if let Self::Char(b) = self {
+			Ok(*b)
+		} else {
+			Err(self)
+		}
+	}
+
 	#[inline]
 	fn into_string(self) -> Result<Rc<str>, Self> {
 		Err(self)
@@ -239,16 +278,16 @@ impl VariantType for Variant {
 					check_arg_count!(args, 0);
 					Self::Real(r.sqrt())
 				}
-				_ => return Err(CallError::undefined_function()),
+				_ => return Err(CallError::undefined_function(function)),
 			},
 			Self::Integer(i) => match function {
 				"abs" => {
 					check_arg_count!(args, 0);
-					Self::Integer(i.abs())
+					Self::Integer(i.checked_abs().ok_or_else(CallError::overflow)?)
 				}
-				_ => return Err(CallError::undefined_function()),
+				_ => return Err(CallError::undefined_function(function)),
 			},
-			_ => return Err(CallError::undefined_function()),
+			_ => return Err(CallError::undefined_function(function)),
 		})
 	}
 
@@ -318,6 +357,21 @@ impl VariantType for Variant {
 		self >> rhs
 	}
 	#[inline]
+	fn rhs_unsigned(&self, rhs: &Self) -> CallResult<Self> {
+		match (self, rhs) {
+			(Variant::Integer(a), Variant::Integer(b)) => u32::try_from(*b)
+				.ok()
+				.and_then(|s| (*a as UInteger).checked_shr(s))
+				.map(|r| Variant::Integer(r as Integer))
+				.ok_or_else(CallError::overflow),
+			_ => Err(CallError::incompatible_types(
+				"shr",
+				self.type_name(),
+				rhs.type_name(),
+			)),
+		}
+	}
+	#[inline]
 	fn neg(&self) -> CallResult<Self> {
 		-self
 	}
@@ -325,6 +379,18 @@ impl VariantType for Variant {
 	fn not(&self) -> CallResult<Self> {
 		!self
 	}
+
+	fn abs(&self) -> CallResult<Self> {
+		number::abs(self)
+	}
+
+	fn sign(&self) -> CallResult<Self> {
+		number::sign(self)
+	}
+
+	fn clamp(&self, min: &Self, max: &Self) -> CallResult<Self> {
+		number::clamp(self, min, max)
+	}
 }
 
 impl fmt::Debug for Variant {
@@ -1,5 +1,6 @@
 mod copy;
 
+use super::number;
 use crate::{check_arg_count, gen_op, VariantType};
 
 pub use copy::Variant as CopyVariant;
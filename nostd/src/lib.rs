@@ -0,0 +1,30 @@
+// Copyright (C) 2021  David Hoppenbrouwers
+//
+// This file is licensed under the MIT license. See LICENSE for details.
+
+//! Minimal embedding of ballscript in a `no_std` environment. Only `alloc` is required.
+
+#![no_std]
+
+use ballscript::{Environment, Variant};
+
+/// Parse the given source and run its `main` function, returning the integer it returns.
+pub fn run(source: &str) -> Option<ballscript::Integer> {
+	let mut string_map = Default::default();
+	let env = Environment::<Variant>::new();
+	let script = ballscript::parse(source, &mut string_map, ()).ok()?;
+	match script.instance().call("main", &[], &env) {
+		Ok(Variant::Integer(i)) => Some(i),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn run_main() {
+		assert_eq!(run("fn main()\n\treturn 2 * 21\n"), Some(42));
+	}
+}